@@ -4,6 +4,8 @@ const version = manifest.version
 const port = browser.runtime.connectNative(nativeAppName)
 
 const receivedPerTab = {}
+// warnings from the previous round, which the host can write into the temporary file
+const warningsPerTab = {}
 
 async function commandListener(command) {
   console.debug(`${manifest.short_name} command: ${command}`)
@@ -114,10 +116,12 @@ async function composeActionListener(tab, info) {
     },
     tab,
     composeDetails,
+    warnings: warningsPerTab[tab.id] || [],
   }
   console.debug(`${manifest.short_name} sending: `, request)
   try {
     port.postMessage(toPlainObject(request))
+    delete warningsPerTab[tab.id]
     await messenger.composeAction.disable(tab.id)
   } catch (_) {
     await createBasicNotification('port', `${manifest.short_name} failed to talk to messaging host`, 'Please check Tools -> Developer Tools -> Error Console for details')
@@ -183,6 +187,20 @@ async function nativeMessagingListener(response) {
         for (const warning of response.warnings) {
           await createBasicNotification('warning', warning.title, warning.message)
         }
+        warningsPerTab[response.tab.id] = response.warnings
+      }
+      if (response.configuration.discard) {
+        delete receivedPerTab[response.tab.id]
+        return
+      }
+      // both are always sent by the host so that an emptied body can be set. the alternative
+      // body that wasn't edited is only kept if there is one.
+      if (composeDetails.isPlainText) {
+        if (!composeDetails.body) {
+          delete composeDetails.body
+        }
+      } else if (!composeDetails.plainTextBody) {
+        delete composeDetails.plainTextBody
       }
       await messenger.compose.setComposeDetails(response.tab.id, composeDetails)
       if (response.configuration.sendOnExit) {
@@ -198,6 +216,10 @@ async function nativeMessagingListener(response) {
   }
 }
 
+async function tabRemovedListener(tabId) {
+  delete warningsPerTab[tabId]
+}
+
 async function nativeMessagingDisconnectListener(p) {
   let message = 'Please try restarting Thunderbird'
   if (p.error) {
@@ -259,6 +281,7 @@ messenger.commands.onCommand.addListener(commandListener)
 messenger.browserAction.onClicked.addListener(browserActionListener)
 messenger.composeAction.onClicked.addListener(composeActionListener)
 browser.notifications.onClicked.addListener(notificationOnClickListener)
+browser.tabs.onRemoved.addListener(tabRemovedListener)
 port.onMessage.addListener(nativeMessagingListener)
 port.onDisconnect.addListener(nativeMessagingDisconnectListener)
 
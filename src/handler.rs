@@ -0,0 +1,1545 @@
+use flate2::read::GzDecoder;
+use serde::Serialize;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::model::messaging::{self, Compose, Configuration, Ping};
+use crate::model::thunderbird::{ComposeDetails, ComposeRecipient};
+use crate::transport::Transport;
+use crate::util::{self, allowed_shells, cleanup, echo};
+use crate::{log_debug, log_error, log_info, log_warn};
+
+const DEFAULT_SHELL_ARGS: &[&str] = &["-c"];
+const DEFAULT_SHELL_ARGS_MACOS: &[&str] = &["-i", "-l", "-c"];
+const CHECK_COMPOSE_JSON: &str = r#"{
+    "configuration": {"version": "", "shell": "", "template": ""},
+    "tab": {
+        "id": 0,
+        "index": 0,
+        "windowId": 0,
+        "status": "complete",
+        "width": 0,
+        "height": 0,
+        "type": "messageCompose",
+        "mailTab": false
+    },
+    "composeDetails": {
+        "from": "",
+        "to": [],
+        "cc": [],
+        "bcc": [],
+        "type": "new",
+        "replyTo": [],
+        "followupTo": [],
+        "newsgroups": [],
+        "subject": "",
+        "isPlainText": true,
+        "body": "",
+        "plainTextBody": "",
+        "attachments": [],
+        "deliveryStatusNotification": null,
+        "returnReceipt": null
+    }
+}"#;
+const SELF_TEST_FROM: &str = "ExtEditorR <self-test@example.com>";
+const SELF_TEST_SUBJECT: &str = "ExtEditorR self-test";
+const SELF_TEST_BODY: &str = "This message was written by the ExtEditorR self-test.\n";
+const READ_RETRY_DELAY: Duration = Duration::from_millis(100);
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+pub fn handle_ping<T>(mut request: Ping)
+where
+    T: Transport,
+{
+    request.pong = request.ping;
+    request.host_version = env!("CARGO_PKG_VERSION").to_string();
+    request.compatible = util::is_extension_compatible(env!("CARGO_PKG_VERSION"), &request.version);
+    request.capabilities = messaging::CAPABILITIES
+        .iter()
+        .map(|capability| capability.to_string())
+        .collect();
+    request.os = env::consts::OS.to_string();
+    request.arch = env::consts::ARCH.to_string();
+    if let Err(write_error) = T::write_message(&request) {
+        log_error!("ExtEditorR failed to send response to Thunderbird: {write_error}");
+    }
+}
+
+/// Tell Thunderbird about a request that could not be read before the host exits, as otherwise
+/// the extension would simply wait for a response. Sent as a bare title and message, since the
+/// tab is unknown.
+pub fn handle_read_error<T>(error: &dyn fmt::Display)
+where
+    T: Transport,
+{
+    let warning = messaging::Warning {
+        title: "ExtEditorR failed to read request".to_owned(),
+        message: format!(
+            "{error}. The messaging host may be incompatible with this version of the extension."
+        ),
+    };
+    log_error!("{}: {}", warning.title, warning.message);
+    // best-effort, as the host is about to exit anyway
+    let _ = T::write_message(&warning);
+}
+
+pub fn handle_compose<T>(request: Compose, dry_run: bool)
+where
+    T: Transport,
+{
+    let temp_filename = util::get_temp_filename(&request);
+    let output_filename = request
+        .configuration
+        .separate_output_file
+        .then(|| util::get_output_filename(&temp_filename));
+    let metadata_filename = request
+        .configuration
+        .write_metadata_json
+        .then(|| util::get_metadata_filename(&temp_filename));
+    let result = handle_eml::<T>(
+        request,
+        &temp_filename,
+        output_filename.as_deref(),
+        metadata_filename.as_deref(),
+        dry_run,
+    );
+    cleanup::registry().remove(&temp_filename);
+    for filename in output_filename.iter().chain(&metadata_filename) {
+        cleanup::registry().remove(filename);
+    }
+    if let Err(e) = result {
+        log_error!("{}: {}", e.title, e.message);
+        if let Err(write_error) = T::write_message(&e) {
+            log_error!("ExtEditorR failed to send response to Thunderbird: {write_error}");
+        }
+    } else {
+        let output_filename = output_filename.filter(|output_filename| output_filename.exists());
+        for filename in std::iter::once(temp_filename)
+            .chain(output_filename)
+            .chain(metadata_filename)
+        {
+            if let Err(remove_error) = fs::remove_file(&filename) {
+                log_warn!(
+                    "ExtEditorR failed to remove temporary file {}: {}",
+                    filename.to_string_lossy(),
+                    remove_error
+                );
+            }
+        }
+    }
+}
+
+fn handle_eml<T>(
+    request: Compose,
+    temp_filename: &Path,
+    output_filename: Option<&Path>,
+    metadata_filename: Option<&Path>,
+    dry_run: bool,
+) -> Result<(), messaging::Error>
+where
+    T: Transport,
+{
+    if !util::is_extension_compatible(env!("CARGO_PKG_VERSION"), &request.configuration.version) {
+        if request.configuration.bypass_version_check {
+            log_warn!(
+                "Bypassing version check: Thunderbird extension is {} while native messaging host is {}.",
+                request.configuration.version,
+                env!("CARGO_PKG_VERSION")
+            );
+        } else {
+            return Err(messaging::Error{
+                tab: request.tab.clone(),
+                reset: false,  // users may want to enable bypass_version_check *and* reload
+                               // ExtEditorR to recover
+                title: "ExtEditorR version mismatch!".to_owned(),
+                message: format!(
+                    "Thunderbird extension is {} while native messaging host is {}. The request has been discarded.",
+                    request.configuration.version,
+                    env!("CARGO_PKG_VERSION")
+                ),
+            });
+        }
+    }
+
+    check_allowed_shells(&request, allowed_shells::get())?;
+
+    log_debug!(
+        "ExtEditorR temporary file: {}",
+        temp_filename.to_string_lossy()
+    );
+    {
+        let mut temp_file = fs::File::create(temp_filename).map_err(|e| messaging::Error {
+            tab: request.tab.clone(),
+            reset: true,
+            title: "ExtEditorR failed to create temporary file".to_owned(),
+            message: describe_create_error(&e, temp_filename),
+        })?;
+        cleanup::registry().add_temp_file(temp_filename);
+        let mut eml = Vec::new();
+        request
+            .to_eml(&mut eml)
+            .and_then(|_| Ok(io::Write::write_all(&mut temp_file, &eml)?))
+            .map_err(|e| messaging::Error {
+                tab: request.tab.clone(),
+                reset: true,
+                title: "ExtEditorR failed to write to temporary file".to_owned(),
+                message: e.to_string(),
+            })?;
+        echo::print_eml(temp_filename, &eml);
+    }
+
+    if let Some(output_filename) = output_filename {
+        // stale output from a previous request of the same tab must not be picked up
+        if output_filename.exists() {
+            fs::remove_file(output_filename).map_err(|e| messaging::Error {
+                tab: request.tab.clone(),
+                reset: true,
+                title: "ExtEditorR failed to remove stale output file".to_owned(),
+                message: util::error_message_with_path(e, output_filename),
+            })?;
+        }
+        cleanup::registry().add_temp_file(output_filename);
+    }
+
+    if let Some(metadata_filename) = metadata_filename {
+        cleanup::registry().add_temp_file(metadata_filename);
+        serde_json::to_vec_pretty(&request.compose_details)
+            .map_err(io::Error::from)
+            .and_then(|metadata| fs::write(metadata_filename, metadata))
+            .map_err(|e| messaging::Error {
+                tab: request.tab.clone(),
+                reset: true,
+                title: "ExtEditorR failed to write metadata file".to_owned(),
+                message: util::error_message_with_path(e, metadata_filename),
+            })?;
+    }
+
+    if dry_run {
+        log_info!("ExtEditorR dry run, reading back temporary file without running any command");
+    } else {
+        run_processing_command(
+            &request,
+            &request.configuration.pre_command,
+            "pre-processing",
+            temp_filename,
+            output_filename,
+        )?;
+        run_editor(&request, temp_filename, output_filename)?;
+        run_processing_command(
+            &request,
+            &request.configuration.post_command,
+            "post-processing",
+            temp_filename,
+            output_filename,
+        )?;
+    }
+    let eml_filename = match output_filename {
+        Some(output_filename) if output_filename.exists() => output_filename,
+        _ => temp_filename,
+    };
+
+    let mut response = request;
+    if response.configuration.report_temp_file_path {
+        response.configuration.temp_file_path = temp_filename.to_string_lossy().to_string();
+    }
+
+    {
+        let compressed = response.configuration.compress_body;
+        let mut reader = retry_transient(
+            response.configuration.read_retries,
+            READ_RETRY_DELAY,
+            || open_eml(eml_filename, compressed),
+        )
+        .map_err(|e| messaging::Error {
+            tab: response.tab.clone(),
+            reset: false,
+            title: "ExtEditorR failed to read from temporary file".to_owned(),
+            message: util::error_message_with_path(e, eml_filename),
+        })?;
+        // a crashed or killed editor may leave a truncated file behind, which would otherwise
+        // wipe the compose window. a deliberately cleared body still comes with headers.
+        if is_blank_eml(&mut reader) {
+            return Err(messaging::Error {
+                tab: response.tab.clone(),
+                reset: false,
+                title: "ExtEditorR found empty temporary file".to_owned(),
+                message: format!(
+                    "{} is empty, possibly because the editor crashed. The message has been left unchanged.",
+                    eml_filename.to_string_lossy()
+                ),
+            });
+        }
+        let responses = response
+            .merge_from_eml(&mut reader, messaging::MAX_BODY_LENGTH)
+            .map_err(|e| messaging::Error {
+                tab: response.tab.clone(),
+                reset: false,
+                title: if e.is::<messaging::MissingSeparatorError>() {
+                    "ExtEditorR failed to find end of headers".to_owned()
+                } else {
+                    "ExtEditorR failed to process temporary file".to_owned()
+                },
+                message: util::error_message_with_path(e, eml_filename),
+            })?;
+
+        log_debug!(
+            "ExtEditorR sending {} chunk(s) to Thunderbird",
+            responses.len()
+        );
+        for response in responses {
+            if let Err(e) = T::write_message(&response) {
+                log_error!("ExtEditorR failed to send response to Thunderbird: {e}");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Merge an eml file into a blank compose and write a human-readable report, or a JSON one for
+/// scripts, for reproducing parsing issues outside Thunderbird. Returns whether the file is free
+/// of warnings.
+pub fn check_eml<W>(eml_filename: &Path, json: bool, w: &mut W) -> anyhow::Result<bool>
+where
+    W: io::Write,
+{
+    let mut compose: Compose = serde_json::from_str(CHECK_COMPOSE_JSON)?;
+    compose.configuration.version = env!("CARGO_PKG_VERSION").to_owned();
+    let compressed = eml_filename.extension().is_some_and(|ext| ext == "gz");
+    let mut reader = open_eml(eml_filename, compressed)
+        .map_err(|e| anyhow::anyhow!("Failed to open {}: {e}", eml_filename.to_string_lossy()))?;
+    let responses = compose.merge_from_eml(&mut reader, messaging::MAX_BODY_LENGTH)?;
+    if json {
+        write_merge_json(&responses, w)
+    } else {
+        write_merge_report(&responses, w)
+    }
+}
+
+/// Write a blank compose with a canned subject and body to a temporary file, run the configured
+/// editor command against it and report what came back. Returns whether the round trip succeeded
+/// without warnings.
+pub fn self_test<W>(configuration: Configuration, w: &mut W) -> anyhow::Result<bool>
+where
+    W: io::Write,
+{
+    let mut compose: Compose = serde_json::from_str(CHECK_COMPOSE_JSON)?;
+    compose.configuration = configuration;
+    compose.configuration.version = env!("CARGO_PKG_VERSION").to_owned();
+    compose.compose_details.from = ComposeRecipient::Email(SELF_TEST_FROM.to_owned());
+    compose.compose_details.subject = SELF_TEST_SUBJECT.to_owned();
+    compose.compose_details.plain_text_body = SELF_TEST_BODY.to_owned();
+    compose.compose_details.is_plain_text = true;
+
+    let temp_filename = util::get_temp_filename(&compose);
+    writeln!(w, "Temporary file: {}", temp_filename.to_string_lossy())?;
+    let result = self_test_round_trip(&mut compose, &temp_filename);
+    cleanup::registry().remove(&temp_filename);
+    let _ = fs::remove_file(&temp_filename);
+    match result {
+        Ok(responses) => {
+            writeln!(w, "Round trip: succeeded")?;
+            write_merge_report(&responses, w)
+        }
+        Err(e) => {
+            writeln!(w, "Round trip: failed")?;
+            writeln!(w, "  {}: {}", e.title, e.message)?;
+            Ok(false)
+        }
+    }
+}
+
+fn self_test_round_trip(
+    compose: &mut Compose,
+    temp_filename: &Path,
+) -> Result<Vec<Compose>, messaging::Error> {
+    let tab = compose.tab.clone();
+    let to_error = |title: &str, e: &dyn std::fmt::Display| messaging::Error {
+        tab: tab.clone(),
+        reset: false,
+        title: title.to_owned(),
+        message: e.to_string(),
+    };
+    // same as a real request
+    check_allowed_shells(compose, allowed_shells::get())?;
+    let mut writer = fs::File::create(temp_filename)
+        .map_err(|e| to_error("Failed to create temporary file", &e))?;
+    cleanup::registry().add_temp_file(temp_filename);
+    compose
+        .to_eml(&mut writer)
+        .map_err(|e| to_error("Failed to write temporary file", &e))?;
+    drop(writer);
+    run_editor(compose, temp_filename, None)?;
+    let mut reader = open_eml(temp_filename, false)
+        .map_err(|e| to_error("Failed to read temporary file", &e))?;
+    compose
+        .merge_from_eml(&mut reader, messaging::MAX_BODY_LENGTH)
+        .map_err(|e| to_error("Failed to merge temporary file", &e))
+}
+
+fn write_merge_report<W>(responses: &[Compose], w: &mut W) -> anyhow::Result<bool>
+where
+    W: io::Write,
+{
+    let mut merged = responses[0].clone();
+    merged.compose_details.body.clear();
+    merged.compose_details.plain_text_body.clear();
+    merged.configuration.suppress_help_headers = true;
+    let mut headers = Vec::new();
+    merged.to_eml(&mut headers)?;
+    writeln!(w, "Headers:")?;
+    for line in String::from_utf8_lossy(&headers).lines() {
+        if !line.is_empty() {
+            writeln!(w, "  {line}")?;
+        }
+    }
+    let body_length = get_body_length(responses);
+    writeln!(
+        w,
+        "Body: {body_length} byte(s) in {} chunk(s)",
+        responses.len()
+    )?;
+    if merged.warnings.is_empty() {
+        writeln!(w, "Warnings: none")?;
+    } else {
+        writeln!(w, "Warnings:")?;
+        for warning in &merged.warnings {
+            writeln!(w, "  {}: {}", warning.title, warning.message)?;
+        }
+    }
+    Ok(merged.warnings.is_empty())
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MergeReport<'a> {
+    /// Without bodies, see `body_length`
+    compose_details: &'a ComposeDetails,
+    configuration: &'a Configuration,
+    warnings: &'a [messaging::Warning],
+    body_length: usize,
+    chunks: usize,
+}
+
+/// Machine-readable counterpart of [`write_merge_report`]
+fn write_merge_json<W>(responses: &[Compose], w: &mut W) -> anyhow::Result<bool>
+where
+    W: io::Write,
+{
+    let mut merged = responses[0].clone();
+    merged.compose_details.body.clear();
+    merged.compose_details.plain_text_body.clear();
+    let report = MergeReport {
+        compose_details: &merged.compose_details,
+        configuration: &merged.configuration,
+        warnings: &merged.warnings,
+        body_length: get_body_length(responses),
+        chunks: responses.len(),
+    };
+    serde_json::to_writer_pretty(&mut *w, &report)?;
+    writeln!(w)?;
+    Ok(merged.warnings.is_empty())
+}
+
+fn get_body_length(responses: &[Compose]) -> usize {
+    responses
+        .iter()
+        .map(|response| {
+            response.compose_details.body.len() + response.compose_details.plain_text_body.len()
+        })
+        .sum()
+}
+
+fn run_editor(
+    request: &Compose,
+    temp_filename: &Path,
+    output_filename: Option<&Path>,
+) -> Result<(), messaging::Error> {
+    if !references_temp_file(&request.configuration, output_filename.is_some()) {
+        return Err(messaging::Error {
+            tab: request.tab.clone(),
+            reset: true,
+            title: "ExtEditorR found invalid editor command".to_owned(),
+            message: format!(
+                "The command template must reference the temporary file as \"{}\"",
+                util::TEMPLATE_TEMP_FILE_NAME
+            ),
+        });
+    }
+    if !request.configuration.persistent_editor_command.is_empty() {
+        return run_persistent_editor(request, temp_filename, output_filename);
+    }
+    let editor = if request.configuration.command.is_empty() {
+        let mut command = util::substitute_temp_filename(
+            &request.configuration.template,
+            &request.configuration.shell,
+            temp_filename,
+        );
+        if let Some(output_filename) = output_filename {
+            command = util::substitute_output_filename(
+                &command,
+                &request.configuration.shell,
+                output_filename,
+            );
+        }
+        get_editor_command(&request.configuration, command)
+    } else {
+        get_direct_editor_command(
+            &request.configuration.command,
+            temp_filename,
+            output_filename,
+        )
+    };
+    run_editor_command(request, editor, temp_filename)
+}
+
+/// See [`Configuration::persistent_editor_command`] for the lifecycle
+fn run_persistent_editor(
+    request: &Compose,
+    temp_filename: &Path,
+    output_filename: Option<&Path>,
+) -> Result<(), messaging::Error> {
+    let lock_filename = util::get_lock_filename(temp_filename);
+    fs::File::create(&lock_filename).map_err(|e| messaging::Error {
+        tab: request.tab.clone(),
+        reset: true,
+        title: "ExtEditorR failed to create lock file".to_owned(),
+        message: util::error_message_with_path(e, &lock_filename),
+    })?;
+    cleanup::registry().add_temp_file(&lock_filename);
+    let editor = get_persistent_editor_command(
+        &request.configuration,
+        temp_filename,
+        output_filename,
+        &lock_filename,
+    );
+    let result = run_editor_command(request, editor, temp_filename)
+        .and_then(|_| wait_for_lock_removal(request, temp_filename, &lock_filename));
+    cleanup::registry().remove(&lock_filename);
+    if lock_filename.exists() {
+        let _ = fs::remove_file(&lock_filename);
+    }
+    result
+}
+
+fn wait_for_lock_removal(
+    request: &Compose,
+    temp_filename: &Path,
+    lock_filename: &Path,
+) -> Result<(), messaging::Error> {
+    log_debug!(
+        "ExtEditorR waiting for editor to remove {}",
+        lock_filename.to_string_lossy()
+    );
+    let timeout = request.configuration.persistent_editor_timeout;
+    let started = Instant::now();
+    while lock_filename.exists() {
+        if timeout > 0 && started.elapsed() >= Duration::from_secs(timeout) {
+            return Err(messaging::Error {
+                tab: request.tab.clone(),
+                reset: true,
+                title: "ExtEditorR timed out waiting for editor".to_owned(),
+                message: format!(
+                    "{} was not removed within {timeout} seconds, the editor may have crashed. Edits so far are in {}.",
+                    lock_filename.to_string_lossy(),
+                    temp_filename.to_string_lossy(),
+                ),
+            });
+        }
+        thread::sleep(LOCK_POLL_INTERVAL);
+    }
+    Ok(())
+}
+
+fn run_editor_command(
+    request: &Compose,
+    mut editor: process::Command,
+    temp_filename: &Path,
+) -> Result<(), messaging::Error> {
+    log_debug!("ExtEditorR running command: {:?}", editor);
+    let editor = editor
+        .stdin(process::Stdio::null())
+        .stdout(process::Stdio::piped())
+        .stderr(process::Stdio::piped())
+        .spawn()
+        .map_err(|e| messaging::Error {
+            tab: request.tab.clone(),
+            reset: true,
+            title: "ExtEditorR failed to start editor".to_owned(),
+            message: e.to_string(),
+        })?;
+    cleanup::registry().add_editor(temp_filename, editor.id());
+    let output = editor.wait_with_output();
+    cleanup::registry().remove_editor(temp_filename);
+    let output = output.map_err(|e| messaging::Error {
+        tab: request.tab.clone(),
+        reset: true,
+        title: "ExtEditorR failed to wait for editor".to_owned(),
+        message: e.to_string(),
+    })?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr)
+            .trim_end()
+            .to_string();
+        if let Some(code) = output
+            .status
+            .code()
+            .filter(|code| request.configuration.ignore_exit_codes.contains(code))
+        {
+            log_warn!("ExtEditorR ignored exit code {code} from external editor: {stderr}");
+            return Ok(());
+        }
+        return Err(messaging::Error {
+            tab: request.tab.clone(),
+            reset: false,
+            title: "ExtEditorR encountered error from external editor".to_owned(),
+            message: util::error_message_with_path(stderr, temp_filename),
+        });
+    }
+
+    Ok(())
+}
+
+/// See [`Configuration::pre_command`] and [`Configuration::post_command`]
+fn run_processing_command(
+    request: &Compose,
+    template: &str,
+    kind: &str,
+    temp_filename: &Path,
+    output_filename: Option<&Path>,
+) -> Result<(), messaging::Error> {
+    let configuration = &request.configuration;
+    if template.is_empty() {
+        return Ok(());
+    }
+    let mut command = util::substitute_temp_filename(template, &configuration.shell, temp_filename);
+    if let Some(output_filename) = output_filename {
+        command = util::substitute_output_filename(&command, &configuration.shell, output_filename);
+    }
+    let mut command = get_editor_command(configuration, command);
+    log_debug!("ExtEditorR running {kind} command: {:?}", command);
+    let output = command
+        .stdin(process::Stdio::null())
+        .output()
+        .map_err(|e| messaging::Error {
+            tab: request.tab.clone(),
+            reset: false,
+            title: format!("ExtEditorR failed to start {kind} command"),
+            message: e.to_string(),
+        })?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr)
+            .trim_end()
+            .to_string();
+        return Err(messaging::Error {
+            tab: request.tab.clone(),
+            reset: false,
+            title: format!("ExtEditorR encountered error from {kind} command"),
+            message: util::error_message_with_path(stderr, temp_filename),
+        });
+    }
+    Ok(())
+}
+
+fn open_eml(eml_filename: &Path, compressed: bool) -> io::Result<Box<dyn io::BufRead>> {
+    let eml_file = fs::File::open(eml_filename)?;
+    if compressed {
+        Ok(Box::new(io::BufReader::new(GzDecoder::new(eml_file))))
+    } else {
+        Ok(Box::new(io::BufReader::new(eml_file)))
+    }
+}
+
+fn is_blank_eml(reader: &mut dyn io::BufRead) -> bool {
+    reader
+        .fill_buf()
+        .map(|buf| buf.iter().all(u8::is_ascii_whitespace))
+        .unwrap_or(false)
+}
+
+/// Retry on errors that may go away shortly, e.g. when an editor saves by renaming a new file over
+/// the old one
+fn retry_transient<F, T>(retries: u32, delay: Duration, mut f: F) -> io::Result<T>
+where
+    F: FnMut() -> io::Result<T>,
+{
+    let mut attempt = 0;
+    loop {
+        match f() {
+            Err(e)
+                if attempt < retries
+                    && matches!(
+                        e.kind(),
+                        io::ErrorKind::NotFound | io::ErrorKind::Interrupted
+                    ) =>
+            {
+                attempt += 1;
+                log_debug!("ExtEditorR retrying ({attempt}/{retries}) after error: {e}");
+                thread::sleep(delay);
+            }
+            result => return result,
+        }
+    }
+}
+
+fn describe_create_error(e: &io::Error, temp_filename: &Path) -> String {
+    let temp_dir = temp_filename
+        .parent()
+        .map(|temp_dir| temp_dir.to_string_lossy())
+        .unwrap_or_default();
+    match e.kind() {
+        io::ErrorKind::NotFound => format!(
+            "Temporary directory {temp_dir} does not exist. Please create it or change the temporary directory in settings."
+        ),
+        io::ErrorKind::PermissionDenied => format!(
+            "Temporary directory {temp_dir} is not writable. Please fix its permissions or change the temporary directory in settings."
+        ),
+        _ => util::error_message_with_path(e, temp_filename),
+    }
+}
+
+/// Refuse to handle the request if it would run a program that is not in the host-side
+/// allow-list, i.e. the shell or the first argument of a direct editor command
+fn check_allowed_shells(
+    request: &Compose,
+    allowed_shells: Option<&[String]>,
+) -> Result<(), messaging::Error> {
+    let configuration = &request.configuration;
+    let uses_shell = configuration.command.is_empty()
+        || !configuration.persistent_editor_command.is_empty()
+        || !configuration.pre_command.is_empty()
+        || !configuration.post_command.is_empty();
+    let programs = configuration
+        .command
+        .first()
+        .map(String::as_str)
+        .into_iter()
+        .chain(uses_shell.then_some(configuration.shell.as_str()));
+    for program in programs {
+        if !allowed_shells::is_allowed(allowed_shells, program) {
+            return Err(messaging::Error {
+                tab: request.tab.clone(),
+                reset: true,
+                title: "ExtEditorR refused to run shell".to_owned(),
+                message: format!(
+                    "{program} is not allowed by the messaging host configuration. Please use one of: {}",
+                    allowed_shells.unwrap_or_default().join(", ")
+                ),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn get_shell_args(configuration: &Configuration) -> Vec<&str> {
+    if !configuration.shell_args.is_empty() {
+        configuration
+            .shell_args
+            .iter()
+            .map(String::as_str)
+            .collect()
+    } else if cfg!(target_os = "macos") && configuration.macos_login_shell {
+        DEFAULT_SHELL_ARGS_MACOS.to_vec()
+    } else {
+        DEFAULT_SHELL_ARGS.to_vec()
+    }
+}
+
+fn get_editor_command(configuration: &Configuration, command: String) -> process::Command {
+    let mut editor = process::Command::new(&configuration.shell);
+    editor.args(get_shell_args(configuration)).arg(command);
+    editor
+}
+
+/// Whether the editor command contains the temporary file placeholder, or the output file one
+/// if a separate output file is used. Otherwise the editor wouldn't edit anything.
+fn references_temp_file(configuration: &Configuration, has_output_file: bool) -> bool {
+    let references = |s: &str| {
+        s.contains(util::TEMPLATE_TEMP_FILE_NAME)
+            || (has_output_file && s.contains(util::TEMPLATE_OUTPUT_FILE_NAME))
+    };
+    if !configuration.persistent_editor_command.is_empty() {
+        references(&configuration.persistent_editor_command)
+    } else if configuration.command.is_empty() {
+        references(&configuration.template)
+    } else {
+        configuration.command.iter().any(|arg| references(arg))
+    }
+}
+
+fn get_persistent_editor_command(
+    configuration: &Configuration,
+    temp_filename: &Path,
+    output_filename: Option<&Path>,
+    lock_filename: &Path,
+) -> process::Command {
+    let shell = &configuration.shell;
+    let mut command = util::substitute_temp_filename(
+        &configuration.persistent_editor_command,
+        shell,
+        temp_filename,
+    );
+    command = util::substitute_lock_filename(&command, shell, lock_filename);
+    if let Some(output_filename) = output_filename {
+        command = util::substitute_output_filename(&command, shell, output_filename);
+    }
+    get_editor_command(configuration, command)
+}
+
+/// Run the editor without a shell, so the paths are passed as they are without escaping
+fn get_direct_editor_command(
+    command: &[String],
+    temp_filename: &Path,
+    output_filename: Option<&Path>,
+) -> process::Command {
+    let temp_filename = temp_filename.to_string_lossy();
+    let output_filename = output_filename.map(Path::to_string_lossy);
+    let mut args = command.iter().map(|arg| {
+        let arg = arg.replace(util::TEMPLATE_TEMP_FILE_NAME, &temp_filename);
+        match output_filename {
+            Some(ref output_filename) => {
+                arg.replace(util::TEMPLATE_OUTPUT_FILE_NAME, output_filename)
+            }
+            None => arg,
+        }
+    });
+    // callers make sure that command is not empty
+    let mut editor = process::Command::new(args.next().unwrap_or_default());
+    editor.args(args);
+    editor
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicI32, Ordering};
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::model::messaging::tests::get_blank_compose;
+    use crate::model::thunderbird::{ComposeRecipient, ComposeRecipientList};
+    use flate2::{write::GzEncoder, Compression};
+
+    type MockTr = crate::transport::MockTransport;
+    static WRITE_MESSAGE_CONTEXT_LOCK: Mutex<()> = Mutex::new(());
+    static NEXT_TAB_ID: AtomicI32 = AtomicI32::new(1);
+
+    /// Removes the temporary directory of a test when dropped
+    struct TestDir(PathBuf);
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// A compose request with `Hello, world!\r\n` as the body and `template` run by `sh`, along
+    /// with a temporary directory and tab id of its own
+    fn get_test_compose(template: &str) -> (Compose, TestDir) {
+        let tab_id = NEXT_TAB_ID.fetch_add(1, Ordering::Relaxed);
+        let temp_dir = env::temp_dir().join(format!(
+            "external_editor_revived_handler_test_{}_{tab_id}",
+            process::id()
+        ));
+        fs::create_dir_all(&temp_dir).unwrap();
+        let mut compose = get_blank_compose();
+        compose.configuration.version = env!("CARGO_PKG_VERSION").to_owned();
+        compose.configuration.shell = "sh".to_owned();
+        compose.configuration.template = template.to_owned();
+        compose.configuration.temporary_directory = temp_dir.to_string_lossy().into_owned();
+        compose.tab.id = tab_id;
+        compose.compose_details.plain_text_body = "Hello, world!\r\n".to_owned();
+        (compose, TestDir(temp_dir))
+    }
+
+    #[test]
+    fn ping_pong_test() {
+        let ping_json = r#"{"ping": 123456}"#;
+        let ping: Ping = serde_json::from_str(ping_json).unwrap();
+
+        let _guard = WRITE_MESSAGE_CONTEXT_LOCK.lock().unwrap();
+        let ctx = MockTr::write_message_context();
+        ctx.expect::<Ping>()
+            .withf(|p: &Ping| {
+                p.ping == 123456
+                    && p.pong == 123456
+                    && !p.compatible
+                    && p.host_version == env!("CARGO_PKG_VERSION")
+            })
+            .returning(|&_| Ok(()));
+        handle_ping::<MockTr>(ping);
+        ctx.checkpoint();
+    }
+
+    #[test]
+    fn ping_pong_successful_version_check_test() {
+        let host_version = env!("CARGO_PKG_VERSION");
+        let ping_json = format!(r#"{{"ping": 123456, "version": "{}"}}"#, host_version);
+        let ping: Ping = serde_json::from_str(&ping_json).unwrap();
+
+        let _guard = WRITE_MESSAGE_CONTEXT_LOCK.lock().unwrap();
+        let ctx = MockTr::write_message_context();
+        ctx.expect::<Ping>()
+            .withf(|p: &Ping| {
+                let host_version = host_version.to_string();
+                p.ping == 123456
+                    && p.pong == 123456
+                    && p.compatible
+                    && p.host_version == host_version
+            })
+            .returning(|&_| Ok(()));
+        handle_ping::<MockTr>(ping);
+        ctx.checkpoint();
+    }
+
+    #[test]
+    fn ping_pong_failed_version_check_test() {
+        let host_version = env!("CARGO_PKG_VERSION");
+        let ping_json = r#"{"ping": 123456, "version": "0.0.0.0"}"#;
+        let ping: Ping = serde_json::from_str(ping_json).unwrap();
+
+        let _guard = WRITE_MESSAGE_CONTEXT_LOCK.lock().unwrap();
+        let ctx = MockTr::write_message_context();
+        ctx.expect::<Ping>()
+            .withf(|p: &Ping| {
+                let host_version = host_version.to_string();
+                p.ping == 123456
+                    && p.pong == 123456
+                    && !p.compatible
+                    && p.host_version == host_version
+            })
+            .returning(|&_| Ok(()));
+        handle_ping::<MockTr>(ping);
+        ctx.checkpoint();
+    }
+
+    #[test]
+    fn ping_pong_capabilities_test() {
+        let ping_json = r#"{"ping": 123456}"#;
+        let ping: Ping = serde_json::from_str(ping_json).unwrap();
+        assert!(ping.capabilities.is_empty());
+
+        let _guard = WRITE_MESSAGE_CONTEXT_LOCK.lock().unwrap();
+        let ctx = MockTr::write_message_context();
+        ctx.expect::<Ping>()
+            .withf(|p: &Ping| {
+                ["discard", "bcc-self", "attach-file", "separate-output-file"]
+                    .iter()
+                    .all(|capability| p.capabilities.iter().any(|c| c == capability))
+            })
+            .returning(|&_| Ok(()));
+        handle_ping::<MockTr>(ping);
+        ctx.checkpoint();
+    }
+
+    #[test]
+    fn ping_pong_platform_test() {
+        let ping_json = r#"{"ping": 123456}"#;
+        let ping: Ping = serde_json::from_str(ping_json).unwrap();
+        assert!(ping.os.is_empty() && ping.arch.is_empty());
+
+        let _guard = WRITE_MESSAGE_CONTEXT_LOCK.lock().unwrap();
+        let ctx = MockTr::write_message_context();
+        ctx.expect::<Ping>()
+            .withf(|p: &Ping| p.os == env::consts::OS && p.arch == env::consts::ARCH)
+            .returning(|&_| Ok(()));
+        handle_ping::<MockTr>(ping);
+        ctx.checkpoint();
+    }
+
+    #[test]
+    fn read_error_test() {
+        let e = serde_json::from_value::<messaging::Exchange>(serde_json::json!({"foo": 1}))
+            .unwrap_err();
+
+        let _guard = WRITE_MESSAGE_CONTEXT_LOCK.lock().unwrap();
+        let ctx = MockTr::write_message_context();
+        ctx.expect::<messaging::Warning>()
+            .withf(|w: &messaging::Warning| {
+                w.title == "ExtEditorR failed to read request"
+                    && w.message.contains("did not match any variant")
+            })
+            .returning(|&_| Ok(()));
+        handle_read_error::<MockTr>(&e);
+        ctx.checkpoint();
+    }
+
+    #[test]
+    fn compressed_eml_test() {
+        let (mut compose, temp_dir) = get_test_compose("");
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        compose.to_eml(&mut encoder).unwrap();
+        let eml_filename = temp_dir.0.join("compressed.eml.gz");
+        fs::write(&eml_filename, encoder.finish().unwrap()).unwrap();
+
+        let mut reader = open_eml(&eml_filename, true).unwrap();
+        let responses = compose.merge_from_eml(&mut reader, 512);
+        let responses = responses.unwrap();
+        assert_eq!(1, responses.len());
+        assert!(responses[0].warnings.is_empty());
+        assert_eq!(
+            "Hello, world!\r\n",
+            responses[0].compose_details.plain_text_body
+        );
+    }
+
+    #[test]
+    fn retry_transient_test() {
+        let mut attempts = 0;
+        let result = retry_transient(3, Duration::ZERO, || {
+            attempts += 1;
+            if attempts == 1 {
+                Err(io::Error::from(io::ErrorKind::NotFound))
+            } else {
+                Ok(attempts)
+            }
+        });
+        assert_eq!(2, result.unwrap());
+
+        let mut attempts = 0;
+        let result: io::Result<()> = retry_transient(3, Duration::ZERO, || {
+            attempts += 1;
+            Err(io::Error::from(io::ErrorKind::NotFound))
+        });
+        assert_eq!(io::ErrorKind::NotFound, result.unwrap_err().kind());
+        assert_eq!(4, attempts);
+
+        let mut attempts = 0;
+        let result: io::Result<()> = retry_transient(3, Duration::ZERO, || {
+            attempts += 1;
+            Err(io::Error::from(io::ErrorKind::PermissionDenied))
+        });
+        assert!(result.is_err());
+        assert_eq!(1, attempts);
+    }
+
+    #[test]
+    fn create_temp_file_in_missing_dir_test() {
+        let temp_dir = env::temp_dir().join("external_editor_revived_missing");
+        let mut compose = get_blank_compose();
+        compose.configuration.temporary_directory = temp_dir.to_string_lossy().into_owned();
+        let temp_filename = util::get_temp_filename(&compose);
+        assert_eq!(Some(temp_dir.as_path()), temp_filename.parent());
+        let e = fs::File::create(&temp_filename).unwrap_err();
+        assert_eq!(
+            format!(
+                "Temporary directory {} does not exist. Please create it or change the temporary directory in settings.",
+                temp_dir.to_string_lossy()
+            ),
+            describe_create_error(&e, &temp_filename)
+        );
+    }
+
+    #[test]
+    fn create_temp_file_in_read_only_dir_test() {
+        let temp_dir = env::temp_dir().join("external_editor_revived_read_only");
+        fs::create_dir_all(&temp_dir).unwrap();
+        let mut permissions = fs::metadata(&temp_dir).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&temp_dir, permissions.clone()).unwrap();
+        let mut compose = get_blank_compose();
+        compose.configuration.temporary_directory = temp_dir.to_string_lossy().into_owned();
+        let temp_filename = util::get_temp_filename(&compose);
+        let result = fs::File::create(&temp_filename);
+        let _ = fs::remove_file(&temp_filename);
+        #[allow(clippy::permissions_set_readonly_false)]
+        permissions.set_readonly(false);
+        fs::set_permissions(&temp_dir, permissions).unwrap();
+        fs::remove_dir(&temp_dir).unwrap();
+        // permissions are not enforced for e.g. root
+        let Err(e) = result else {
+            return;
+        };
+        assert_eq!(
+            format!(
+                "Temporary directory {} is not writable. Please fix its permissions or change the temporary directory in settings.",
+                temp_dir.to_string_lossy()
+            ),
+            describe_create_error(&e, &temp_filename)
+        );
+    }
+
+    #[test]
+    fn default_shell_args_test() {
+        let mut compose = get_blank_compose();
+        compose.configuration.shell = "sh".to_owned();
+        let editor = get_editor_command(&compose.configuration, "vim".to_owned());
+        let args: Vec<_> = editor.get_args().collect();
+        assert_eq!("sh", editor.get_program());
+        assert_eq!(Some(&"vim".as_ref()), args.last());
+        if cfg!(target_os = "macos") {
+            assert_eq!(DEFAULT_SHELL_ARGS_MACOS.len() + 1, args.len());
+        } else {
+            assert_eq!(DEFAULT_SHELL_ARGS.len() + 1, args.len());
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn macos_login_shell_args_test() {
+        let mut compose = get_blank_compose();
+        assert!(compose.configuration.macos_login_shell);
+        assert_eq!(
+            DEFAULT_SHELL_ARGS_MACOS,
+            get_shell_args(&compose.configuration).as_slice()
+        );
+
+        compose.configuration.macos_login_shell = false;
+        assert_eq!(
+            DEFAULT_SHELL_ARGS,
+            get_shell_args(&compose.configuration).as_slice()
+        );
+
+        compose.configuration.shell_args = vec!["-l".to_owned(), "-c".to_owned()];
+        assert_eq!(vec!["-l", "-c"], get_shell_args(&compose.configuration));
+    }
+
+    #[test]
+    fn custom_shell_args_test() {
+        let mut compose = get_blank_compose();
+        compose.configuration.shell = "pwsh".to_owned();
+        compose.configuration.shell_args = vec!["-NoProfile".to_owned(), "-Command".to_owned()];
+        let editor = get_editor_command(&compose.configuration, "notepad.exe".to_owned());
+        let args: Vec<_> = editor.get_args().collect();
+        assert_eq!("pwsh", editor.get_program());
+        assert_eq!(vec!["-NoProfile", "-Command", "notepad.exe"], args);
+    }
+
+    #[test]
+    fn echo_compose_with_custom_shell_args_test() {
+        let (mut compose, _temp_dir) = get_test_compose(r#"cat "/path/to/temp.eml""#);
+        compose.configuration.shell_args = vec!["-e".to_owned(), "-u".to_owned(), "-c".to_owned()];
+
+        let _guard = WRITE_MESSAGE_CONTEXT_LOCK.lock().unwrap();
+        let ctx = MockTr::write_message_context();
+        ctx.expect::<Compose>()
+            .withf(|c: &Compose| {
+                c.compose_details.plain_text_body == "Hello, world!\r\n"
+                    && c.configuration.total == 1
+            })
+            .returning(|&_| Ok(()));
+        handle_compose::<MockTr>(compose, false);
+        ctx.checkpoint();
+    }
+
+    #[test]
+    fn dry_run_compose_test() {
+        let (mut compose, _temp_dir) = get_test_compose("");
+        // would fail the request if it were run
+        compose.configuration.shell = "false".to_string();
+        compose.configuration.meta_headers = true;
+        compose.configuration.send_on_exit = true;
+        compose.compose_details.from = ComposeRecipient::Email("foo@example.com".to_owned());
+        compose.compose_details.to = ComposeRecipientList::Multiple(vec![
+            ComposeRecipient::Email("bar@example.com".to_owned()),
+            ComposeRecipient::Email("baz@example.com".to_owned()),
+        ]);
+        compose.compose_details.cc =
+            ComposeRecipientList::Single(ComposeRecipient::Email("qux@example.com".to_owned()));
+        compose.compose_details.subject = "Dry run".to_owned();
+
+        let _guard = WRITE_MESSAGE_CONTEXT_LOCK.lock().unwrap();
+        let ctx = MockTr::write_message_context();
+        ctx.expect::<Compose>()
+            .withf(|c: &Compose| {
+                c.warnings.is_empty()
+                    && c.configuration.send_on_exit
+                    && c.compose_details.from
+                        == ComposeRecipient::Email("foo@example.com".to_owned())
+                    && c.compose_details.to
+                        == ComposeRecipientList::Multiple(vec![
+                            ComposeRecipient::Email("bar@example.com".to_owned()),
+                            ComposeRecipient::Email("baz@example.com".to_owned()),
+                        ])
+                    && c.compose_details.cc
+                        == ComposeRecipientList::Multiple(vec![ComposeRecipient::Email(
+                            "qux@example.com".to_owned(),
+                        )])
+                    && c.compose_details.subject == "Dry run"
+                    && c.compose_details.plain_text_body == "Hello, world!\r\n"
+                    && c.configuration.total == 1
+            })
+            .returning(|&_| Ok(()));
+        handle_compose::<MockTr>(compose.clone(), true);
+        ctx.checkpoint();
+
+        // processing commands are not run either
+        compose.configuration.shell = "sh".to_string();
+        compose.configuration.pre_command = "false".to_owned();
+        compose.configuration.post_command = "false".to_owned();
+        ctx.expect::<Compose>()
+            .withf(|c: &Compose| {
+                c.compose_details.subject == "Dry run"
+                    && c.compose_details.plain_text_body == "Hello, world!\r\n"
+            })
+            .returning(|&_| Ok(()));
+        handle_compose::<MockTr>(compose, true);
+        ctx.checkpoint();
+    }
+
+    #[test]
+    fn report_temp_file_path_test() {
+        let (mut compose, _temp_dir) = get_test_compose("");
+        compose.configuration.report_temp_file_path = true;
+        let temp_filename = util::get_temp_filename(&compose)
+            .to_string_lossy()
+            .into_owned();
+
+        let _guard = WRITE_MESSAGE_CONTEXT_LOCK.lock().unwrap();
+        let ctx = MockTr::write_message_context();
+        ctx.expect::<Compose>()
+            .withf(move |c: &Compose| {
+                c.configuration.temp_file_path == temp_filename
+                    && serde_json::to_value(c).unwrap()["configuration"]["tempFilePath"].is_string()
+            })
+            .returning(|&_| Ok(()));
+        handle_compose::<MockTr>(compose, true);
+        ctx.checkpoint();
+
+        let compose = get_blank_compose();
+        let json = serde_json::to_value(&compose).unwrap();
+        assert!(json["configuration"].get("tempFilePath").is_none());
+    }
+
+    #[test]
+    fn separate_output_file_test() {
+        let (mut compose, _temp_dir) = get_test_compose(
+            r#"sed 's/world/output/' "/path/to/temp.eml" > "/path/to/output.eml""#,
+        );
+        compose.configuration.separate_output_file = true;
+        let temp_filename = util::get_temp_filename(&compose);
+        let output_filename = util::get_output_filename(&temp_filename);
+
+        let _guard = WRITE_MESSAGE_CONTEXT_LOCK.lock().unwrap();
+        let ctx = MockTr::write_message_context();
+        ctx.expect::<Compose>()
+            .withf(|c: &Compose| c.compose_details.plain_text_body == "Hello, output!\r\n")
+            .returning(|&_| Ok(()));
+        handle_compose::<MockTr>(compose.clone(), false);
+        ctx.checkpoint();
+        assert!(!temp_filename.exists());
+        assert!(!output_filename.exists());
+
+        // falls back to the temporary file if the editor didn't write to the output file
+        compose.configuration.template = r#"cat "/path/to/temp.eml""#.to_owned();
+        ctx.expect::<Compose>()
+            .withf(|c: &Compose| c.compose_details.plain_text_body == "Hello, world!\r\n")
+            .returning(|&_| Ok(()));
+        handle_compose::<MockTr>(compose, false);
+        ctx.checkpoint();
+        assert!(!temp_filename.exists());
+        assert!(!output_filename.exists());
+    }
+
+    #[test]
+    fn empty_eml_test() {
+        let (compose, _temp_dir) = get_test_compose(r#": > "/path/to/temp.eml""#);
+        let temp_filename = util::get_temp_filename(&compose);
+        let tab_id = compose.tab.id;
+
+        let _guard = WRITE_MESSAGE_CONTEXT_LOCK.lock().unwrap();
+        let ctx = MockTr::write_message_context();
+        ctx.expect::<messaging::Error>()
+            .withf(move |e: &messaging::Error| {
+                !e.reset && e.tab.id == tab_id && e.title == "ExtEditorR found empty temporary file"
+            })
+            .returning(|&_| Ok(()));
+        handle_compose::<MockTr>(compose, false);
+        ctx.checkpoint();
+        assert_eq!(0, fs::metadata(&temp_filename).unwrap().len());
+    }
+
+    #[test]
+    fn template_without_placeholder_test() {
+        let (mut compose, _temp_dir) = get_test_compose("vim");
+        let tab_id = compose.tab.id;
+
+        let _guard = WRITE_MESSAGE_CONTEXT_LOCK.lock().unwrap();
+        let ctx = MockTr::write_message_context();
+        ctx.expect::<messaging::Error>()
+            .withf(move |e: &messaging::Error| {
+                e.reset
+                    && e.tab.id == tab_id
+                    && e.title == "ExtEditorR found invalid editor command"
+            })
+            .returning(|&_| Ok(()));
+        handle_compose::<MockTr>(compose.clone(), false);
+        ctx.checkpoint();
+
+        assert!(!references_temp_file(&compose.configuration, true));
+        compose.configuration.template = r#"vim "/path/to/output.eml""#.to_owned();
+        assert!(!references_temp_file(&compose.configuration, false));
+        assert!(references_temp_file(&compose.configuration, true));
+        compose.configuration.command = vec!["vim".to_owned(), "/path/to/temp.eml".to_owned()];
+        assert!(references_temp_file(&compose.configuration, false));
+    }
+
+    #[test]
+    fn ignore_exit_codes_test() {
+        let (mut compose, _temp_dir) = get_test_compose(r#"cat "/path/to/temp.eml"; exit 3"#);
+        compose.configuration.ignore_exit_codes = vec![1, 3];
+        let temp_filename = util::get_temp_filename(&compose);
+
+        let _guard = WRITE_MESSAGE_CONTEXT_LOCK.lock().unwrap();
+        let ctx = MockTr::write_message_context();
+        ctx.expect::<Compose>()
+            .withf(|c: &Compose| c.compose_details.plain_text_body == "Hello, world!\r\n")
+            .returning(|&_| Ok(()));
+        handle_compose::<MockTr>(compose.clone(), false);
+        ctx.checkpoint();
+        assert!(!temp_filename.exists());
+
+        compose.configuration.ignore_exit_codes = vec![1];
+        ctx.expect::<messaging::Error>()
+            .withf(|e: &messaging::Error| {
+                !e.reset && e.title == "ExtEditorR encountered error from external editor"
+            })
+            .returning(|&_| Ok(()));
+        handle_compose::<MockTr>(compose, false);
+        ctx.checkpoint();
+        assert!(temp_filename.exists());
+    }
+
+    #[test]
+    fn write_metadata_json_test() {
+        let (mut compose, temp_dir) = get_test_compose("");
+        compose.configuration.write_metadata_json = true;
+        compose.compose_details.subject = "Metadata".to_owned();
+        let temp_filename = util::get_temp_filename(&compose);
+        let metadata_filename = util::get_metadata_filename(&temp_filename);
+        let copy_filename = temp_dir.0.join("metadata_copy.json");
+        compose.configuration.template = format!(
+            r#"cp "{}" "{}" && touch "/path/to/temp.eml""#,
+            metadata_filename.to_string_lossy(),
+            copy_filename.to_string_lossy()
+        );
+
+        let _guard = WRITE_MESSAGE_CONTEXT_LOCK.lock().unwrap();
+        let ctx = MockTr::write_message_context();
+        ctx.expect::<Compose>()
+            .withf(|c: &Compose| c.compose_details.subject == "Metadata")
+            .returning(|&_| Ok(()));
+        handle_compose::<MockTr>(compose.clone(), false);
+        ctx.checkpoint();
+        assert!(!temp_filename.exists());
+        assert!(!metadata_filename.exists());
+
+        let metadata = fs::read_to_string(copy_filename).unwrap();
+        assert_eq!(
+            serde_json::to_value(&compose.compose_details).unwrap(),
+            serde_json::from_str::<serde_json::Value>(&metadata).unwrap()
+        );
+    }
+
+    #[test]
+    fn direct_editor_command_test() {
+        let command = vec![
+            "vim".to_owned(),
+            "-c".to_owned(),
+            "w /path/to/output.eml".to_owned(),
+            "/path/to/temp.eml".to_owned(),
+        ];
+        let temp_filename = Path::new("/tmp/John $mith `true`/external_editor_revived_1.eml");
+        let output_filename = util::get_output_filename(temp_filename);
+        let editor = get_direct_editor_command(&command, temp_filename, Some(&output_filename));
+        let args: Vec<_> = editor.get_args().collect();
+        assert_eq!("vim", editor.get_program());
+        assert_eq!(
+            vec![
+                "-c",
+                "w /tmp/John $mith `true`/external_editor_revived_1.output.eml",
+                "/tmp/John $mith `true`/external_editor_revived_1.eml",
+            ],
+            args
+        );
+    }
+
+    #[test]
+    fn persistent_editor_command_test() {
+        let mut configuration = get_blank_compose().configuration;
+        configuration.shell = "sh".to_owned();
+        configuration.persistent_editor_command =
+            r#"nvim --server /tmp/nvim.sock --remote "/path/to/temp.eml" && echo "/path/to/temp.lock""#
+                .to_owned();
+        let temp_filename = Path::new("/tmp/John $mith/external_editor_revived_1.eml");
+        let lock_filename = util::get_lock_filename(temp_filename);
+        let editor =
+            get_persistent_editor_command(&configuration, temp_filename, None, &lock_filename);
+        let args: Vec<_> = editor.get_args().collect();
+        assert_eq!("sh", editor.get_program());
+        assert_eq!(
+            vec![
+                "-c",
+                r#"nvim --server /tmp/nvim.sock --remote "/tmp/John \$mith/external_editor_revived_1.eml" && echo "/tmp/John \$mith/external_editor_revived_1.lock""#,
+            ],
+            args
+        );
+    }
+
+    #[test]
+    fn echo_compose_with_persistent_editor_test() {
+        // would fail the request if it were run
+        let (mut compose, _temp_dir) = get_test_compose("false");
+        // the lock file is removed in the background as if the editor is done
+        compose.configuration.persistent_editor_command = r#"test -f "/path/to/temp.lock" && printf 'Subject: Persistent\r\n\r\nHello, world!\r\n' > "/path/to/temp.eml" && (sleep 0.2; rm "/path/to/temp.lock") > /dev/null 2>&1 &"#.to_owned();
+        let temp_filename = util::get_temp_filename(&compose);
+        let lock_filename = util::get_lock_filename(&temp_filename);
+
+        let _guard = WRITE_MESSAGE_CONTEXT_LOCK.lock().unwrap();
+        let ctx = MockTr::write_message_context();
+        ctx.expect::<Compose>()
+            .withf(|c: &Compose| {
+                c.compose_details.subject == "Persistent"
+                    && c.compose_details.plain_text_body == "Hello, world!\r\n"
+            })
+            .returning(|&_| Ok(()));
+        handle_compose::<MockTr>(compose, false);
+        ctx.checkpoint();
+        assert!(!temp_filename.exists());
+        assert!(!lock_filename.exists());
+    }
+
+    #[test]
+    fn persistent_editor_timeout_test() {
+        let (mut compose, _temp_dir) = get_test_compose("");
+        // as if the editor crashed before removing the lock file
+        compose.configuration.persistent_editor_command =
+            r#"test -f "/path/to/temp.eml""#.to_owned();
+        compose.configuration.persistent_editor_timeout = 1;
+        let temp_filename = util::get_temp_filename(&compose);
+        let lock_filename = util::get_lock_filename(&temp_filename);
+
+        let _guard = WRITE_MESSAGE_CONTEXT_LOCK.lock().unwrap();
+        let ctx = MockTr::write_message_context();
+        ctx.expect::<messaging::Error>()
+            .withf(|e: &messaging::Error| {
+                e.reset
+                    && e.title == "ExtEditorR timed out waiting for editor"
+                    && e.message.contains("within 1 seconds")
+            })
+            .returning(|&_| Ok(()));
+        handle_compose::<MockTr>(compose, false);
+        ctx.checkpoint();
+        assert!(!lock_filename.exists());
+    }
+
+    #[test]
+    fn post_command_test() {
+        let (mut compose, _temp_dir) = get_test_compose(r#"cat "/path/to/temp.eml""#);
+        compose.configuration.post_command =
+            r#"printf 'Goodbye, world!\r\n' >> "/path/to/temp.eml""#.to_owned();
+
+        let _guard = WRITE_MESSAGE_CONTEXT_LOCK.lock().unwrap();
+        let ctx = MockTr::write_message_context();
+        ctx.expect::<Compose>()
+            .withf(|c: &Compose| {
+                c.compose_details.plain_text_body == "Hello, world!\r\nGoodbye, world!\r\n"
+            })
+            .returning(|&_| Ok(()));
+        handle_compose::<MockTr>(compose.clone(), false);
+        ctx.checkpoint();
+
+        compose.configuration.post_command = "echo oops >&2; false".to_owned();
+        ctx.expect::<messaging::Error>()
+            .withf(|e: &messaging::Error| {
+                !e.reset
+                    && e.title == "ExtEditorR encountered error from post-processing command"
+                    && e.message.contains("oops")
+            })
+            .returning(|&_| Ok(()));
+        handle_compose::<MockTr>(compose, false);
+        ctx.checkpoint();
+    }
+
+    #[test]
+    fn pre_command_test() {
+        // fails unless the editor sees the change
+        let (mut compose, _temp_dir) = get_test_compose(r#"grep -q Goodbye "/path/to/temp.eml""#);
+        compose.configuration.pre_command =
+            r#"printf 'Goodbye, world!\r\n' >> "/path/to/temp.eml""#.to_owned();
+
+        let _guard = WRITE_MESSAGE_CONTEXT_LOCK.lock().unwrap();
+        let ctx = MockTr::write_message_context();
+        ctx.expect::<Compose>()
+            .withf(|c: &Compose| {
+                c.compose_details.plain_text_body == "Hello, world!\r\nGoodbye, world!\r\n"
+            })
+            .returning(|&_| Ok(()));
+        handle_compose::<MockTr>(compose.clone(), false);
+        ctx.checkpoint();
+
+        // the editor is not started
+        compose.configuration.pre_command = "false".to_owned();
+        compose.configuration.template = r#"touch "/path/to/temp.eml.started""#.to_owned();
+        ctx.expect::<messaging::Error>()
+            .withf(|e: &messaging::Error| {
+                !e.reset && e.title == "ExtEditorR encountered error from pre-processing command"
+            })
+            .returning(|&_| Ok(()));
+        let temp_filename = util::get_temp_filename(&compose);
+        handle_compose::<MockTr>(compose, false);
+        ctx.checkpoint();
+        assert!(!Path::new(&format!("{}.started", temp_filename.to_string_lossy())).exists());
+    }
+
+    #[test]
+    fn allowed_shells_test() {
+        let mut compose = get_blank_compose();
+        compose.configuration.shell = "/bin/sh".to_owned();
+        let allowed_shells = vec!["/bin/sh".to_owned()];
+        assert!(check_allowed_shells(&compose, None).is_ok());
+        assert!(check_allowed_shells(&compose, Some(&allowed_shells)).is_ok());
+
+        compose.configuration.shell = "/bin/bash".to_owned();
+        let e = check_allowed_shells(&compose, Some(&allowed_shells)).unwrap_err();
+        assert_eq!("ExtEditorR refused to run shell", e.title);
+        assert!(e.message.starts_with("/bin/bash is not allowed"));
+
+        // direct commands are checked instead, unless there are other shell commands
+        compose.configuration.command = vec!["/bin/sh".to_owned(), "/path/to/temp.eml".to_owned()];
+        assert!(check_allowed_shells(&compose, Some(&allowed_shells)).is_ok());
+        compose.configuration.post_command = "true".to_owned();
+        assert!(check_allowed_shells(&compose, Some(&allowed_shells)).is_err());
+        compose.configuration.post_command.clear();
+        compose.configuration.command = vec!["vim".to_owned(), "/path/to/temp.eml".to_owned()];
+        assert!(check_allowed_shells(&compose, Some(&allowed_shells)).is_err());
+    }
+
+    #[test]
+    fn echo_compose_with_direct_command_test() {
+        let (mut compose, _temp_dir) = get_test_compose("");
+        // would fail the request if it were run
+        compose.configuration.shell = "false".to_string();
+        compose.configuration.command = vec!["cat".to_owned(), "/path/to/temp.eml".to_owned()];
+
+        let _guard = WRITE_MESSAGE_CONTEXT_LOCK.lock().unwrap();
+        let ctx = MockTr::write_message_context();
+        ctx.expect::<Compose>()
+            .withf(|c: &Compose| c.compose_details.plain_text_body == "Hello, world!\r\n")
+            .returning(|&_| Ok(()));
+        handle_compose::<MockTr>(compose, false);
+        ctx.checkpoint();
+    }
+
+    #[test]
+    fn echo_compose_test() {
+        let (compose, _temp_dir) = get_test_compose(r#"cat "/path/to/temp.eml""#);
+
+        let _guard = WRITE_MESSAGE_CONTEXT_LOCK.lock().unwrap();
+        let ctx = MockTr::write_message_context();
+        ctx.expect::<Compose>()
+            .withf(|c: &Compose| {
+                c.compose_details.plain_text_body == "Hello, world!\r\n"
+                    && c.configuration.total == 1
+            })
+            .returning(|&_| Ok(()));
+        handle_compose::<MockTr>(compose, false);
+        ctx.checkpoint();
+    }
+}
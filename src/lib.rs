@@ -0,0 +1,12 @@
+//! Native messaging host of External Editor Revived.
+//!
+//! Apart from the `external-editor-revived` binary, the conversion between Thunderbird compose
+//! details and eml files is available as a library. Only [`model`] is considered stable public
+//! API, i.e. [`model::messaging::Compose`] (incl. [`model::messaging::Compose::to_eml`] and
+//! [`model::messaging::Compose::merge_from_eml`]) and the types it is made up of. [`handler`],
+//! [`transport`] and [`util`] are exposed for the binary and may change without notice.
+
+pub mod handler;
+pub mod model;
+pub mod transport;
+pub mod util;
@@ -1,167 +1,34 @@
-mod model;
-mod transport;
-mod util;
-
-use model::app_manifest::AppManifest;
-use model::messaging::{self, Compose, Exchange, Ping};
+use external_editor_revived::handler;
+use external_editor_revived::model::app_manifest::{self, AppManifest, ManifestFormat};
+use external_editor_revived::model::messaging::{Configuration, Exchange};
+use external_editor_revived::transport::{self, Transport};
+use external_editor_revived::util::exchange_log::{self, Direction};
+use external_editor_revived::util::logger::{self, Level};
+use external_editor_revived::util::{allowed_shells, cleanup, config_file, echo};
+use external_editor_revived::{log_error, log_warn};
+use serde_json::{Map, Value};
 use std::env;
-use std::fs;
 use std::io;
 use std::path::Path;
 use std::process;
+use std::str::FromStr;
 use std::thread;
-use transport::Transport;
-
-const TEMPLATE_TEMP_FILE_NAME: &str = "/path/to/temp.eml";
-const DEFAULT_SHELL_ARGS: &[&str] = &["-c"];
-const DEFAULT_SHELL_ARGS_MACOS: &[&str] = &["-i", "-l", "-c"];
-
-fn handle_ping<T>(mut request: Ping)
-where
-    T: transport::Transport,
-{
-    request.pong = request.ping;
-    request.host_version = env!("CARGO_PKG_VERSION").to_string();
-    request.compatible = util::is_extension_compatible(env!("CARGO_PKG_VERSION"), &request.version);
-    if let Err(write_error) = T::write_message(&request) {
-        eprintln!("ExtEditorR failed to send response to Thunderbird: {write_error}");
-    }
-}
-
-fn handle_compose<T>(request: Compose)
-where
-    T: transport::Transport,
-{
-    let temp_filename = util::get_temp_filename(&request);
-    if let Err(e) = handle_eml::<T>(request, &temp_filename) {
-        eprintln!("{}: {}", e.title, e.message);
-        if let Err(write_error) = T::write_message(&e) {
-            eprintln!("ExtEditorR failed to send response to Thunderbird: {write_error}");
-        }
-    } else if let Err(remove_error) = fs::remove_file(&temp_filename) {
-        eprintln!(
-            "ExtEditorR failed to remove temporary file {}: {}",
-            temp_filename.to_string_lossy(),
-            remove_error
-        );
-    }
-}
-
-fn handle_eml<T>(request: Compose, temp_filename: &Path) -> Result<(), messaging::Error>
-where
-    T: transport::Transport,
-{
-    if !util::is_extension_compatible(env!("CARGO_PKG_VERSION"), &request.configuration.version) {
-        if request.configuration.bypass_version_check {
-            eprintln!(
-                "Bypassing version check: Thunderbird extension is {} while native messaging host is {}.",
-                request.configuration.version,
-                env!("CARGO_PKG_VERSION")
-            );
-        } else {
-            return Err(messaging::Error{
-                tab: request.tab.clone(),
-                reset: false,  // users may want to enable bypass_version_check *and* reload
-                               // ExtEditorR to recover
-                title: "ExtEditorR version mismatch!".to_owned(),
-                message: format!(
-                    "Thunderbird extension is {} while native messaging host is {}. The request has been discarded.",
-                    request.configuration.version,
-                    env!("CARGO_PKG_VERSION")
-                ),
-            });
-        }
-    }
-
-    {
-        let mut temp_file = fs::File::create(temp_filename).map_err(|e| messaging::Error {
-            tab: request.tab.clone(),
-            reset: true,
-            title: "ExtEditorR failed to create temporary file".to_owned(),
-            message: e.to_string(),
-        })?;
-        request
-            .to_eml(&mut temp_file)
-            .map_err(|e| messaging::Error {
-                tab: request.tab.clone(),
-                reset: true,
-                title: "ExtEditorR failed to write to temporary file".to_owned(),
-                message: e.to_string(),
-            })?;
-    }
-
-    let command = if cfg!(target_os = "windows") {
-        request.configuration.template.replace(
-            TEMPLATE_TEMP_FILE_NAME,
-            &temp_filename.to_string_lossy().replace('\\', "\\\\"),
-        )
-    } else {
-        request
-            .configuration
-            .template
-            .replace(TEMPLATE_TEMP_FILE_NAME, &temp_filename.to_string_lossy())
-    };
-    let output = process::Command::new(&request.configuration.shell)
-        .args(if cfg!(target_os = "macos") {
-            DEFAULT_SHELL_ARGS_MACOS
-        } else {
-            DEFAULT_SHELL_ARGS
-        })
-        .arg(command)
-        .output()
-        .map_err(|e| messaging::Error {
-            tab: request.tab.clone(),
-            reset: true,
-            title: "ExtEditorR failed to start editor".to_owned(),
-            message: e.to_string(),
-        })?;
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr)
-            .trim_end()
-            .to_string();
-        return Err(messaging::Error {
-            tab: request.tab,
-            reset: false,
-            title: "ExtEditorR encountered error from external editor".to_owned(),
-            message: util::error_message_with_path(stderr, temp_filename),
-        });
-    }
-
-    let mut response = request;
-
-    {
-        let temp_file = fs::File::open(temp_filename).map_err(|e| messaging::Error {
-            tab: response.tab.clone(),
-            reset: false,
-            title: "ExtEditorR failed to read from temporary file".to_owned(),
-            message: util::error_message_with_path(e, temp_filename),
-        })?;
-
-        let mut reader = io::BufReader::new(temp_file);
-        let responses = response
-            .merge_from_eml(&mut reader, messaging::MAX_BODY_LENGTH)
-            .map_err(|e| messaging::Error {
-                tab: response.tab.clone(),
-                reset: false,
-                title: "ExtEditorR failed to process temporary file".to_owned(),
-                message: util::error_message_with_path(e, temp_filename),
-            })?;
-
-        for response in responses {
-            if let Err(e) = T::write_message(&response) {
-                eprintln!("ExtEditorR failed to send response to Thunderbird: {e}");
-            }
-        }
-    }
-
-    Ok(())
-}
 
 fn print_help() -> anyhow::Result<()> {
     match env::current_exe() {
         Ok(program_path) => {
-            let native_app_manifest = AppManifest::new(&program_path.to_string_lossy());
-            let app_name = native_app_manifest.name;
+            let app_name = get_argument_value("--app-name")
+                .filter(|app_name| !app_name.is_empty())
+                .unwrap_or_else(|| app_manifest::NATIVE_APP_NAME.to_owned());
+            let manifest_format = match get_argument_value("--manifest-format") {
+                Some(manifest_format) => {
+                    ManifestFormat::from_str(&manifest_format).map_err(|e| anyhow::anyhow!(e))?
+                }
+                None => ManifestFormat::default(),
+            };
+            let native_app_manifest =
+                AppManifest::new(&app_name, &program_path.to_string_lossy(), manifest_format);
+            let app_name = &native_app_manifest.name;
             eprintln!("Please create '{app_name}.json' manifest file with the JSON below.");
             if cfg!(target_os = "macos") {
                 eprintln!(
@@ -181,6 +48,42 @@ fn print_help() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Read a configuration file for the self-test, which restricts the shells like the one passed
+/// to the host does
+fn read_configuration(config_filename: &Path) -> anyhow::Result<Configuration> {
+    let mut defaults = config_file::load(config_filename)?;
+    restrict_shells(Some(&mut defaults));
+    // the version is filled in by self_test anyway
+    let mut request = serde_json::json!({"configuration": {"version": ""}});
+    config_file::apply_defaults(&defaults, &mut request);
+    Ok(serde_json::from_value(request["configuration"].take())?)
+}
+
+/// Apply the allow-list of shells from the environment, or else from the configuration file
+fn restrict_shells(defaults: Option<&mut Map<String, Value>>) {
+    let configured_shells = match defaults.map(config_file::take_allowed_shells) {
+        Some(Ok(allowed_shells)) => allowed_shells,
+        Some(Err(e)) => {
+            log_error!("ExtEditorR refuses to run any shell: {e}");
+            Some(Vec::new())
+        }
+        None => None,
+    };
+    if let Some(shells) = allowed_shells::from_env().or(configured_shells) {
+        allowed_shells::set(shells);
+    }
+}
+
+fn get_argument_value(name: &str) -> Option<String> {
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == name {
+            return args.next();
+        }
+    }
+    None
+}
+
 fn main() -> anyhow::Result<()> {
     if env::args().count() == 1 {
         // Thunderbird calls us with: /path/to/external-editor-revived /path/to/native-messaging-hosts/external_editor_revived.json external-editor-revived@tsundere.moe
@@ -200,112 +103,104 @@ fn main() -> anyhow::Result<()> {
             "-h" | "--help" => {
                 return print_help();
             }
+            "--check" => {
+                let json = env::args().skip(2).any(|arg| arg == "--json");
+                let Some(eml_filename) = env::args().skip(2).find(|arg| arg != "--json") else {
+                    eprintln!("Usage: --check [--json] /path/to/temp.eml");
+                    process::exit(2);
+                };
+                let clean = handler::check_eml(Path::new(&eml_filename), json, &mut io::stdout())?;
+                if !clean {
+                    process::exit(1);
+                }
+                return Ok(());
+            }
+            "--selftest" => {
+                let Some(config_filename) = env::args().nth(2) else {
+                    eprintln!("Usage: --selftest /path/to/configuration.json");
+                    process::exit(2);
+                };
+                let configuration = read_configuration(Path::new(&config_filename))?;
+                cleanup::install_signal_handler();
+                let succeeded = handler::self_test(configuration, &mut io::stdout())?;
+                if !succeeded {
+                    process::exit(1);
+                }
+                return Ok(());
+            }
             _ => {}
         }
     }
 
-    type Tr = transport::ThunderbirdTransport;
-    loop {
-        let request = Tr::read_message::<Exchange>()
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
-
-        thread::spawn(move || match request {
-            Exchange::Ping(ping) => handle_ping::<Tr>(ping),
-            Exchange::Compose(compose) => handle_compose::<Tr>(compose),
-        });
+    let verbosity = get_argument_value("--verbosity")
+        .or_else(|| env::var(logger::LOG_LEVEL_ENV).ok())
+        .filter(|verbosity| !verbosity.is_empty());
+    if let Some(verbosity) = verbosity {
+        match Level::from_str(&verbosity) {
+            Ok(level) => logger::set_max_level(level),
+            Err(_) => log_warn!("ExtEditorR ignored unknown log level {verbosity}"),
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::sync::Mutex;
-
-    use super::*;
-    use model::messaging::tests::get_blank_compose;
-
-    type MockTr = transport::MockTransport;
-    static WRITE_MESSAGE_CONTEXT_LOCK: Mutex<()> = Mutex::new(());
-
-    #[test]
-    fn ping_pong_test() {
-        let ping_json = r#"{"ping": 123456}"#;
-        let ping: Ping = serde_json::from_str(ping_json).unwrap();
-
-        let _guard = WRITE_MESSAGE_CONTEXT_LOCK.lock().unwrap();
-        let ctx = MockTr::write_message_context();
-        ctx.expect::<Ping>()
-            .withf(|p: &Ping| {
-                p.ping == 123456
-                    && p.pong == 123456
-                    && !p.compatible
-                    && p.host_version == env!("CARGO_PKG_VERSION")
-            })
-            .returning(|&_| Ok(()));
-        handle_ping::<MockTr>(ping);
-        ctx.checkpoint();
+    let log_file = get_argument_value("--log-file")
+        .or_else(|| env::var(exchange_log::LOG_FILE_ENV).ok())
+        .filter(|log_file| !log_file.is_empty());
+    if let Some(log_file) = log_file {
+        if let Err(e) = exchange_log::open(Path::new(&log_file)) {
+            log_error!("ExtEditorR failed to open log file {log_file}: {e}");
+        }
     }
 
-    #[test]
-    fn ping_pong_successful_version_check_test() {
-        let host_version = env!("CARGO_PKG_VERSION");
-        let ping_json = format!(r#"{{"ping": 123456, "version": "{}"}}"#, host_version);
-        let ping: Ping = serde_json::from_str(&ping_json).unwrap();
-
-        let _guard = WRITE_MESSAGE_CONTEXT_LOCK.lock().unwrap();
-        let ctx = MockTr::write_message_context();
-        ctx.expect::<Ping>()
-            .withf(|p: &Ping| {
-                let host_version = host_version.to_string();
-                p.ping == 123456
-                    && p.pong == 123456
-                    && p.compatible
-                    && p.host_version == host_version
-            })
-            .returning(|&_| Ok(()));
-        handle_ping::<MockTr>(ping);
-        ctx.checkpoint();
+    let dry_run = env::args().any(|arg| arg == "--dry-run");
+    if env::args().any(|arg| arg == "--echo") {
+        echo::enable();
     }
 
-    #[test]
-    fn ping_pong_failed_version_check_test() {
-        let host_version = env!("CARGO_PKG_VERSION");
-        let ping_json = r#"{"ping": 123456, "version": "0.0.0.0"}"#;
-        let ping: Ping = serde_json::from_str(ping_json).unwrap();
+    let config_file = get_argument_value("--config")
+        .or_else(|| env::var(config_file::CONFIG_FILE_ENV).ok())
+        .filter(|config_file| !config_file.is_empty());
+    let mut defaults = match config_file {
+        Some(config_file) => match config_file::load(Path::new(&config_file)) {
+            Ok(defaults) => Some(defaults),
+            Err(e) => {
+                log_error!("ExtEditorR failed to load configuration file: {e}");
+                None
+            }
+        },
+        None => None,
+    };
+    restrict_shells(defaults.as_mut());
 
-        let _guard = WRITE_MESSAGE_CONTEXT_LOCK.lock().unwrap();
-        let ctx = MockTr::write_message_context();
-        ctx.expect::<Ping>()
-            .withf(|p: &Ping| {
-                let host_version = host_version.to_string();
-                p.ping == 123456
-                    && p.pong == 123456
-                    && !p.compatible
-                    && p.host_version == host_version
-            })
-            .returning(|&_| Ok(()));
-        handle_ping::<MockTr>(ping);
-        ctx.checkpoint();
-    }
+    cleanup::install_signal_handler();
 
-    #[test]
-    fn echo_compose_test() {
-        let mut compose = get_blank_compose();
-        compose.configuration.version = env!("CARGO_PKG_VERSION").to_owned();
-        compose.configuration.shell = "sh".to_string();
-        compose.configuration.template = r#"cat "/path/to/temp.eml""#.to_owned();
-        compose.configuration.temporary_directory = ".".to_owned();
-        compose.tab.id = 1;
-        compose.compose_details.plain_text_body = "Hello, world!\r\n".to_owned();
+    type Tr = transport::ThunderbirdTransport;
+    loop {
+        // stdin is closed when Thunderbird exits, so there is no one to report this to
+        if Tr::is_input_closed() {
+            return Ok(());
+        }
+        let mut request = match Tr::read_message::<serde_json::Value>() {
+            Ok(request) => request,
+            Err(e) => {
+                handler::handle_read_error::<Tr>(&e);
+                return Err(io::Error::other(e.to_string()).into());
+            }
+        };
+        if let Some(ref defaults) = defaults {
+            config_file::apply_defaults(defaults, &mut request);
+        }
+        let request: Exchange = match serde_json::from_value(request) {
+            Ok(request) => request,
+            Err(e) => {
+                handler::handle_read_error::<Tr>(&e);
+                return Err(e.into());
+            }
+        };
+        exchange_log::record(Direction::Received, &request);
 
-        let _guard = WRITE_MESSAGE_CONTEXT_LOCK.lock().unwrap();
-        let ctx = MockTr::write_message_context();
-        ctx.expect::<Compose>()
-            .withf(|c: &Compose| {
-                c.compose_details.plain_text_body == "Hello, world!\r\n"
-                    && c.configuration.total == 1
-            })
-            .returning(|&_| Ok(()));
-        handle_compose::<MockTr>(compose);
-        ctx.checkpoint();
+        thread::spawn(move || match request {
+            Exchange::Ping(ping) => handler::handle_ping::<Tr>(ping),
+            Exchange::Compose(compose) => handler::handle_compose::<Tr>(compose, dry_run),
+        });
     }
 }
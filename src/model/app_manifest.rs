@@ -1,27 +1,94 @@
 use serde::Serialize;
+use std::str::FromStr;
 
 const CONNECTION_TYPE: &str = "stdio";
-const NATIVE_APP_NAME: &str = "external_editor_revived";
+pub const NATIVE_APP_NAME: &str = "external_editor_revived";
 const EXTENSION_ID: &str = "external-editor-revived@tsundere.moe";
 
+/// Firefox and Thunderbird list allowed extensions by ID, while Chromium lists origins
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ManifestFormat {
+    #[default]
+    Firefox,
+    Chromium,
+}
+
+impl FromStr for ManifestFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "firefox" | "thunderbird" => Ok(Self::Firefox),
+            "chromium" | "chrome" => Ok(Self::Chromium),
+            _ => Err(format!("Unknown manifest format {s}")),
+        }
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct AppManifest {
-    pub name: &'static str,
+    pub name: String,
     pub description: &'static str,
     pub path: String,
     #[serde(rename = "type")]
     pub connection_type: &'static str,
-    pub allowed_extensions: Vec<&'static str>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub allowed_extensions: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub allowed_origins: Vec<String>,
 }
 
 impl AppManifest {
-    pub fn new(path: &str) -> Self {
+    pub fn new(name: &str, path: &str, format: ManifestFormat) -> Self {
+        let (allowed_extensions, allowed_origins) = match format {
+            ManifestFormat::Firefox => (vec![EXTENSION_ID.to_owned()], Vec::new()),
+            ManifestFormat::Chromium => (
+                Vec::new(),
+                vec![format!("chrome-extension://{EXTENSION_ID}/")],
+            ),
+        };
         Self {
-            name: NATIVE_APP_NAME,
+            name: name.to_owned(),
             description: env!("CARGO_PKG_DESCRIPTION"),
             path: path.to_owned(),
             connection_type: CONNECTION_TYPE,
-            allowed_extensions: vec![EXTENSION_ID],
+            allowed_extensions,
+            allowed_origins,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_app_name_test() {
+        let manifest = AppManifest::new(
+            "external_editor_revived_fork",
+            "/usr/bin/eer-fork",
+            ManifestFormat::Firefox,
+        );
+        let json = serde_json::to_value(&manifest).unwrap();
+        assert_eq!("external_editor_revived_fork", json["name"]);
+        assert_eq!("/usr/bin/eer-fork", json["path"]);
+        assert_eq!(EXTENSION_ID, json["allowed_extensions"][0]);
+        assert!(json.get("allowed_origins").is_none());
+    }
+
+    #[test]
+    fn chromium_manifest_test() {
+        let manifest = AppManifest::new(NATIVE_APP_NAME, "/usr/bin/eer", ManifestFormat::Chromium);
+        let json = serde_json::to_value(&manifest).unwrap();
+        assert!(json.get("allowed_extensions").is_none());
+        assert_eq!(
+            format!("chrome-extension://{EXTENSION_ID}/"),
+            json["allowed_origins"][0]
+        );
+        assert_eq!(
+            Ok(ManifestFormat::Chromium),
+            ManifestFormat::from_str("chromium")
+        );
+        assert!(ManifestFormat::from_str("safari").is_err());
+    }
+}
@@ -1,21 +1,75 @@
 use anyhow::{anyhow, Result};
+use chrono::{DateTime, FixedOffset};
 use serde::{Deserialize, Serialize};
+use std::env;
 use std::error::Error as StdError;
 use std::{io, str::FromStr};
 
-use super::thunderbird::*;
-use crate::{util, writeln_crlf};
+use super::thunderbird::{self, *};
+use crate::util::logger::{self, Level};
+use crate::{log_warn, util, writeln_crlf};
 
 pub const MAX_BODY_LENGTH: usize = 768 * 1024;
+pub const DEFAULT_MAX_HEADER_LENGTH: usize = 64 * 1024;
+pub const DEFAULT_READ_RETRIES: u32 = 3;
+pub const DEFAULT_MAX_CHUNKS: usize = 1000;
+pub const DEFAULT_ATTACHMENT_SIZE_NOTICE: u64 = 20 * 1024 * 1024;
+pub const DEFAULT_PERSISTENT_EDITOR_TIMEOUT: u64 = 24 * 60 * 60;
 
+/// Optional features supported by this host, sent back in [`Ping`]
+pub const CAPABILITIES: &[&str] = &[
+    "allow-sender-header",
+    "allow-threading-headers",
+    "append-position",
+    "attach-file",
+    "auto-cc-bcc",
+    "bcc-self",
+    "blank-lines-in-headers",
+    "charset",
+    "collapse-recipients",
+    "compress-body",
+    "discard",
+    "fence-control-headers",
+    "merge-duplicate-custom-headers",
+    "permitted-headers",
+    "persistent-editor",
+    "plain-text-toggle",
+    "post-command",
+    "pre-command",
+    "preserve-signature",
+    "preview",
+    "quote-prefix",
+    "raw-body",
+    "report-temp-file-path",
+    "request-id",
+    "reset",
+    "send-later",
+    "separate-output-file",
+    "sort-recipients",
+    "strip-signature",
+    "trim-trailing-newline",
+    "warn-empty-subject",
+    "warn-no-recipients",
+    "warnings-in-eml",
+    "x-priority",
+];
+
+// HEADER_* are the canonical forms written by to_eml. When reading, header names are matched
+// case-insensitively, i.e. against HEADER_LOWER_* after lowercasing.
 const HEADER_META: &str = "X-ExtEditorR";
+const FENCE_OPEN: &str = "# >>> ExtEditorR";
+const FENCE_CLOSE: &str = "# <<< ExtEditorR";
 const HEADER_LOWER_META: &str = "x-exteditorr"; // cspell: disable-line
 const HEADER_NORMALISED_META: &str = "X-Exteditorr"; // normalised by Thunderbird, cspell: disable-line
 const HEADER_LOWER_ESCAPED_META: &str = "x-exteditorr-x-exteditorr"; // cspell: disable-line
 const HEADER_PRIORITY: &str = "X-ExtEditorR-Priority";
 const HEADER_LOWER_PRIORITY: &str = "x-exteditorr-priority"; // cspell: disable-line
+const HEADER_X_PRIORITY: &str = "X-Priority";
+const HEADER_LOWER_X_PRIORITY: &str = "x-priority";
 const HEADER_DELIVERY_FORMAT: &str = "X-ExtEditorR-Delivery-Format";
 const HEADER_LOWER_DELIVERY_FORMAT: &str = "x-exteditorr-delivery-format"; // cspell: disable-line
+const HEADER_PLAIN_TEXT: &str = "X-ExtEditorR-Plain-Text";
+const HEADER_LOWER_PLAIN_TEXT: &str = "x-exteditorr-plain-text"; // cspell: disable-line
 const HEADER_ATTACH_VCARD: &str = "X-ExtEditorR-Attach-vCard";
 const HEADER_LOWER_ATTACH_VCARD: &str = "x-exteditorr-attach-vcard"; // cspell: disable-line
 const HEADER_DELIVERY_STATUS_NOTIFICATION: &str = "X-ExtEditorR-Delivery-Status-Notification";
@@ -24,6 +78,20 @@ const HEADER_DSN: &str = "X-ExtEditorR-DSN";
 const HEADER_LOWER_DSN: &str = "x-exteditorr-dsn"; // cspell: disable-line
 const HEADER_RETURN_RECEIPT: &str = "X-ExtEditorR-Return-Receipt";
 const HEADER_LOWER_RETURN_RECEIPT: &str = "x-exteditorr-return-receipt"; // cspell: disable-line
+const HEADER_BCC_SELF: &str = "X-ExtEditorR-Bcc-Self";
+const HEADER_LOWER_BCC_SELF: &str = "x-exteditorr-bcc-self"; // cspell: disable-line
+const HEADER_ATTACH_FILE: &str = "X-ExtEditorR-Attach-File";
+const HEADER_LOWER_ATTACH_FILE: &str = "x-exteditorr-attach-file"; // cspell: disable-line
+const HEADER_LOWER_DISCARD: &str = "x-exteditorr-discard"; // cspell: disable-line
+const HEADER_LOWER_RESET: &str = "x-exteditorr-reset"; // cspell: disable-line
+const HEADER_LOWER_PREVIEW: &str = "x-exteditorr-preview"; // cspell: disable-line
+const HEADER_SUBJECT_PREFIX: &str = "X-ExtEditorR-Subject-Prefix";
+const HEADER_LOWER_SUBJECT_PREFIX: &str = "x-exteditorr-subject-prefix"; // cspell: disable-line
+const HEADER_SEND_LATER: &str = "X-ExtEditorR-Send-Later";
+const HEADER_LOWER_SEND_LATER: &str = "x-exteditorr-send-later"; // cspell: disable-line
+const HEADER_CHARSET: &str = "X-ExtEditorR-Charset";
+const HEADER_LOWER_CHARSET: &str = "x-exteditorr-charset"; // cspell: disable-line
+const DEFAULT_CHARSET: &str = "UTF-8";
 const HEADER_SEND_ON_EXIT: &str = "X-ExtEditorR-Send-On-Exit";
 const HEADER_LOWER_SEND_ON_EXIT: &str = "x-exteditorr-send-on-exit"; // cspell: disable-line
 const HEADER_ALLOW_X_HEADERS: &str = "X-ExtEditorR-Allow-X-Headers";
@@ -31,6 +99,12 @@ const HEADER_LOWER_ALLOW_X_HEADERS: &str = "x-exteditorr-allow-x-headers"; // cs
 const HEADER_LOWER_ALLOW_CUSTOM_HEADERS: &str = "x-exteditorr-allow-custom-headers"; // cspell: disable-line
 const HEADER_LOWER_CUSTOM_HEADER: &str = "x-exteditorr-custom-header"; // cspell: disable-line
 const HEADER_LOWER_X_HEADER: &str = "x-exteditorr-x-header"; // cspell: disable-line
+const HEADER_IN_REPLY_TO: &str = "In-Reply-To";
+const HEADER_REFERENCES: &str = "References";
+const HEADER_SENDER: &str = "Sender";
+const HEADER_LOWER_MOZILLA_PREFIX: &str = "x-mozilla-";
+const HEADER_WARNING: &str = "X-ExtEditorR-Warning";
+const HEADER_LOWER_WARNING: &str = "x-exteditorr-warning"; // cspell: disable-line
 const HEADER_HELP: &str = "X-ExtEditorR-Help";
 const HEADER_LOWER_HELP: &str = "x-exteditorr-help"; // cspell: disable-line
 const HEADER_HELP_LINES: &[&str] = &[
@@ -41,6 +115,7 @@ const HEADER_HELP_LINES: &[&str] = &[
     "Priority options: lowest, low, normal, high, highest.",
     "Delivery format options: auto, plaintext, html, both.",
     "Custom header names must start with \"X-\".",
+    "Set `X-ExtEditorR-Discard: true` to abandon all changes.",
     "KEEP blank line below to separate headers from body.",
 ];
 
@@ -64,6 +139,12 @@ pub struct Ping {
     pub host_version: String,
     #[serde(default)]
     pub compatible: bool,
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+    #[serde(default)]
+    pub os: String,
+    #[serde(default)]
+    pub arch: String,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -74,22 +155,309 @@ pub struct Configuration {
     pub sequence: usize,
     #[serde(default)]
     pub total: usize,
-    #[serde(skip_serializing)]
+    /// Echoed verbatim in every response to the request, generated by the host if empty
+    #[serde(default)]
+    pub request_id: String,
+    #[serde(default, skip_serializing)]
     pub shell: String,
-    #[serde(skip_serializing)]
+    #[serde(default, skip_serializing)]
+    pub shell_args: Vec<String>,
+    #[serde(default = "default_true", skip_serializing)]
+    pub macos_login_shell: bool,
+    #[serde(default, skip_serializing)]
     pub template: String,
+    /// Editor command line, run directly without a shell if not empty. `/path/to/temp.eml` and
+    /// `/path/to/output.eml` are substituted in each argument.
+    #[serde(default, skip_serializing)]
+    pub command: Vec<String>,
+    /// Command template to hand the temporary file over to an already running editor, e.g. an
+    /// nvim server, instead of starting a new one. It's run through the shell like `template`,
+    /// with `/path/to/temp.lock` substituted as well. The lifecycle is:
+    ///
+    /// 1. The lock file is created next to the temporary file
+    /// 2. This command is run, which usually returns right after passing on the file
+    /// 3. The editor removes the lock file once done, e.g. in an autocommand when the buffer is
+    ///    closed
+    /// 4. The temporary file is read back as usual
+    #[serde(default, skip_serializing)]
+    pub persistent_editor_command: String,
+    /// Give up waiting for the editor to remove the lock file after this many seconds, e.g. if it
+    /// crashed, 0 to wait indefinitely
+    #[serde(default = "default_persistent_editor_timeout", skip_serializing)]
+    pub persistent_editor_timeout: u64,
+    /// Command template run through the shell after the temporary file is written and before the
+    /// editor starts, e.g. to insert a template. Placeholders are substituted as in `template`.
+    #[serde(default, skip_serializing)]
+    pub pre_command: String,
+    /// Command template run through the shell after the editor exits successfully and before the
+    /// temporary file is read back, e.g. a formatter. `/path/to/temp.eml` and
+    /// `/path/to/output.eml` are substituted as in `template`.
+    #[serde(default, skip_serializing)]
+    pub post_command: String,
     #[serde(default)]
     pub temporary_directory: String,
+    /// File name of the temporary file with `{tab_id}`, `{window_id}`, `{subject}` and `{date}`
+    /// placeholders. Without `{tab_id}`, the tab ID is added before the extension so that compose
+    /// windows don't share the same file.
+    #[serde(default)]
+    pub temp_file_name_template: String,
     #[serde(default)]
     pub send_on_exit: bool,
+    /// Schedule the message instead of sending it immediately, mutually exclusive with
+    /// `send_on_exit`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub send_later: Option<DateTime<FixedOffset>>,
+    /// Prepended to the subject unless it's there already
+    #[serde(default)]
+    pub subject_prefix: String,
+    /// Charset for the Content-Type of the outgoing message, the canonical name of an
+    /// `X-ExtEditorR-Charset` label
+    #[serde(default)]
+    pub charset: String,
+    #[serde(default)]
+    pub discard: bool,
+    /// Set by `X-ExtEditorR-Preview: true` so that the extension asks for confirmation before
+    /// applying the response. Never sent on exit.
+    #[serde(default)]
+    pub preview: bool,
+    /// Unlike discard, the original compose details are still sent back and applied
+    #[serde(skip)]
+    pub reset: bool,
+    #[serde(skip)]
+    pub bcc_self: bool,
+    /// Fill in an empty Reply-To with the sender
+    #[serde(default)]
+    pub default_reply_to_from: bool,
+    /// Write the priority as the numeric `X-Priority` header instead of `X-ExtEditorR-Priority`.
+    /// Both are accepted when reading, unless custom headers are allowed and this is off, in which
+    /// case `X-Priority` is a custom header like any other. So is a value that's not a number.
+    #[serde(default)]
+    pub emit_x_priority: bool,
+    /// Addresses always added to Cc when reading the file back, unless they are there already
+    #[serde(default)]
+    pub auto_cc: Vec<String>,
+    /// Addresses always added to Bcc when reading the file back, unless they are there already
+    #[serde(default)]
+    pub auto_bcc: Vec<String>,
     #[serde(default)]
     pub suppress_help_headers: bool,
+    /// Write warnings from the previous round, which the extension keeps per tab and sends back in
+    /// the next request, as `X-ExtEditorR-Warning` headers at the top so that they are still
+    /// visible when the editor is opened again
+    #[serde(default)]
+    pub warnings_in_eml: bool,
     #[serde(default)]
     pub meta_headers: bool,
     #[serde(default)]
     pub allow_custom_headers: bool,
     #[serde(default)]
     pub bypass_version_check: bool,
+    #[serde(default)]
+    pub merge_duplicate_custom_headers: CustomHeaderMergeStrategy,
+    /// Whether the edited text replaces the body or is added to it
+    #[serde(default)]
+    pub append_position: AppendPosition,
+    #[serde(default)]
+    pub report_temp_file_path: bool,
+    /// Read the result from `/path/to/output.eml` instead if the editor has written to it
+    #[serde(default)]
+    pub separate_output_file: bool,
+    /// Also write the compose details as JSON next to the temporary file for editor scripts. It
+    /// is not read back.
+    #[serde(default)]
+    pub write_metadata_json: bool,
+    /// Convert the body from HTML to plain text if the compose is switched to plain text, i.e.
+    /// `X-ExtEditorR-Plain-Text: true`
+    #[serde(default)]
+    pub convert_html_to_text: bool,
+    /// Show `>` quote markers of plain text bodies as this prefix in the editor, one per nesting
+    /// level, and turn them back into `>` when reading. Lines that already started with the prefix
+    /// are shown behind a zero-width space, which marks them to be left alone.
+    #[serde(default)]
+    pub quote_prefix: String,
+    /// Never alter lines starting with `>` quote markers, e.g. by trimming the trailing newline or
+    /// showing the markers as `quote_prefix`
+    #[serde(default)]
+    pub preserve_quote_prefixes: bool,
+    /// Strip a single trailing newline (usually added by editors) from the body
+    #[serde(default)]
+    pub trim_trailing_newline: bool,
+    /// Restore the `-- ` signature delimiter if its trailing space has been stripped
+    #[serde(default)]
+    pub preserve_signature: bool,
+    /// Remove the `-- ` signature delimiter and the signature after it from a plain text body, so
+    /// that Thunderbird's own signature handling takes over
+    #[serde(default)]
+    pub strip_signature: bool,
+    /// Pass an HTML body through without normalising its line breaks, e.g. to keep `\r` in
+    /// `<pre>`. Plain text bodies are always normalised.
+    #[serde(default)]
+    pub raw_body: bool,
+    #[serde(default)]
+    pub warn_no_recipients: bool,
+    /// Add a notice if the subject is blank. Unlike other warnings, this doesn't cancel
+    /// send-on-exit unless `empty_subject_blocks_send` is also set.
+    #[serde(default)]
+    pub warn_empty_subject: bool,
+    #[serde(default)]
+    pub empty_subject_blocks_send: bool,
+    /// Decompress the edited file with gzip, e.g. when the editor is wrapped by a script that
+    /// compresses the file on save. The file name stays the same.
+    #[serde(default)]
+    pub compress_body: bool,
+    /// Replaces the built-in help header lines if not empty
+    #[serde(default)]
+    pub help_lines: Vec<String>,
+    /// A marker line, e.g. `#BodyStartsAfterThisLine#`, that separates headers from body in
+    /// place of the blank line
+    #[serde(default)]
+    pub body_separator: String,
+    /// Sort each recipient list in the temporary file. This only affects the display order, the
+    /// order read back from the file is what gets sent.
+    #[serde(default)]
+    pub sort_recipients: bool,
+    /// Write each recipient list as a single comma-separated header, e.g. `To: a@example.com,
+    /// b@example.com`, which is then split on commas when reading the file back
+    #[serde(default)]
+    pub collapse_recipients: bool,
+    /// Keep send-on-exit even if there are warnings, which disable it by default. An invalid sender
+    /// or missing recipients still disable it.
+    #[serde(default)]
+    pub send_despite_warnings: bool,
+    /// How many more times to try opening the edited file if it's missing, e.g. during a
+    /// rename-on-save
+    #[serde(default = "default_read_retries")]
+    pub read_retries: u32,
+    /// Add a notice if attachments add up to more than this in bytes, 0 to disable. Unlike
+    /// other warnings, this doesn't cancel send-on-exit.
+    #[serde(default = "default_attachment_size_notice")]
+    pub attachment_size_notice: u64,
+    /// Fail instead of sending the body to Thunderbird in more chunks than this
+    #[serde(default = "default_max_chunks")]
+    pub max_chunks: usize,
+    /// Non-zero exit codes of the editor that are treated as success. If empty, any non-zero exit
+    /// code aborts the request.
+    #[serde(default)]
+    pub ignore_exit_codes: Vec<i32>,
+    /// Fail instead of clearing the body if the file ends without the blank line (or
+    /// `body_separator`) after headers
+    #[serde(default)]
+    pub require_body_separator: bool,
+    /// Skip a blank line among headers if the next non-blank line is a known header, e.g.
+    /// `Subject` or `X-ExtEditorR-*`, so that headers can be grouped visually. Otherwise the first
+    /// blank line is always the end of headers.
+    #[serde(default)]
+    pub blank_lines_in_headers: bool,
+    /// Wrap X-ExtEditorR and help headers between fence comment lines so that editors can fold
+    /// them
+    #[serde(default)]
+    pub fence_control_headers: bool,
+    /// Accept `In-Reply-To` and `References` as custom headers. Note that older versions of
+    /// Thunderbird reject custom headers that don't start with `X-`.
+    #[serde(default)]
+    pub allow_threading_headers: bool,
+    /// Accept `Sender`, e.g. when sending on behalf of someone else, as a custom header
+    #[serde(default)]
+    pub allow_sender_header: bool,
+    /// Names of other headers that are accepted as custom headers even without the `X-` prefix,
+    /// e.g. `Comments`. Like threading headers, older versions of Thunderbird may reject them.
+    #[serde(default)]
+    pub permitted_headers: Vec<String>,
+    /// Headers longer than this in bytes are left out of the temporary file and kept as they are,
+    /// or ignored if found in the file. Either way there is a warning.
+    #[serde(default = "default_max_header_length")]
+    pub max_header_length: usize,
+    /// Headers that are left out of the temporary file, e.g. `Bcc` or `X-ExtEditorR-Priority`
+    /// (or just `Priority`). Their values are kept as they are.
+    #[serde(default)]
+    pub hidden_headers: Vec<String>,
+    /// Headers that are written even if they are not set, which takes precedence over
+    /// `hidden_headers`
+    #[serde(default)]
+    pub always_shown_headers: Vec<String>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub temp_file_path: String,
+    /// Total body length in bytes across all chunks, only reported in debug mode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body_length: Option<usize>,
+}
+
+impl Configuration {
+    /// Whether a header can be passed on as a custom header even if custom headers are not
+    /// allowed, or if it doesn't start with `X-`
+    fn is_permitted_header(&self, name: &str) -> bool {
+        is_mozilla_header(name)
+            || (self.allow_threading_headers && is_threading_header(name))
+            || (self.allow_sender_header && name.trim().eq_ignore_ascii_case(HEADER_SENDER))
+            || self
+                .permitted_headers
+                .iter()
+                .any(|permitted| permitted.trim().eq_ignore_ascii_case(name))
+    }
+
+    fn is_header_hidden(&self, name: &str) -> bool {
+        contains_header_name(&self.hidden_headers, name) && !self.is_header_always_shown(name)
+    }
+
+    fn is_header_always_shown(&self, name: &str) -> bool {
+        contains_header_name(&self.always_shown_headers, name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CustomHeaderMergeStrategy {
+    /// Pass duplicate custom headers to Thunderbird as they are
+    #[default]
+    Off,
+    /// Join values of custom headers with the same name using commas
+    Join,
+    /// Only keep the last value of custom headers with the same name
+    KeepLast,
+}
+
+/// In [`AppendPosition::Prepend`] and [`AppendPosition::Append`] modes, the temporary file
+/// starts with an empty body, and the edited text is added before or after the original body,
+/// with a line break in between if needed. The combined body is then split into chunks as usual.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AppendPosition {
+    /// Replace the original body with the edited text
+    #[default]
+    Replace,
+    /// Add the edited text before the original body
+    Prepend,
+    /// Add the edited text after the original body
+    Append,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ContentDisposition {
+    #[default]
+    Attachment,
+    Inline,
+}
+
+impl FromStr for ContentDisposition {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "attachment" => Ok(Self::Attachment),
+            "inline" => Ok(Self::Inline),
+            _ => Err(anyhow!("Unknown content disposition {s}")),
+        }
+    }
+}
+
+/// A file to attach, from `X-ExtEditorR-Attach-File: [inline;|attachment;] /path/to/file`. The
+/// path must be absolute after expanding `~` and environment variables.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct AttachFile {
+    pub path: String,
+    #[serde(default)]
+    pub disposition: ContentDisposition,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -100,6 +468,9 @@ pub struct Compose {
     pub tab: Tab,
     #[serde(rename = "composeDetails")]
     pub compose_details: ComposeDetails,
+    /// Files to attach, from `X-ExtEditorR-Attach-File` headers
+    #[serde(default, rename = "attachFiles", skip_serializing_if = "Vec::is_empty")]
+    pub attach_files: Vec<AttachFile>,
 }
 
 impl Compose {
@@ -107,16 +478,56 @@ impl Compose {
     where
         W: io::Write,
     {
-        writeln_crlf!(w, "From: {}", self.compose_details.from.to_header_value()?)?;
-        Self::compose_recipient_list_to_eml(w, "To", &self.compose_details.to)?;
-        Self::compose_recipient_list_to_eml(w, "Cc", &self.compose_details.cc)?;
-        Self::compose_recipient_list_to_eml(w, "Bcc", &self.compose_details.bcc)?;
-        Self::compose_recipient_list_to_eml(w, "Reply-To", &self.compose_details.reply_to)?;
-        writeln_crlf!(w, "Subject: {}", self.compose_details.subject)?;
+        if self.configuration.warnings_in_eml && !self.warnings.is_empty() {
+            self.write_fence(w, FENCE_OPEN)?;
+            for warning in &self.warnings {
+                writeln_crlf!(
+                    w,
+                    "{}: {}: {}",
+                    HEADER_WARNING,
+                    warning.title,
+                    fold_header_value(&warning.message)
+                )?;
+            }
+            self.write_fence(w, FENCE_CLOSE)?;
+        }
+        writeln_crlf!(
+            w,
+            "From: {}",
+            single_line(&self.compose_details.from.to_header_value()?)
+        )?;
+        self.compose_recipient_list_to_eml(w, "To", &self.compose_details.to)?;
+        self.compose_recipient_list_to_eml(w, "Cc", &self.compose_details.cc)?;
+        self.compose_recipient_list_to_eml(w, "Bcc", &self.compose_details.bcc)?;
+        self.compose_recipient_list_to_eml(w, "Reply-To", &self.compose_details.reply_to)?;
+        // an over-long subject is left out and then kept as it is when merging
+        if !self.is_header_too_long("Subject", &self.compose_details.subject) {
+            writeln_crlf!(w, "Subject: {}", single_line(&self.compose_details.subject))?;
+        }
+        let x_priority = self
+            .compose_details
+            .priority
+            .as_ref()
+            .filter(|_| self.configuration.emit_x_priority);
+        if let Some(priority) = x_priority {
+            if !self.configuration.is_header_hidden(HEADER_X_PRIORITY) {
+                writeln_crlf!(
+                    w,
+                    "{}: {} ({})",
+                    HEADER_X_PRIORITY,
+                    priority.to_x_priority(),
+                    priority
+                )?;
+            }
+        }
         // X-ExtEditorR headers
         let mut headers = Vec::new();
-        if let Some(ref priority) = self.compose_details.priority {
+        if x_priority.is_some() {
+            // written above
+        } else if let Some(ref priority) = self.compose_details.priority {
             headers.push(format!("{HEADER_PRIORITY}: {priority}"));
+        } else if self.configuration.is_header_always_shown(HEADER_PRIORITY) {
+            headers.push(format!("{HEADER_PRIORITY}: [{}]", Priority::Normal));
         }
         if let Some(ref delivery_format) = self.compose_details.delivery_format {
             match delivery_format {
@@ -129,6 +540,10 @@ impl Compose {
                 )),
             }
         }
+        headers.push(format!(
+            "{HEADER_PLAIN_TEXT}: {}",
+            self.compose_details.is_plain_text
+        ));
         if let Some(attach_vcard) = self.compose_details.attach_vcard.inner {
             headers.push(format!("{HEADER_ATTACH_VCARD}: [{attach_vcard}]"));
         }
@@ -141,9 +556,26 @@ impl Compose {
                 HEADER_DELIVERY_STATUS_NOTIFICATION
             };
             headers.push(format!("{header_dsn_name}: {delivery_status_notification}"));
+        } else if self.configuration.is_header_always_shown(HEADER_DSN)
+            || self
+                .configuration
+                .is_header_always_shown(HEADER_DELIVERY_STATUS_NOTIFICATION)
+        {
+            headers.push(format!("{HEADER_DSN}: [false]"));
         }
         if let Some(return_receipt) = self.compose_details.return_receipt {
             headers.push(format!("{HEADER_RETURN_RECEIPT}: {return_receipt}"));
+        } else if self
+            .configuration
+            .is_header_always_shown(HEADER_RETURN_RECEIPT)
+        {
+            headers.push(format!("{HEADER_RETURN_RECEIPT}: [false]"));
+        }
+        if !self.compose_details.from.is_empty() {
+            headers.push(format!(
+                "{HEADER_BCC_SELF}: [{}]",
+                self.compose_details.is_bcc_self()
+            ));
         }
         headers.push(format!(
             "{HEADER_ALLOW_X_HEADERS}: {}",
@@ -154,18 +586,50 @@ impl Compose {
             "{HEADER_SEND_ON_EXIT}: {}",
             self.configuration.send_on_exit
         ));
-        let (meta_custom_headers, other_custom_headers): (Vec<_>, Vec<_>) = self
+        if !self.configuration.subject_prefix.is_empty()
+            || self
+                .configuration
+                .is_header_always_shown(HEADER_SUBJECT_PREFIX)
+        {
+            headers.push(format!(
+                "{HEADER_SUBJECT_PREFIX}: {}",
+                single_line(&self.configuration.subject_prefix)
+            ));
+        }
+        if let Some(send_later) = self.configuration.send_later {
+            headers.push(format!("{HEADER_SEND_LATER}: {}", send_later.to_rfc3339()));
+        }
+        let charset = match self.configuration.charset.as_str() {
+            "" => DEFAULT_CHARSET,
+            charset => charset,
+        };
+        headers.push(format!("{HEADER_CHARSET}: {}", single_line(charset)));
+        headers.retain(|header| {
+            header
+                .split_once(':')
+                .is_none_or(|(name, _)| !self.configuration.is_header_hidden(name))
+        });
+        // like the subject, over-long custom headers are kept as they are when merging
+        let custom_headers: Vec<_> = self
             .compose_details
             .custom_headers
             .iter()
-            .partition(|custom_header| {
+            .filter(|custom_header| !self.is_custom_header_too_long(custom_header))
+            .collect();
+        // only the leading escaped meta headers go together with other X-ExtEditorR headers, so
+        // that the order of custom headers is preserved
+        let meta_custom_headers_len = custom_headers
+            .iter()
+            .take_while(|custom_header| {
                 custom_header
                     .name
                     .to_lowercase()
                     .starts_with(HEADER_LOWER_META)
-                    && !custom_header.value.contains(',')
-                    && !custom_header.value.contains(':')
-            });
+                    && !custom_header.value.contains([',', ':', '\n'])
+            })
+            .count();
+        let (meta_custom_headers, other_custom_headers) =
+            custom_headers.split_at(meta_custom_headers_len);
         for custom_header in meta_custom_headers {
             headers.push(format!(
                 "{}-{}: {}",
@@ -176,6 +640,7 @@ impl Compose {
                 custom_header.value
             ));
         }
+        self.write_fence(w, FENCE_OPEN)?;
         if self.configuration.meta_headers {
             let headers: Vec<_> = headers
                 .into_iter()
@@ -190,6 +655,7 @@ impl Compose {
                 writeln_crlf!(w, "{}", header)?;
             }
         }
+        self.write_fence(w, FENCE_CLOSE)?;
 
         for custom_header in other_custom_headers {
             if custom_header
@@ -205,17 +671,39 @@ impl Compose {
                     "{}-{}: {}",
                     HEADER_META,
                     header_name,
-                    custom_header.value
+                    fold_header_value(&custom_header.value)
                 )?;
             } else {
-                writeln_crlf!(w, "{}: {}", custom_header.name, custom_header.value)?;
+                writeln_crlf!(
+                    w,
+                    "{}: {}",
+                    custom_header.name,
+                    fold_header_value(&custom_header.value)
+                )?;
             }
         }
         if !self.configuration.suppress_help_headers {
-            Self::write_help_headers(w)?;
+            self.write_fence(w, FENCE_OPEN)?;
+            self.write_help_headers(w)?;
+            self.write_fence(w, FENCE_CLOSE)?;
+        }
+        let body_separator = self.configuration.body_separator.trim();
+        if body_separator.is_empty() {
+            writeln_crlf!(w)?;
+        } else {
+            writeln_crlf!(w, "{}", body_separator)?;
+        }
+        if self.configuration.append_position == AppendPosition::Replace {
+            let mut body = if self.uses_raw_body() {
+                self.compose_details.get_raw_body().to_owned()
+            } else {
+                self.compose_details.get_body()
+            };
+            if self.uses_quote_prefix() {
+                body = thunderbird::replace_quote_markers(&body, &self.configuration.quote_prefix);
+            }
+            write!(w, "{}", body)?;
         }
-        writeln_crlf!(w)?;
-        write!(w, "{}", self.compose_details.get_body())?;
         Ok(())
     }
 
@@ -223,40 +711,244 @@ impl Compose {
     where
         R: io::BufRead,
     {
-        let mut compose_details_list: Vec<ComposeDetails> = Vec::new();
+        self.merge_from_eml_in_mode(r, max_body_length, logger::enabled(Level::Debug))
+    }
+
+    /// See [`Self::merge_from_eml`]. In debug mode, the total body length is reported as well.
+    fn merge_from_eml_in_mode<R>(
+        &mut self,
+        r: &mut R,
+        max_body_length: usize,
+        debug: bool,
+    ) -> Result<Vec<Self>>
+    where
+        R: io::BufRead,
+    {
+        if self.configuration.request_id.is_empty() {
+            self.configuration.request_id = util::generate_request_id();
+        }
+        let original_compose_details = self.compose_details.clone();
+        let original_configuration = self.configuration.clone();
+        let restore_signature_delimiter = self.configuration.preserve_signature
+            && original_compose_details.has_signature_delimiter();
+        let was_html = !original_compose_details.is_plain_text;
+        // only the body matching isPlainText is presented for editing, so the alternative one
+        // is passed back as it is
+        let alternative_body = if original_compose_details.is_plain_text {
+            original_compose_details.body.clone()
+        } else {
+            original_compose_details.plain_text_body.clone()
+        };
+        let original_body = if original_compose_details.is_plain_text {
+            original_compose_details.plain_text_body.clone()
+        } else {
+            original_compose_details.body.clone()
+        };
 
         self.compose_details.clear_recipients();
         self.configuration.send_on_exit = false;
+        self.configuration.send_later = None;
+        self.configuration.subject_prefix.clear();
+        self.configuration.charset.clear();
+        self.configuration.discard = false;
+        self.configuration.preview = false;
+        self.configuration.reset = false;
+        self.configuration.bcc_self = false;
+        self.attach_files.clear();
+        // warnings from the previous round, possibly echoed in the file
+        self.warnings.clear();
+
+        let (headers, mut body_prefix) = self.read_headers(r)?;
+        self.merge_headers(headers, &original_compose_details, &original_configuration)?;
+        self.validate_merged(&original_compose_details)?;
 
+        // read body
         let mut buf = Vec::new();
-        // read headers
-        let mut unknown_headers = Vec::new();
-        self.compose_details.custom_headers.clear();
+        let restore_original = self.configuration.discard || self.configuration.reset;
+        if restore_original {
+            // restore everything, which also cancels send-on-exit
+            self.compose_details = original_compose_details;
+            self.configuration.send_on_exit = false;
+            self.configuration.send_later = None;
+            self.attach_files.clear();
+            let original_body = if self.compose_details.is_plain_text {
+                &self.compose_details.plain_text_body
+            } else {
+                &self.compose_details.body
+            };
+            buf.extend_from_slice(original_body.as_bytes());
+        } else {
+            buf.append(&mut body_prefix);
+            r.read_to_end(&mut buf)?;
+        }
+        self.compose_details.body.clear();
+        self.compose_details.plain_text_body.clear();
+        let mut body = String::from_utf8_lossy(&buf).into_owned();
+        if !restore_original {
+            body = self.transform_edited_body(
+                body,
+                &original_body,
+                was_html,
+                restore_signature_delimiter,
+            );
+        }
+        if !self.uses_raw_body() {
+            // editors may have converted some or all of the line breaks
+            body = thunderbird::normalize_line_endings(&body);
+        }
+        if debug {
+            self.configuration.body_length = Some(body.len());
+        }
+        // the alternative body is skipped if isPlainText was switched, since the edited body has
+        // taken its place. note that with delivery format `both`, Thunderbird still generates the
+        // plain text part from the HTML body.
+        let alternative_body = if self.compose_details.is_plain_text != was_html {
+            alternative_body
+        } else {
+            String::new()
+        };
+        self.split_into_responses(&body, &alternative_body, max_body_length)
+    }
+
+    /// Read the header lines up to the separator, unfolding headers that allow folding. Returns
+    /// the headers with 1-based line numbers for error messages, and the lines read ahead that
+    /// belong to the body.
+    fn read_headers<R>(&self, r: &mut R) -> Result<(NumberedHeaders, Vec<u8>)>
+    where
+        R: io::BufRead,
+    {
+        let mut buf = Vec::new();
+        let mut headers: NumberedHeaders = Vec::new();
+        let mut line_number = 0;
+        let mut found_separator = false;
+        // lines read ahead after a blank line that turned out to be the separator
+        let mut body_prefix = Vec::new();
         while let Ok(length) = r.read_until(b'\n', &mut buf) {
             if length == 0 {
                 break;
             }
-            let line = String::from_utf8_lossy(&buf).trim().to_owned();
-            if line.is_empty() {
+            line_number += 1;
+            let mut line = String::from_utf8_lossy(&buf).into_owned();
+            if util::is_blank(&line) && !self.configuration.blank_lines_in_headers {
+                found_separator = true;
                 break;
             }
-            if let Some((header_name, header_value)) = line.split_once(':') {
-                self.process_header(header_name, header_value, &mut unknown_headers)?;
-            } else {
-                eprintln!("ExtEditorR failed to process header {line}");
+            if util::is_blank(&line) {
+                // a blank line is only skipped if the next non-blank line is a known header (or a
+                // fence). otherwise it is the separator and whatever follows is the body, which
+                // may well start with e.g. `Note: `.
+                match read_next_non_blank_line(r, &mut body_prefix, &mut line_number) {
+                    Some(next) if is_fence(&next) || looks_like_known_header(&next) => {
+                        body_prefix.clear();
+                        line = next;
+                    }
+                    _ => {
+                        found_separator = true;
+                        break;
+                    }
+                }
+            }
+            if self.is_body_separator(&line) {
+                found_separator = true;
+                break;
+            }
+            // fences are purely cosmetic, so they are skipped even if not enabled
+            if is_fence(&line) {
+                buf.clear();
+                continue;
+            }
+            let is_indented = line.starts_with([' ', '\t']);
+            match headers.last_mut() {
+                // folded header, see RFC 5322 section 2.2.3
+                Some((_, header)) if is_indented && allows_folding(header) => {
+                    header.push('\n');
+                    header.push_str(line[1..].trim_end());
+                }
+                // otherwise most likely an indented header
+                _ if is_indented && !looks_like_header(&line) => {
+                    log_warn!("ExtEditorR ignored indented line {}", line.trim());
+                }
+                _ => headers.push((line_number, line.trim().to_owned())),
             }
             buf.clear();
         }
+        if !found_separator && self.configuration.require_body_separator {
+            return Err(MissingSeparatorError {
+                line: String::new(),
+            }
+            .into());
+        }
+        Ok((headers, body_prefix))
+    }
+
+    /// Apply the headers read from the temporary file, followed by everything derived from them
+    fn merge_headers(
+        &mut self,
+        headers: NumberedHeaders,
+        original_compose_details: &ComposeDetails,
+        original_configuration: &Configuration,
+    ) -> Result<()> {
+        let mut unknown_headers = Vec::new();
+        self.compose_details.custom_headers.clear();
+        // the last one wins, which may not be what the user wants
+        for name in ["From", "Subject"] {
+            let count = headers
+                .iter()
+                .filter(|(_, header)| {
+                    header.split_once(':').is_some_and(|(header_name, _)| {
+                        header_name.trim().eq_ignore_ascii_case(name)
+                    })
+                })
+                .count();
+            if count > 1 {
+                self.warnings.push(Warning {
+                    title: "Duplicate headers".to_owned(),
+                    message: format!(
+                        "ExtEditorR found {count} {name} headers, only the last one is used"
+                    ),
+                });
+            }
+        }
+        for (line_number, header) in headers {
+            let first_line = header.lines().next().unwrap_or_default().to_owned();
+            self.process_header_line(header, &mut unknown_headers)
+                .map_err(|e| {
+                    // the line is part of the message already
+                    if e.is::<MissingSeparatorError>() {
+                        return e;
+                    }
+                    let message = format!("{e} (line {line_number}: `{first_line}`)");
+                    e.context(message)
+                })?;
+        }
+        self.restore_hidden_headers(original_compose_details, original_configuration);
+        if self.configuration.bcc_self {
+            self.compose_details.add_bcc_self();
+        }
+        if self.configuration.default_reply_to_from {
+            self.compose_details.default_reply_to_from();
+        }
+        self.compose_details
+            .add_missing_cc(&self.configuration.auto_cc);
+        self.compose_details
+            .add_missing_bcc(&self.configuration.auto_bcc);
+        self.apply_subject_prefix();
         if !self.configuration.allow_custom_headers {
             // TODO: this is not ideal when it comes to meta headers, since the warning message
             // does not contain the original forms of:
             // 1. X-ExtEditorR: Delivery-Format: plaintext
             // 2. X-ExtEditorR: X-ExtEditorR: foo
             // 3. X-ExtEditorR-X-ExtEditorR: foo
-            self.compose_details
-                .custom_headers
-                .drain(..)
-                .for_each(|custom_header| unknown_headers.push(custom_header.name));
+            self.compose_details.custom_headers.retain(|custom_header| {
+                let is_permitted = self.configuration.is_permitted_header(&custom_header.name);
+                if !is_permitted {
+                    unknown_headers.push(custom_header.name.clone());
+                }
+                is_permitted
+            });
+        }
+        if self.configuration.merge_duplicate_custom_headers != CustomHeaderMergeStrategy::Off {
+            self.merge_duplicate_custom_headers();
         }
         if !unknown_headers.is_empty() {
             let mut message = "ExtEditorR did not recognise the following headers:\n".to_string();
@@ -271,27 +963,156 @@ impl Compose {
             };
             self.warnings.push(warning);
         }
-        // disable send-on-exit if there are warnings
-        if !self.warnings.is_empty() {
+        Ok(())
+    }
+
+    /// Warnings about the merged message, which also decide whether it's sent on exit
+    fn validate_merged(&mut self, original_compose_details: &ComposeDetails) -> Result<()> {
+        // the message can't be delivered as it is, so it's never sent on exit
+        let mut undeliverable = false;
+        if !self.compose_details.from.is_valid_sender() {
+            undeliverable = true;
+            let from = self.compose_details.from.to_header_value()?;
+            self.warnings.push(Warning {
+                title: "Invalid sender".to_owned(),
+                message: format!("ExtEditorR found invalid From header value: {from}"),
+            });
+        }
+        if self.configuration.warn_no_recipients
+            && self.compose_details.to.is_empty()
+            && self.compose_details.cc.is_empty()
+            && self.compose_details.bcc.is_empty()
+            && self.compose_details.newsgroups.is_empty()
+        {
+            undeliverable = true;
+            self.warnings.push(Warning {
+                title: "No recipients".to_owned(),
+                message: "ExtEditorR did not find any To, Cc or Bcc recipients".to_owned(),
+            });
+        }
+        if self.configuration.send_later.is_some() && self.configuration.send_on_exit {
+            self.configuration.send_on_exit = false;
+            self.warnings.push(Warning {
+                title: "Conflicting send options".to_owned(),
+                message: format!(
+                    "ExtEditorR found both {HEADER_SEND_LATER} and {HEADER_SEND_ON_EXIT}, the message will be sent later"
+                ),
+            });
+        }
+        if self.configuration.preview {
+            // the message may still change after the confirmation
             self.configuration.send_on_exit = false;
         }
-        // read body
-        self.compose_details.body.clear();
-        self.compose_details.plain_text_body.clear();
-        buf.clear();
-        r.read_to_end(&mut buf)?;
-        let body = String::from_utf8_lossy(&buf);
+        if self.configuration.empty_subject_blocks_send {
+            self.add_empty_subject_notice();
+        }
+        // disable send-on-exit if there are warnings, unless the user opted out
+        if undeliverable || (!self.warnings.is_empty() && !self.configuration.send_despite_warnings)
+        {
+            self.configuration.send_on_exit = false;
+        }
+        // purely advisory, so added after the send-on-exit check above
+        self.add_attachment_size_notice();
+        self.keep_long_headers(original_compose_details);
+        if !self.configuration.empty_subject_blocks_send {
+            self.add_empty_subject_notice();
+        }
+        Ok(())
+    }
+
+    /// Turn the edited body back into what Thunderbird expects, e.g. by restoring quote markers
+    /// and the signature delimiter, and combine it with the original body if configured
+    fn transform_edited_body(
+        &mut self,
+        mut body: String,
+        original_body: &str,
+        was_html: bool,
+        restore_signature_delimiter: bool,
+    ) -> String {
+        if self.configuration.convert_html_to_text && was_html && self.compose_details.is_plain_text
+        {
+            body = util::html_text::to_plain_text(&body);
+        }
+        if self.uses_quote_prefix() {
+            body = thunderbird::restore_quote_markers(&body, &self.configuration.quote_prefix);
+        }
+        if restore_signature_delimiter {
+            thunderbird::restore_signature_delimiter(&mut body);
+        }
+        if self.configuration.strip_signature && self.compose_details.is_plain_text {
+            thunderbird::strip_signature(&mut body);
+        }
+        // purely advisory like the attachment size notice
+        self.add_delivery_format_notice(&body);
+        let ends_with_quote = self.configuration.preserve_quote_prefixes
+            && body
+                .trim_end_matches(['\r', '\n'])
+                .rsplit('\n')
+                .next()
+                .is_some_and(is_quote_line);
+        if self.configuration.trim_trailing_newline && !ends_with_quote {
+            // done before chunking so that only the last chunk is affected
+            let trimmed_len = body
+                .strip_suffix("\r\n")
+                .or_else(|| body.strip_suffix('\n'))
+                .map(str::len);
+            if let Some(trimmed_len) = trimmed_len {
+                body.truncate(trimmed_len);
+            }
+        }
+        // done after trimming, which only applies to the edited text
+        match self.configuration.append_position {
+            AppendPosition::Replace => body,
+            AppendPosition::Prepend => join_bodies(&body, original_body),
+            AppendPosition::Append => join_bodies(original_body, &body),
+        }
+    }
+
+    /// Split the body and the alternative body into responses of at most `max_body_length`
+    /// bytes, as Thunderbird limits the size of native messages
+    fn split_into_responses(
+        &mut self,
+        body: &str,
+        alternative_body: &str,
+        max_body_length: usize,
+    ) -> Result<Vec<Self>> {
+        let mut compose_details_list: Vec<ComposeDetails> = Vec::new();
+        let total_length = body.len() + alternative_body.len();
+        // each chunk is longer than max_body_length, so this is an upper bound
+        let chunks = total_length.div_ceil(max_body_length + 1);
+        if chunks > self.configuration.max_chunks {
+            return Err(anyhow!(
+                "ExtEditorR found body too large ({total_length} bytes), which would be sent in {chunks} chunks while at most {} are allowed",
+                self.configuration.max_chunks
+            ));
+        }
+        // chunks are concatenated by the extension field by field, so the alternative body simply
+        // continues after the edited one within the same length budget
         let mut chunk = String::new();
-        for c in body.chars() {
-            chunk.push(c);
-            if chunk.len() > max_body_length {
+        let mut alternative_chunk = String::new();
+        let chars = body
+            .chars()
+            .map(|c| (c, false))
+            .chain(alternative_body.chars().map(|c| (c, true)));
+        for (c, is_alternative) in chars {
+            if is_alternative {
+                alternative_chunk.push(c);
+            } else {
+                chunk.push(c);
+            }
+            if chunk.len() + alternative_chunk.len() > max_body_length {
                 self.compose_details.set_body(chunk.clone());
+                self.compose_details
+                    .set_alternative_body(alternative_chunk.clone());
                 compose_details_list.push(self.compose_details.clone());
                 chunk.clear();
+                alternative_chunk.clear();
             }
         }
         self.compose_details.set_body(chunk.clone());
-        if !chunk.is_empty() || compose_details_list.is_empty() {
+        self.compose_details
+            .set_alternative_body(alternative_chunk.clone());
+        if !chunk.is_empty() || !alternative_chunk.is_empty() || compose_details_list.is_empty() {
             compose_details_list.push(self.compose_details.clone());
         }
 
@@ -311,6 +1132,32 @@ impl Compose {
         Ok(responses)
     }
 
+    fn process_header_line(
+        &mut self,
+        line: String,
+        unknown_headers: &mut Vec<String>,
+    ) -> Result<()> {
+        match line.split_once(':') {
+            Some((header_name, _)) if line.len() > self.configuration.max_header_length => {
+                self.warnings.push(Warning {
+                    title: "Header too long".to_owned(),
+                    message: format!(
+                        "ExtEditorR ignored {} header longer than {} bytes",
+                        header_name.trim(),
+                        self.configuration.max_header_length
+                    ),
+                });
+                Ok(())
+            }
+            Some((header_name, header_value)) if is_header_name(header_name) => {
+                self.process_header(header_name, header_value, unknown_headers)
+            }
+            _ => Err(MissingSeparatorError { line }.into()),
+        }
+    }
+
+    /// Header names are case-insensitive, including the ones in compact meta headers. Folded
+    /// header values contain `\n`, which is only kept in custom headers.
     fn process_header(
         &mut self,
         header_name: &str,
@@ -318,29 +1165,69 @@ impl Compose {
         unknown_headers: &mut Vec<String>,
     ) -> Result<()> {
         let header_name_lower = header_name.trim().to_lowercase();
-        let header_value = header_value.trim();
-        if header_value.is_empty() {
+        let folded_value = header_value.trim();
+        let unfolded_value = folded_value.replace('\n', " ");
+        let header_value = unfolded_value.as_str();
+        if util::is_blank(header_value) {
+            if header_name_lower == "from" {
+                // unlike a removed From header, an emptied one is not left unchanged
+                self.compose_details.from = ComposeRecipient::Email(String::new());
+            }
             return Ok(());
         }
         match header_name_lower.as_str() {
             "from" => {
-                self.compose_details.from = ComposeRecipient::from_header_value(header_value)?
+                let from = ComposeRecipient::from_header_value(header_value)?;
+                // a node is only kept if it came from Thunderbird in the first place
+                if matches!(from, ComposeRecipient::Node(_)) && from != self.compose_details.from {
+                    self.warnings.push(Warning {
+                        title: "Invalid sender".to_owned(),
+                        message: format!(
+                            "ExtEditorR does not support contact or mailing list in From header, ignored: {header_value}"
+                        ),
+                    });
+                } else {
+                    self.compose_details.from = from;
+                }
+            }
+            "to" => {
+                for recipient in self.parse_recipients(header_value)? {
+                    self.compose_details.add_to(recipient);
+                }
+            }
+            "cc" => {
+                for recipient in self.parse_recipients(header_value)? {
+                    self.compose_details.add_cc(recipient);
+                }
+            }
+            "bcc" => {
+                for recipient in self.parse_recipients(header_value)? {
+                    self.compose_details.add_bcc(recipient);
+                }
+            }
+            "reply-to" => {
+                for recipient in self.parse_recipients(header_value)? {
+                    self.compose_details.add_reply_to(recipient);
+                }
             }
-            "to" => self
-                .compose_details
-                .add_to(ComposeRecipient::from_header_value(header_value)?),
-            "cc" => self
-                .compose_details
-                .add_cc(ComposeRecipient::from_header_value(header_value)?),
-            "bcc" => self
-                .compose_details
-                .add_bcc(ComposeRecipient::from_header_value(header_value)?),
-            "reply-to" => self
-                .compose_details
-                .add_reply_to(ComposeRecipient::from_header_value(header_value)?),
             "subject" => self.compose_details.subject = header_value.to_string(),
             HEADER_LOWER_PRIORITY => {
-                self.compose_details.priority = Some(Priority::from_str(header_value)?)
+                // unlike other optional headers, brackets clear a priority set earlier
+                self.compose_details.priority =
+                    Self::parse_optional_header::<Priority>(HEADER_PRIORITY, header_value)?;
+            }
+            // otherwise passed through like other custom headers
+            HEADER_LOWER_X_PRIORITY
+                if self.configuration.emit_x_priority
+                    || !self.configuration.allow_custom_headers =>
+            {
+                match Priority::from_x_priority(header_value) {
+                    Some(priority) => self.compose_details.priority = Some(priority),
+                    None => self
+                        .compose_details
+                        .custom_headers
+                        .push(CustomHeader::new(header_name, folded_value)),
+                }
             }
             HEADER_LOWER_DELIVERY_FORMAT => {
                 if let Some(delivery_format) = Self::parse_optional_header::<DeliveryFormat>(
@@ -350,6 +1237,9 @@ impl Compose {
                     self.compose_details.delivery_format = Some(Some(delivery_format));
                 }
             }
+            HEADER_LOWER_PLAIN_TEXT => {
+                self.compose_details.is_plain_text = bool::from_str(header_value)?;
+            }
             HEADER_LOWER_ATTACH_VCARD => {
                 if let Some(attach_vcard) =
                     Self::parse_optional_header::<bool>(HEADER_ATTACH_VCARD, header_value)?
@@ -358,11 +1248,19 @@ impl Compose {
                 }
             }
             HEADER_LOWER_DELIVERY_STATUS_NOTIFICATION | HEADER_LOWER_DSN => {
-                self.compose_details.delivery_status_notification =
-                    Some(bool::from_str(header_value)?);
+                if let Some(delivery_status_notification) =
+                    Self::parse_optional_header::<bool>(HEADER_DSN, header_value)?
+                {
+                    self.compose_details.delivery_status_notification =
+                        Some(delivery_status_notification);
+                }
             }
             HEADER_LOWER_RETURN_RECEIPT => {
-                self.compose_details.return_receipt = Some(bool::from_str(header_value)?);
+                if let Some(return_receipt) =
+                    Self::parse_optional_header::<bool>(HEADER_RETURN_RECEIPT, header_value)?
+                {
+                    self.compose_details.return_receipt = Some(return_receipt);
+                }
             }
             HEADER_LOWER_ALLOW_X_HEADERS | HEADER_LOWER_ALLOW_CUSTOM_HEADERS => {
                 self.configuration.allow_custom_headers = bool::from_str(header_value)?;
@@ -370,10 +1268,77 @@ impl Compose {
             HEADER_LOWER_X_HEADER | HEADER_LOWER_CUSTOM_HEADER => {
                 self.compose_details
                     .custom_headers
-                    .push(Self::parse_custom_header(header_value)?);
+                    .push(Self::parse_custom_header(folded_value)?);
             }
             HEADER_LOWER_SEND_ON_EXIT => self.configuration.send_on_exit = header_value == "true",
-            HEADER_LOWER_HELP => {}
+            HEADER_LOWER_BCC_SELF => {
+                if let Some(bcc_self) =
+                    Self::parse_optional_header::<bool>(HEADER_BCC_SELF, header_value)?
+                {
+                    self.configuration.bcc_self = bcc_self;
+                }
+            }
+            HEADER_LOWER_ATTACH_FILE => {
+                let (disposition, path) = match header_value.split_once(';') {
+                    Some((prefix, path)) if prefix.chars().all(|c| c.is_ascii_alphabetic()) => {
+                        (prefix.parse::<ContentDisposition>(), path.trim())
+                    }
+                    _ => (Ok(ContentDisposition::Attachment), header_value),
+                };
+                let Ok(disposition) = disposition else {
+                    self.warnings.push(Warning {
+                        title: "Invalid attachment disposition".to_owned(),
+                        message: format!(
+                            "ExtEditorR expected inline or attachment in {HEADER_ATTACH_FILE}: {header_value}"
+                        ),
+                    });
+                    return Ok(());
+                };
+                // relative to wherever Thunderbird was started otherwise
+                let path = util::expand_path(path, |name| env::var(name).ok());
+                if !path.is_absolute() {
+                    self.warnings.push(Warning {
+                        title: "Attachment path not absolute".to_owned(),
+                        message: format!(
+                            "ExtEditorR expected an absolute path in {HEADER_ATTACH_FILE}: {header_value}"
+                        ),
+                    });
+                } else if path.is_file() {
+                    self.attach_files.push(AttachFile {
+                        path: path.to_string_lossy().into_owned(),
+                        disposition,
+                    });
+                } else {
+                    let path = path.to_string_lossy();
+                    self.warnings.push(Warning {
+                        title: "Attachment not found".to_owned(),
+                        message: format!("ExtEditorR could not find file {path}"),
+                    });
+                }
+            }
+            HEADER_LOWER_CHARSET => match encoding_rs::Encoding::for_label(header_value.as_bytes())
+            {
+                Some(encoding) => self.configuration.charset = encoding.name().to_owned(),
+                None => self.warnings.push(Warning {
+                    title: "Unknown charset".to_owned(),
+                    message: format!(
+                        "ExtEditorR did not recognise {HEADER_CHARSET} value: {header_value}"
+                    ),
+                }),
+            },
+            HEADER_LOWER_SUBJECT_PREFIX => {
+                self.configuration.subject_prefix = header_value.to_owned()
+            }
+            HEADER_LOWER_SEND_LATER => {
+                let send_later = DateTime::parse_from_rfc3339(header_value).map_err(|_| {
+                    anyhow!("ExtEditorR failed to parse {HEADER_SEND_LATER} value: {header_value}")
+                })?;
+                self.configuration.send_later = Some(send_later);
+            }
+            HEADER_LOWER_DISCARD => self.configuration.discard = bool::from_str(header_value)?,
+            HEADER_LOWER_RESET => self.configuration.reset = bool::from_str(header_value)?,
+            HEADER_LOWER_PREVIEW => self.configuration.preview = bool::from_str(header_value)?,
+            HEADER_LOWER_HELP | HEADER_LOWER_WARNING => {}
             HEADER_LOWER_META => {
                 let compact_headers: Vec<_> = header_value.split(',').map(str::trim).collect();
                 for compact_header in compact_headers {
@@ -386,14 +1351,38 @@ impl Compose {
                             unknown_headers,
                         )?;
                     } else {
-                        eprintln!("ExtEditorR failed to process header {compact_header}");
+                        log_warn!("ExtEditorR failed to process header {compact_header}");
                     }
                 }
             }
+            _ if self.configuration.allow_threading_headers
+                && is_threading_header(&header_name_lower) =>
+            {
+                let header_name = if header_name_lower == "in-reply-to" {
+                    HEADER_IN_REPLY_TO
+                } else {
+                    HEADER_REFERENCES
+                };
+                self.compose_details
+                    .custom_headers
+                    .push(CustomHeader::new(header_name, folded_value));
+            }
+            _ if self.configuration.allow_sender_header
+                && header_name_lower.eq_ignore_ascii_case(HEADER_SENDER) =>
+            {
+                self.compose_details
+                    .custom_headers
+                    .push(CustomHeader::new(HEADER_SENDER, folded_value));
+            }
+            _ if self.configuration.is_permitted_header(&header_name_lower) => {
+                self.compose_details
+                    .custom_headers
+                    .push(CustomHeader::new(header_name, folded_value));
+            }
             _ if header_name_lower.starts_with(HEADER_LOWER_ESCAPED_META) => {
                 self.compose_details.custom_headers.push(CustomHeader::new(
                     &header_name[HEADER_META.len() + 1..],
-                    header_value,
+                    folded_value,
                 ));
             }
             _ if header_name_lower.starts_with("x-")
@@ -402,7 +1391,7 @@ impl Compose {
                 // Thunderbird throws error if header name doesn't start with X-
                 self.compose_details
                     .custom_headers
-                    .push(CustomHeader::new(header_name, header_value));
+                    .push(CustomHeader::new(header_name, folded_value));
             }
             _ => {
                 unknown_headers.push(header_name.to_owned());
@@ -412,7 +1401,193 @@ impl Compose {
         Ok(())
     }
 
+    /// Hidden headers are not in the temporary file, so their original values are kept unless the
+    /// user added them back
+    fn restore_hidden_headers(
+        &mut self,
+        original_compose_details: &ComposeDetails,
+        original_configuration: &Configuration,
+    ) {
+        let configuration = &original_configuration;
+        let details = &mut self.compose_details;
+        for (name, list, original_list) in [
+            ("To", &mut details.to, &original_compose_details.to),
+            ("Cc", &mut details.cc, &original_compose_details.cc),
+            ("Bcc", &mut details.bcc, &original_compose_details.bcc),
+            (
+                "Reply-To",
+                &mut details.reply_to,
+                &original_compose_details.reply_to,
+            ),
+        ] {
+            if configuration.is_header_hidden(name) && list.is_empty() {
+                *list = original_list.clone();
+            }
+        }
+        if configuration.is_header_hidden(HEADER_SEND_ON_EXIT) && !self.configuration.send_on_exit {
+            self.configuration.send_on_exit = configuration.send_on_exit;
+        }
+        if configuration.is_header_hidden(HEADER_SEND_LATER)
+            && self.configuration.send_later.is_none()
+        {
+            self.configuration.send_later = configuration.send_later;
+        }
+        if configuration.is_header_hidden(HEADER_SUBJECT_PREFIX)
+            && self.configuration.subject_prefix.is_empty()
+        {
+            self.configuration
+                .subject_prefix
+                .clone_from(&configuration.subject_prefix);
+        }
+        if configuration.is_header_hidden(HEADER_CHARSET) && self.configuration.charset.is_empty() {
+            self.configuration
+                .charset
+                .clone_from(&configuration.charset);
+        }
+    }
+
+    fn is_body_separator(&self, line: &str) -> bool {
+        let body_separator = self.configuration.body_separator.trim();
+        (!body_separator.is_empty() && line.trim() == body_separator) || is_legacy_separator(line)
+    }
+
+    fn add_attachment_size_notice(&mut self) {
+        let threshold = self.configuration.attachment_size_notice;
+        let attachments = &self.compose_details.attachments;
+        let total_size: u64 = attachments
+            .iter()
+            .map(|attachment| attachment.size.max(0) as u64)
+            .sum();
+        if threshold > 0 && total_size > threshold {
+            self.warnings.push(Warning {
+                title: "Large attachments".to_owned(),
+                message: format!(
+                    "ExtEditorR found {} attachment(s) of {:.1} MiB in total",
+                    attachments.len(),
+                    total_size as f64 / (1024.0 * 1024.0)
+                ),
+            });
+        }
+    }
+
+    /// Whether the header line would be longer than `max_header_length`, in which case it is
+    /// neither written to nor read from the temporary file
+    fn is_header_too_long(&self, name: &str, value: &str) -> bool {
+        // i.e. `Name: value`
+        name.len() + 2 + value.len() > self.configuration.max_header_length
+    }
+
+    fn is_custom_header_too_long(&self, custom_header: &CustomHeader) -> bool {
+        if custom_header
+            .name
+            .to_lowercase()
+            .starts_with(HEADER_LOWER_META)
+        {
+            // escaped with another X-ExtEditorR- prefix
+            let name = format!("{HEADER_META}-{}", custom_header.name);
+            self.is_header_too_long(&name, &custom_header.value)
+        } else {
+            self.is_header_too_long(&custom_header.name, &custom_header.value)
+        }
+    }
+
+    /// Restore the over-long headers that were left out of the temporary file by
+    /// [`Self::to_eml`], so that they are not lost
+    fn keep_long_headers(&mut self, original_compose_details: &ComposeDetails) {
+        let mut kept = Vec::new();
+        if self.is_header_too_long("Subject", &original_compose_details.subject) {
+            kept.push("Subject".to_owned());
+        }
+        for custom_header in &original_compose_details.custom_headers {
+            if self.is_custom_header_too_long(custom_header) {
+                kept.push(custom_header.name.clone());
+                self.compose_details
+                    .custom_headers
+                    .push(custom_header.clone());
+            }
+        }
+        if !kept.is_empty() {
+            self.warnings.push(Warning {
+                title: "Header too long".to_owned(),
+                message: format!(
+                    "ExtEditorR left out and kept the following headers longer than {} bytes: {}",
+                    self.configuration.max_header_length,
+                    kept.join(", ")
+                ),
+            });
+        }
+    }
+
+    fn add_empty_subject_notice(&mut self) {
+        if self.configuration.warn_empty_subject && self.compose_details.subject.trim().is_empty() {
+            self.warnings.push(Warning {
+                title: "Empty subject".to_owned(),
+                message: "ExtEditorR found an empty Subject header".to_owned(),
+            });
+        }
+    }
+
+    fn add_delivery_format_notice(&mut self, body: &str) {
+        let message = match self.compose_details.delivery_format {
+            Some(Some(DeliveryFormat::PlainText)) if util::html_text::contains_markup(body) => {
+                "ExtEditorR found HTML tags in the body, which will be sent as they are in plain text"
+            }
+            Some(Some(DeliveryFormat::Html)) if self.compose_details.is_plain_text => {
+                "ExtEditorR found HTML delivery format for a message composed in plain text"
+            }
+            _ => return,
+        };
+        self.warnings.push(Warning {
+            title: "Delivery format mismatch".to_owned(),
+            message: message.to_owned(),
+        });
+    }
+
+    fn uses_raw_body(&self) -> bool {
+        !self.compose_details.is_plain_text && self.configuration.raw_body
+    }
+
+    fn uses_quote_prefix(&self) -> bool {
+        self.compose_details.is_plain_text
+            && !self.configuration.quote_prefix.is_empty()
+            && !self.configuration.preserve_quote_prefixes
+    }
+
+    fn apply_subject_prefix(&mut self) {
+        let prefix = self.configuration.subject_prefix.trim();
+        let subject = &self.compose_details.subject;
+        if prefix.is_empty() || subject.trim_start().starts_with(prefix) {
+            return;
+        }
+        self.compose_details.subject = if subject.is_empty() {
+            prefix.to_owned()
+        } else {
+            format!("{prefix} {subject}")
+        };
+    }
+
+    fn merge_duplicate_custom_headers(&mut self) {
+        let mut merged_custom_headers: Vec<CustomHeader> = Vec::new();
+        for custom_header in self.compose_details.custom_headers.drain(..) {
+            let existing = merged_custom_headers
+                .iter_mut()
+                .find(|merged| merged.name.eq_ignore_ascii_case(&custom_header.name));
+            match (existing, self.configuration.merge_duplicate_custom_headers) {
+                (Some(existing), CustomHeaderMergeStrategy::Join) => {
+                    existing.value += ", ";
+                    existing.value += &custom_header.value;
+                }
+                (Some(existing), CustomHeaderMergeStrategy::KeepLast) => {
+                    existing.value = custom_header.value;
+                }
+                _ => merged_custom_headers.push(custom_header),
+            }
+        }
+        self.compose_details.custom_headers = merged_custom_headers;
+    }
+
     fn compose_recipient_list_to_eml<W>(
+        &self,
         w: &mut W,
         name: &str,
         list: &ComposeRecipientList,
@@ -420,28 +1595,82 @@ impl Compose {
     where
         W: io::Write,
     {
-        match list {
-            ComposeRecipientList::Single(recipient) => {
-                writeln_crlf!(w, "{}: {}", name, recipient.to_header_value()?)?;
-            }
-            ComposeRecipientList::Multiple(recipients) if recipients.is_empty() => {
-                writeln_crlf!(w, "{}: ", name)?;
-            }
-            ComposeRecipientList::Multiple(recipients) => {
-                for recipient in recipients {
-                    writeln_crlf!(w, "{}: {}", name, recipient.to_header_value()?)?;
-                }
-            }
+        if self.configuration.is_header_hidden(name) {
+            return Ok(());
+        }
+        let mut recipients: Vec<_> = match list {
+            ComposeRecipientList::Single(recipient) => vec![recipient],
+            ComposeRecipientList::Multiple(recipients) => recipients.iter().collect(),
+        }
+        .into_iter()
+        .filter(|recipient| !recipient.is_empty())
+        .collect();
+        if self.configuration.sort_recipients {
+            recipients.sort_by_cached_key(|recipient| recipient.sort_key());
+        }
+        if recipients.is_empty() {
+            // placeholder
+            writeln_crlf!(w, "{}: ", name)?;
+        } else if self.configuration.collapse_recipients {
+            let values = recipients
+                .iter()
+                .map(|recipient| Ok(single_line(&recipient.to_header_value()?)))
+                .collect::<Result<Vec<_>>>()?;
+            writeln_crlf!(w, "{}: {}", name, values.join(", "))?;
+            return Ok(());
+        }
+        for recipient in recipients {
+            writeln_crlf!(
+                w,
+                "{}: {}",
+                name,
+                single_line(&recipient.to_header_value()?)
+            )?;
         }
         Ok(())
     }
 
-    fn write_help_headers<W>(w: &mut W) -> Result<()>
+    /// A recipient header holds one recipient, or a comma-separated list of them if recipients
+    /// are collapsed
+    fn parse_recipients(&self, header_value: &str) -> Result<Vec<ComposeRecipient>> {
+        if !self.configuration.collapse_recipients {
+            return Ok(vec![ComposeRecipient::from_header_value(header_value)?]);
+        }
+        split_recipients(header_value)
+            .into_iter()
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(ComposeRecipient::from_header_value)
+            .collect()
+    }
+
+    fn write_fence<W>(&self, w: &mut W, fence: &str) -> Result<()>
     where
         W: io::Write,
     {
-        for line in HEADER_HELP_LINES {
-            writeln_crlf!(w, "{}: {}", HEADER_HELP, line)?;
+        if self.configuration.fence_control_headers {
+            writeln_crlf!(w, "{}", fence)?;
+        }
+        Ok(())
+    }
+
+    fn write_help_headers<W>(&self, w: &mut W) -> Result<()>
+    where
+        W: io::Write,
+    {
+        if self.configuration.help_lines.is_empty() {
+            for line in HEADER_HELP_LINES {
+                writeln_crlf!(w, "{}: {}", HEADER_HELP, line)?;
+            }
+        } else {
+            for line in &self.configuration.help_lines {
+                if line.contains(['\r', '\n']) {
+                    return Err(anyhow!(
+                        "ExtEditorR found line break in custom help line: {line:?}"
+                    ));
+                }
+                writeln_crlf!(w, "{}: {}", HEADER_HELP, line)?;
+            }
         }
         Ok(())
     }
@@ -487,10 +1716,233 @@ pub struct Warning {
     pub message: String,
 }
 
+/// Headers with the 1-based line numbers they start at, for error messages
+type NumberedHeaders = Vec<(usize, String)>;
+
+/// A line in the header region that does not look like a header, which usually means that the
+/// blank line between headers and body has been removed. `line` is empty if the end of file was
+/// reached instead.
+#[derive(Debug)]
+pub struct MissingSeparatorError {
+    pub line: String,
+}
+
+impl std::fmt::Display for MissingSeparatorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.line.is_empty() {
+            return write!(
+                f,
+                "ExtEditorR reached end of file before the blank line between headers and body"
+            );
+        }
+        write!(
+            f,
+            "ExtEditorR expected a header but found `{}`. Please make sure there is a blank line between headers and body",
+            self.line
+        )
+    }
+}
+
+impl StdError for MissingSeparatorError {}
+
+// https://datatracker.ietf.org/doc/html/rfc5322#section-2.2
+fn is_header_name(name: &str) -> bool {
+    let name = name.trim_end();
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_graphic() && c != ':')
+}
+
+/// Replace line breaks in values of single-line headers with spaces, so that e.g. a crafted
+/// subject can't introduce other headers
+fn single_line(value: &str) -> String {
+    value
+        .split(['\r', '\n'])
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Turn embedded newlines into folded header lines, see RFC 5322 section 2.2.3
+fn fold_header_value(value: &str) -> String {
+    value.replace("\r\n", "\n").replace('\n', "\r\n ")
+}
+
+fn is_threading_header(name: &str) -> bool {
+    name.eq_ignore_ascii_case(HEADER_IN_REPLY_TO) || name.eq_ignore_ascii_case(HEADER_REFERENCES)
+}
+
+/// Whether `name` is in `names`, where the `X-ExtEditorR-` prefix is optional
+fn contains_header_name(names: &[String], name: &str) -> bool {
+    let name = name.trim();
+    let short_name = name
+        .get(..HEADER_META.len() + 1)
+        .filter(|prefix| prefix.eq_ignore_ascii_case(&format!("{HEADER_META}-")))
+        .map(|_| &name[HEADER_META.len() + 1..]);
+    names.iter().any(|entry| {
+        let entry = entry.trim();
+        entry.eq_ignore_ascii_case(name)
+            || short_name.is_some_and(|short_name| entry.eq_ignore_ascii_case(short_name))
+    })
+}
+
+/// `X-Mozilla-*` headers are used by Thunderbird internally, e.g. in drafts
+fn is_mozilla_header(name: &str) -> bool {
+    name.trim()
+        .get(..HEADER_LOWER_MOZILLA_PREFIX.len())
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case(HEADER_LOWER_MOZILLA_PREFIX))
+}
+
+fn is_quote_line(line: &str) -> bool {
+    line.starts_with('>')
+}
+
+/// Concatenate two bodies, with a line break in between if the first one doesn't end with one
+fn join_bodies(first: &str, second: &str) -> String {
+    if first.is_empty() || second.is_empty() || first.ends_with('\n') {
+        format!("{first}{second}")
+    } else {
+        format!("{first}\r\n{second}")
+    }
+}
+
+/// Whether a line is in the `Name: value` form, where the name is one of the headers written by
+/// [`Compose::to_eml`] apart from custom ones. Unlike RFC 5322, whitespace is required after the
+/// colon so that e.g. URLs are not mistaken for headers.
+/// Whether a line is a header at all, known or not
+fn looks_like_header(line: &str) -> bool {
+    line.trim()
+        .split_once(':')
+        .is_some_and(|(name, _)| is_header_name(name))
+}
+
+/// Whether the following indented lines continue a header, i.e. it's one of the headers written
+/// with [`fold_header_value`]. Other headers are single-line, so an indented line after them is
+/// more likely an indented header.
+fn allows_folding(header: &str) -> bool {
+    let Some((name, _)) = header.split_once(':') else {
+        return false;
+    };
+    let name = name.trim().to_lowercase();
+    match name.as_str() {
+        HEADER_LOWER_WARNING | HEADER_LOWER_CUSTOM_HEADER | HEADER_LOWER_X_HEADER => true,
+        "from" | "to" | "cc" | "bcc" | "reply-to" | "subject" | HEADER_LOWER_X_PRIORITY => false,
+        _ => name.starts_with(HEADER_LOWER_ESCAPED_META) || !name.starts_with(HEADER_LOWER_META),
+    }
+}
+
+fn looks_like_known_header(line: &str) -> bool {
+    line.split_once(':').is_some_and(|(name, value)| {
+        let name = name.to_lowercase();
+        let is_known = matches!(
+            name.as_str(),
+            "from" | "to" | "cc" | "bcc" | "reply-to" | "subject" | HEADER_LOWER_X_PRIORITY
+        ) || name.starts_with(HEADER_LOWER_META);
+        is_known && (value.is_empty() || value.starts_with(|c: char| c.is_ascii_whitespace()))
+    })
+}
+
+/// Split a header value on commas, except for the ones in quoted display names, e.g. `"Doe, John"
+/// <john@example.com>`, addresses, comments and contact or mailing list JSON
+fn split_recipients(value: &str) -> Vec<&str> {
+    let mut recipients = Vec::new();
+    let mut depth = 0usize;
+    let mut in_quotes = false;
+    let mut escaped = false;
+    let mut start = 0;
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            _ if in_quotes => {}
+            '<' | '(' | '{' => depth += 1,
+            '>' | ')' | '}' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                recipients.push(&value[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    recipients.push(&value[start..]);
+    recipients
+}
+
+/// Whether a line is the separator of the legacy ExternalEditor add-on, i.e.
+/// `-=-=-=-=-=-=-=-=-=# Don't remove this line #=-=-=-=-=-=-=-=-=-`, which is accepted for
+/// migration. Spacing, case and punctuation within the marker are ignored.
+fn is_legacy_separator(line: &str) -> bool {
+    let is_decoration = |s: &str| !s.is_empty() && s.chars().all(|c| c == '-' || c == '=');
+    let Some((prefix, rest)) = line.trim().split_once('#') else {
+        return false;
+    };
+    let Some((marker, suffix)) = rest.rsplit_once('#') else {
+        return false;
+    };
+    let marker: String = marker
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    is_decoration(prefix) && is_decoration(suffix) && marker == "dontremovethisline"
+}
+
+/// Read lines into `buf` until a non-blank one, which is returned. Returns `None` at the end of
+/// file. `line_number` is incremented for each line read.
+fn read_next_non_blank_line<R>(
+    r: &mut R,
+    buf: &mut Vec<u8>,
+    line_number: &mut usize,
+) -> Option<String>
+where
+    R: io::BufRead,
+{
+    loop {
+        let start = buf.len();
+        match r.read_until(b'\n', buf) {
+            Ok(0) | Err(_) => return None,
+            Ok(_) => *line_number += 1,
+        }
+        let line = String::from_utf8_lossy(&buf[start..]);
+        if !util::is_blank(&line) {
+            return Some(line.into_owned());
+        }
+    }
+}
+
+fn is_fence(line: &str) -> bool {
+    let line = line.trim();
+    line == FENCE_OPEN || line == FENCE_CLOSE
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_max_header_length() -> usize {
+    DEFAULT_MAX_HEADER_LENGTH
+}
+
+fn default_read_retries() -> u32 {
+    DEFAULT_READ_RETRIES
+}
+
+fn default_attachment_size_notice() -> u64 {
+    DEFAULT_ATTACHMENT_SIZE_NOTICE
+}
+
+fn default_max_chunks() -> usize {
+    DEFAULT_MAX_CHUNKS
+}
+
+fn default_persistent_editor_timeout() -> u64 {
+    DEFAULT_PERSISTENT_EDITOR_TIMEOUT
+}
+
 #[cfg(test)]
 pub mod tests {
     use base64::Engine;
     use regex::Regex;
+    use std::{env, fs};
 
     use super::*;
     use crate::model::thunderbird::tests::get_blank_compose_details;
@@ -559,6 +2011,37 @@ pub mod tests {
         assert_contains!(output, "Subject: ");
     }
 
+    #[test]
+    fn single_empty_recipient_placeholder_test() {
+        let mut request = get_blank_compose();
+        request.compose_details.to =
+            ComposeRecipientList::Single(ComposeRecipient::Email("".to_owned()));
+        request.compose_details.reply_to =
+            ComposeRecipientList::Single(ComposeRecipient::Email(" ".to_owned()));
+        request.compose_details.cc = ComposeRecipientList::Multiple(vec![
+            ComposeRecipient::Email("".to_owned()),
+            ComposeRecipient::Email("foo@example.com".to_owned()),
+        ]);
+
+        let output = to_eml_and_assert(&request);
+        let lines: Vec<_> = output.lines().collect();
+        for name in ["To", "Cc", "Bcc", "Reply-To"] {
+            let prefix = format!("{name}:");
+            assert_eq!(
+                1,
+                lines
+                    .iter()
+                    .filter(|line| line.starts_with(&prefix))
+                    .count(),
+                "expected exactly one {name} line in output:\n{output}"
+            );
+        }
+        assert!(lines.contains(&"To: "));
+        assert!(lines.contains(&"Reply-To: "));
+        assert!(lines.contains(&"Cc: foo@example.com"));
+        assert!(lines.contains(&"Bcc: "));
+    }
+
     #[test]
     fn omit_header_placeholder_when_given_test() {
         let mut request = get_blank_compose();
@@ -589,6 +2072,121 @@ pub mod tests {
         );
     }
 
+    #[test]
+    fn header_error_line_number_test() {
+        let eml = "Subject: Hello\r\n\r\nTo: foo@example.com\r\nX-ExtEditorR-Priority: urgent\r\n\r\nThis is a test.\r\n";
+        let mut request = get_blank_compose();
+        // blank lines are counted as well
+        request.configuration.blank_lines_in_headers = true;
+        let err = request
+            .merge_from_eml(&mut eml.as_bytes(), 512)
+            .unwrap_err();
+        assert_eq!(
+            "ExtEditorR failed to parse X-ExtEditorR-Priority value: urgent (line 4: `X-ExtEditorR-Priority: urgent`)",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn missing_separator_test() {
+        let mut eml = "Subject: Hello, world!\r\nThis is a test.\r\n".as_bytes();
+        let mut request = get_blank_compose();
+        let result = request.merge_from_eml(&mut eml, 512);
+        let err = result.unwrap_err();
+        let err = err.downcast_ref::<MissingSeparatorError>().unwrap();
+        assert_eq!("This is a test.", err.line);
+
+        let mut eml = "Subject: Hello, world!\r\nDear John: this is a test.\r\n".as_bytes();
+        let mut request = get_blank_compose();
+        let result = request.merge_from_eml(&mut eml, 512);
+        assert!(result
+            .unwrap_err()
+            .downcast_ref::<MissingSeparatorError>()
+            .is_some());
+    }
+
+    #[test]
+    fn unicode_blank_separator_test() {
+        for separator in ["\u{200B}", " \u{00A0}\t", "\u{FEFF}"] {
+            let eml = format!("Subject: Hello\r\n{separator}\r\nThis is a test.\r\n");
+            let mut request = get_blank_compose();
+            let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+            assert!(responses[0].warnings.is_empty());
+            assert_eq!("Hello", responses[0].compose_details.subject);
+            assert_eq!(
+                "This is a test.\r\n",
+                responses[0].compose_details.plain_text_body
+            );
+        }
+    }
+
+    #[test]
+    fn blank_line_among_headers_test() {
+        let mut request = get_blank_compose();
+        request.configuration.blank_lines_in_headers = true;
+        let eml = "Subject: Hello\r\n\r\n\r\nTo: foo@example.com\r\n\r\nThis is a test.\r\n";
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut eml.as_bytes(), 512)
+            .unwrap();
+        assert!(responses[0].warnings.is_empty());
+        assert_eq!("Hello", responses[0].compose_details.subject);
+        assert_eq!(
+            vec![ComposeRecipient::Email("foo@example.com".to_owned())],
+            responses[0].compose_details.to.as_slice()
+        );
+        assert_eq!(
+            "This is a test.\r\n",
+            responses[0].compose_details.plain_text_body
+        );
+
+        // followed by what doesn't look like a header, so it's the separator
+        for body in [
+            "\r\nThis is a test.\r\n",
+            "Dear John:this is a test.\r\n",
+            "https://example.com\r\n",
+            "  Indented: text\r\n",
+            "Note: see below\r\n\r\nThis is a test.\r\n",
+            "X-Foo: not a custom header\r\n",
+        ] {
+            let eml = format!("Subject: Hello\r\n\r\n{body}");
+            let responses = request
+                .clone()
+                .merge_from_eml(&mut eml.as_bytes(), 512)
+                .unwrap();
+            assert_eq!("Hello", responses[0].compose_details.subject);
+            assert_eq!(body, responses[0].compose_details.plain_text_body);
+        }
+
+        let eml = "Subject: Hello\r\n\r\n\r\n";
+        let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+        assert_eq!("\r\n", responses[0].compose_details.plain_text_body);
+    }
+
+    #[test]
+    fn header_like_body_test() {
+        // the first blank line is always the separator by default
+        for body in [
+            "Note: see below\r\n\r\nThis is a test.\r\n",
+            "To: everyone on the team\r\n\r\nThis is a test.\r\n",
+            "Subject: Re: Hello\r\n",
+            "\r\nX-ExtEditorR-Send-On-Exit: true\r\n",
+        ] {
+            let eml = format!("Subject: Hello\r\nTo: foo@example.com\r\n\r\n{body}");
+            let responses = get_blank_compose()
+                .merge_from_eml(&mut eml.as_bytes(), 512)
+                .unwrap();
+            assert!(responses[0].warnings.is_empty());
+            assert_eq!("Hello", responses[0].compose_details.subject);
+            assert_eq!(
+                vec![ComposeRecipient::Email("foo@example.com".to_owned())],
+                responses[0].compose_details.to.as_slice()
+            );
+            assert!(!responses[0].configuration.send_on_exit);
+            assert_eq!(body, responses[0].compose_details.plain_text_body);
+        }
+    }
+
     #[test]
     fn merge_from_and_to_test() {
         let mut eml = "From: foo@example.com\r\nTo: foo@instance.com\r\nTo: {\"id\":\"bar\",\"type\":\"mailingList\"}\r\n\r\nThis is a test.\r\n".as_bytes();
@@ -667,6 +2265,34 @@ pub mod tests {
         );
     }
 
+    #[test]
+    fn merge_with_whitespace_placeholder_test() {
+        for blank in ["\t", "\u{a0}", " \u{a0}\t", "\u{200b}", "\u{feff} "] {
+            let eml = format!(
+                "From: foo@example.com\r\nTo:{blank}\r\nCc:{blank}\r\nReply-To:{blank}\r\n\r\nThis is a test.\r\n"
+            );
+            let mut request = get_blank_compose();
+            request.compose_details.reply_to = ComposeRecipientList::Single(
+                ComposeRecipient::Email("another@example.com".to_owned()),
+            );
+            let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+            assert_eq!(1, responses.len());
+            assert!(responses[0].warnings.is_empty());
+            assert_eq!(
+                ComposeRecipientList::Multiple(vec![]),
+                responses[0].compose_details.to
+            );
+            assert_eq!(
+                ComposeRecipientList::Multiple(vec![]),
+                responses[0].compose_details.cc
+            );
+            assert_eq!(
+                ComposeRecipientList::Multiple(vec![]),
+                responses[0].compose_details.reply_to
+            );
+        }
+    }
+
     #[test]
     fn chunked_response_test() {
         let mut eml =
@@ -686,42 +2312,1199 @@ pub mod tests {
     }
 
     #[test]
-    fn merge_delivery_format_test() {
+    fn request_id_test() {
+        let eml = "From: foo@example.com\r\n\r\nHello, world! Hello, world! Hello!\r\n";
         let mut request = get_blank_compose();
-        let output = to_eml_and_assert(&request);
-        refute_contains!(output, "X-ExtEditorR-Delivery-Format:");
-
-        request.compose_details.delivery_format = Some(None);
-        let output = to_eml_and_assert(&request);
-        assert_contains!(output, "X-ExtEditorR-Delivery-Format: [auto]");
+        request.configuration.request_id = "foo".to_owned();
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut eml.as_bytes(), 13)
+            .unwrap();
+        assert_eq!(3, responses.len());
+        assert!(responses
+            .iter()
+            .all(|response| response.configuration.request_id == "foo"));
 
-        request.compose_details.delivery_format = Some(Some(DeliveryFormat::Both));
-        let output = to_eml_and_assert(&request);
-        assert_contains!(output, "X-ExtEditorR-Delivery-Format: [both]");
+        // generated if missing
+        request.configuration.request_id.clear();
+        let responses = request.merge_from_eml(&mut eml.as_bytes(), 13).unwrap();
+        assert!(!responses[0].configuration.request_id.is_empty());
+        assert!(responses
+            .iter()
+            .all(|response| response.configuration.request_id
+                == responses[0].configuration.request_id));
+    }
 
-        let mut eml = "X-ExtEditorR-Delivery-Format: [hello]\r\n\r\nThis is a test.\r\n".as_bytes();
+    #[test]
+    fn normalize_line_endings_test() {
+        let mut request = get_blank_compose();
+        let mut eml = "From: foo@example.com\n\r\nOne\rTwo\nThree\r\n\r\rFour\n\n".as_bytes();
         let responses = request.merge_from_eml(&mut eml, 512).unwrap();
-        assert_eq!(1, responses.len());
         assert_eq!(
-            &DeliveryFormat::Both,
-            responses[0]
-                .compose_details
-                .delivery_format
-                .as_ref()
-                .unwrap()
-                .as_ref()
-                .unwrap()
+            "One\r\nTwo\r\nThree\r\n\r\n\r\nFour\r\n\r\n",
+            responses[0].compose_details.plain_text_body
         );
+    }
 
-        request.compose_details.delivery_format = None;
-        let mut eml =
-            "X-ExtEditorR-Delivery-Format: plaintext\r\n\r\nThis is a test.\r\n".as_bytes();
-        let responses = request.merge_from_eml(&mut eml, 512).unwrap();
-        assert_eq!(1, responses.len());
+    #[test]
+    fn raw_body_test() {
+        let mut request = get_blank_compose();
+        request.configuration.raw_body = true;
+        request.compose_details.is_plain_text = false;
+        request.compose_details.body = "<pre>One\rTwo\nThree</pre>\r\n".to_owned();
+        let output = to_eml_and_assert(&request);
+        assert!(output.ends_with("\r\n\r\n<pre>One\rTwo\nThree</pre>\r\n"));
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut output.as_bytes(), 512)
+            .unwrap();
         assert_eq!(
-            &DeliveryFormat::PlainText,
-            responses[0]
-                .compose_details
+            "<pre>One\rTwo\nThree</pre>\r\n",
+            responses[0].compose_details.body
+        );
+
+        // always normalised without the flag or in plain text
+        request.configuration.raw_body = false;
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut output.as_bytes(), 512)
+            .unwrap();
+        assert_eq!(
+            "<pre>One\r\nTwo\r\nThree</pre>\r\n",
+            responses[0].compose_details.body
+        );
+        request.configuration.raw_body = true;
+        request.compose_details.is_plain_text = true;
+        let output = output.replace(
+            "X-ExtEditorR-Plain-Text: false",
+            "X-ExtEditorR-Plain-Text: true",
+        );
+        let responses = request.merge_from_eml(&mut output.as_bytes(), 512).unwrap();
+        assert_eq!(
+            "<pre>One\r\nTwo\r\nThree</pre>\r\n",
+            responses[0].compose_details.plain_text_body
+        );
+    }
+
+    #[test]
+    fn convert_html_to_text_test() {
+        let mut request = get_blank_compose();
+        request.compose_details.is_plain_text = false;
+        request.compose_details.body =
+            "<p>Hello, <b>world</b>!</p><p>See <a href=\"https://example.com/\">this</a>.</p>\r\n"
+                .to_owned();
+        let output = to_eml_and_assert(&request);
+        assert_contains!(output, "X-ExtEditorR-Plain-Text: false\r\n");
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut output.as_bytes(), 512)
+            .unwrap();
+        assert!(!responses[0].compose_details.is_plain_text);
+
+        let output = output.replace(
+            "X-ExtEditorR-Plain-Text: false",
+            "X-ExtEditorR-Plain-Text: true",
+        );
+        // the HTML is kept as it is without the flag
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut output.as_bytes(), 512)
+            .unwrap();
+        assert!(responses[0].compose_details.is_plain_text);
+        assert_eq!(
+            request.compose_details.body,
+            responses[0].compose_details.plain_text_body
+        );
+
+        request.configuration.convert_html_to_text = true;
+        let responses = request.merge_from_eml(&mut output.as_bytes(), 512).unwrap();
+        assert!(responses[0].compose_details.is_plain_text);
+        assert_eq!(
+            "Hello, world!\r\nSee this <https://example.com/>.\r\n",
+            responses[0].compose_details.plain_text_body
+        );
+        assert!(responses[0].compose_details.body.is_empty());
+    }
+
+    #[test]
+    fn trim_trailing_newline_test() {
+        let mut request = get_blank_compose();
+        request.configuration.trim_trailing_newline = true;
+
+        let mut eml = "From: foo@example.com\r\n\r\nHello, world!\r\n\r\n".as_bytes();
+        let responses = request.clone().merge_from_eml(&mut eml, 512).unwrap();
+        assert_eq!(1, responses.len());
+        assert_eq!(
+            "Hello, world!\r\n",
+            responses[0].compose_details.plain_text_body
+        );
+
+        let mut eml = "From: foo@example.com\r\n\r\nHello, world!\n".as_bytes();
+        let responses = request.clone().merge_from_eml(&mut eml, 512).unwrap();
+        assert_eq!(1, responses.len());
+        assert_eq!(
+            "Hello, world!",
+            responses[0].compose_details.plain_text_body
+        );
+    }
+
+    #[test]
+    fn chunked_trim_trailing_newline_test() {
+        let mut request = get_blank_compose();
+        request.configuration.trim_trailing_newline = true;
+
+        let mut eml =
+            "From: foo@example.com\r\n\r\nHello, world!\r\nHello, world! Hello!\r\n".as_bytes();
+        let responses = request.clone().merge_from_eml(&mut eml, 13).unwrap();
+        assert_eq!(3, responses.len());
+        assert_eq!(
+            "Hello, world!\r",
+            responses[0].compose_details.plain_text_body
+        );
+        assert_eq!(
+            "\nHello, world!",
+            responses[1].compose_details.plain_text_body
+        );
+        assert_eq!(" Hello!", responses[2].compose_details.plain_text_body);
+
+        // the newline was the only content of the last chunk
+        let mut eml = "From: foo@example.com\r\n\r\nHello, world! \r\n".as_bytes();
+        let responses = request.merge_from_eml(&mut eml, 13).unwrap();
+        assert_eq!(1, responses.len());
+        assert_eq!(
+            "Hello, world! ",
+            responses[0].compose_details.plain_text_body
+        );
+    }
+
+    #[test]
+    fn preserve_signature_test() {
+        let mut request = get_blank_compose();
+        request.configuration.preserve_signature = true;
+        request.configuration.trim_trailing_newline = true;
+        request.compose_details.is_plain_text = true;
+        request.compose_details.plain_text_body = "Hello, world!\r\n\r\n-- \r\nFoo\r\n".to_owned();
+
+        let output = to_eml_and_assert(&request);
+        assert!(output.ends_with("\r\n\r\nHello, world!\r\n\r\n-- \r\nFoo\r\n"));
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut output.as_bytes(), 512)
+            .unwrap();
+        assert_eq!(1, responses.len());
+        assert_eq!(
+            "Hello, world!\r\n\r\n-- \r\nFoo",
+            responses[0].compose_details.plain_text_body
+        );
+
+        // trailing whitespace stripped by editor
+        let output = output.replace("-- \r\n", "--\r\n");
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut output.as_bytes(), 512)
+            .unwrap();
+        assert_eq!(
+            "Hello, world!\r\n\r\n-- \r\nFoo",
+            responses[0].compose_details.plain_text_body
+        );
+
+        request.configuration.preserve_signature = false;
+        let responses = request.merge_from_eml(&mut output.as_bytes(), 512).unwrap();
+        assert_eq!(
+            "Hello, world!\r\n\r\n--\r\nFoo",
+            responses[0].compose_details.plain_text_body
+        );
+    }
+
+    #[test]
+    fn strip_signature_test() {
+        let mut request = get_blank_compose();
+        request.configuration.strip_signature = true;
+        request.compose_details.is_plain_text = true;
+        request.compose_details.plain_text_body =
+            "Hello, world!\r\n--\r\nnot a signature\r\n\r\n-- \r\nFoo\r\n-- \r\nBar\r\n".to_owned();
+
+        let output = to_eml_and_assert(&request);
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut output.as_bytes(), 512)
+            .unwrap();
+        assert_eq!(
+            "Hello, world!\r\n--\r\nnot a signature\r\n\r\n-- \r\nFoo\r\n",
+            responses[0].compose_details.plain_text_body
+        );
+
+        // no delimiter
+        let output = output.replace("-- \r\n", "--\r\n");
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut output.as_bytes(), 512)
+            .unwrap();
+        assert_eq!(
+            "Hello, world!\r\n--\r\nnot a signature\r\n\r\n--\r\nFoo\r\n--\r\nBar\r\n",
+            responses[0].compose_details.plain_text_body
+        );
+
+        request.configuration.strip_signature = false;
+        let output = to_eml_and_assert(&request);
+        let responses = request.merge_from_eml(&mut output.as_bytes(), 512).unwrap();
+        assert_eq!(
+            "Hello, world!\r\n--\r\nnot a signature\r\n\r\n-- \r\nFoo\r\n-- \r\nBar\r\n",
+            responses[0].compose_details.plain_text_body
+        );
+    }
+
+    #[test]
+    fn bcc_self_test() {
+        let mut request = get_blank_compose();
+        request.compose_details.from = ComposeRecipient::Email("Foo <foo@example.com>".to_owned());
+        let output = to_eml_and_assert(&request);
+        assert_contains!(output, "X-ExtEditorR-Bcc-Self: [false]\r\n");
+
+        let output = output.replace("Bcc-Self: [false]", "Bcc-Self: true");
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut output.as_bytes(), 512)
+            .unwrap();
+        assert_eq!(1, responses.len());
+        assert_eq!(
+            ComposeRecipientList::Multiple(vec![ComposeRecipient::Email(
+                "Foo <foo@example.com>".to_owned()
+            )]),
+            responses[0].compose_details.bcc
+        );
+
+        // unchanged if bracketed
+        let output = output.replace("Bcc-Self: true", "Bcc-Self: [true]");
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut output.as_bytes(), 512)
+            .unwrap();
+        assert_eq!(
+            ComposeRecipientList::Multiple(vec![]),
+            responses[0].compose_details.bcc
+        );
+    }
+
+    #[test]
+    fn auto_cc_bcc_test() {
+        let mut request = get_blank_compose();
+        request.compose_details.from = ComposeRecipient::Email("foo@example.com".to_owned());
+        request.configuration.auto_cc = vec!["archive@example.com".to_owned()];
+        request.configuration.auto_bcc = vec![
+            "Audit <audit@example.com>".to_owned(),
+            "foo@example.com".to_owned(),
+        ];
+        let output = to_eml_and_assert(&request);
+        // only applied when reading
+        assert_contains!(output, "Cc: \r\n");
+
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut output.as_bytes(), 512)
+            .unwrap();
+        assert_eq!(
+            ComposeRecipientList::Multiple(vec![ComposeRecipient::Email(
+                "archive@example.com".to_owned()
+            )]),
+            responses[0].compose_details.cc
+        );
+        assert_eq!(
+            ComposeRecipientList::Multiple(vec![
+                ComposeRecipient::Email("Audit <audit@example.com>".to_owned()),
+                ComposeRecipient::Email("foo@example.com".to_owned()),
+            ]),
+            responses[0].compose_details.bcc
+        );
+
+        // not duplicated, e.g. when the file is read back again
+        let output = to_eml_and_assert(&responses[0]);
+        let responses = responses[0]
+            .clone()
+            .merge_from_eml(&mut output.as_bytes(), 512)
+            .unwrap();
+        assert_eq!(1, responses[0].compose_details.cc.as_slice().len());
+        assert_eq!(2, responses[0].compose_details.bcc.as_slice().len());
+
+        let eml = "Cc: Archive <ARCHIVE@example.com>\r\nX-ExtEditorR-Bcc-Self: true\r\n\r\n";
+        let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+        assert_eq!(
+            ComposeRecipientList::Multiple(vec![ComposeRecipient::Email(
+                "Archive <ARCHIVE@example.com>".to_owned()
+            )]),
+            responses[0].compose_details.cc
+        );
+        assert_eq!(2, responses[0].compose_details.bcc.as_slice().len());
+    }
+
+    #[test]
+    fn bcc_self_idempotency_test() {
+        let mut request = get_blank_compose();
+        request.compose_details.from = ComposeRecipient::Node(ComposeRecipientNode {
+            id: "foo".to_owned(),
+            node_type: ComposeRecipientNodeType::Contact,
+        });
+        request.compose_details.bcc =
+            ComposeRecipientList::Single(request.compose_details.from.clone());
+        let output = to_eml_and_assert(&request);
+        assert_contains!(output, "X-ExtEditorR-Bcc-Self: [true]\r\n");
+
+        let output = output.replace("Bcc-Self: [true]", "Bcc-Self: true");
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut output.as_bytes(), 512)
+            .unwrap();
+        assert_eq!(
+            ComposeRecipientList::Multiple(vec![request.compose_details.from.clone()]),
+            responses[0].compose_details.bcc
+        );
+
+        let eml = "From: Foo <FOO@example.com>\r\nBcc: foo@example.com\r\nX-ExtEditorR-Bcc-Self: true\r\n\r\n";
+        let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+        assert_eq!(
+            ComposeRecipientList::Multiple(vec![ComposeRecipient::Email(
+                "foo@example.com".to_owned()
+            )]),
+            responses[0].compose_details.bcc
+        );
+    }
+
+    #[test]
+    fn warn_no_recipients_test() {
+        let eml = "From: foo@example.com\r\nTo: \r\nX-ExtEditorR-Send-On-Exit: true\r\n\r\n";
+        let mut request = get_blank_compose();
+        request.configuration.warn_no_recipients = true;
+        request.compose_details.to =
+            ComposeRecipientList::Single(ComposeRecipient::Email("bar@example.com".to_owned()));
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut eml.as_bytes(), 512)
+            .unwrap();
+        assert_eq!(1, responses.len());
+        assert_eq!(1, responses[0].warnings.len());
+        assert_eq!("No recipients", responses[0].warnings[0].title);
+        assert!(!responses[0].configuration.send_on_exit);
+
+        let eml = eml.replace("To: ", "Bcc: bar@example.com");
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut eml.as_bytes(), 512)
+            .unwrap();
+        assert!(responses[0].warnings.is_empty());
+        assert!(responses[0].configuration.send_on_exit);
+    }
+
+    #[test]
+    fn warn_empty_subject_test() {
+        let eml = "From: foo@example.com\r\nSubject:  \r\nX-ExtEditorR-Send-On-Exit: true\r\n\r\n";
+        let mut request = get_blank_compose();
+        request.configuration.warn_empty_subject = true;
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut eml.as_bytes(), 512)
+            .unwrap();
+        assert_eq!(1, responses[0].warnings.len());
+        assert_eq!("Empty subject", responses[0].warnings[0].title);
+        // advisory by default
+        assert!(responses[0].configuration.send_on_exit);
+
+        request.configuration.empty_subject_blocks_send = true;
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut eml.as_bytes(), 512)
+            .unwrap();
+        assert_eq!(1, responses[0].warnings.len());
+        assert!(!responses[0].configuration.send_on_exit);
+
+        let eml = eml.replace("Subject:  ", "Subject: Hello");
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut eml.as_bytes(), 512)
+            .unwrap();
+        assert!(responses[0].warnings.is_empty());
+        assert!(responses[0].configuration.send_on_exit);
+    }
+
+    #[test]
+    fn warn_no_recipients_newsgroups_test() {
+        let eml = "From: foo@example.com\r\nX-ExtEditorR-Send-On-Exit: true\r\n\r\n";
+        let mut request = get_blank_compose();
+        request.configuration.warn_no_recipients = true;
+        request.compose_details.newsgroups = Newsgroups::Single("comp.lang.rust".to_owned());
+        let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+        assert_eq!(1, responses.len());
+        assert!(responses[0].warnings.is_empty());
+        assert!(responses[0].configuration.send_on_exit);
+    }
+
+    #[test]
+    fn max_chunks_test() {
+        let mut request = get_blank_compose();
+        request.configuration.max_chunks = 3;
+        let eml = format!("Subject: Hello\r\n\r\n{}", "a".repeat(30));
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut eml.as_bytes(), 10)
+            .unwrap();
+        assert_eq!(3, responses.len());
+
+        let eml = format!("Subject: Hello\r\n\r\n{}", "a".repeat(40));
+        let e = request.merge_from_eml(&mut eml.as_bytes(), 10).unwrap_err();
+        assert_contains!(e.to_string(), "body too large (40 bytes)");
+    }
+
+    #[test]
+    fn attach_file_test() {
+        let attachment = env::temp_dir().join("external_editor_revived_attach_file_test.txt");
+        fs::write(&attachment, "Hello, world!").unwrap();
+        let attachment = attachment.to_string_lossy().to_string();
+        let eml = format!(
+            "From: foo@example.com\r\nX-ExtEditorR-Attach-File: {attachment}\r\nX-ExtEditorR: Attach-File: /path/to/nowhere.txt\r\nX-ExtEditorR-Send-On-Exit: true\r\n\r\n"
+        );
+        let mut request = get_blank_compose();
+        let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+        fs::remove_file(&attachment).unwrap();
+        assert_eq!(1, responses.len());
+        assert_eq!(
+            vec![AttachFile {
+                path: attachment,
+                disposition: ContentDisposition::Attachment,
+            }],
+            responses[0].attach_files
+        );
+        assert_eq!(1, responses[0].warnings.len());
+        assert_eq!("Attachment not found", responses[0].warnings[0].title);
+        assert_contains!(responses[0].warnings[0].message, "/path/to/nowhere.txt");
+        assert!(!responses[0].configuration.send_on_exit);
+
+        let json = serde_json::to_value(&responses[0]).unwrap();
+        assert_eq!(1, json["attachFiles"].as_array().unwrap().len());
+        let json = serde_json::to_value(get_blank_compose()).unwrap();
+        assert!(json.get("attachFiles").is_none());
+    }
+
+    #[test]
+    fn attach_file_disposition_test() {
+        let attachment =
+            env::temp_dir().join("external_editor_revived_attach_file_disposition_test.png");
+        fs::write(&attachment, "Hello, world!").unwrap();
+        let attachment = attachment.to_string_lossy().to_string();
+        let eml = format!(
+            "X-ExtEditorR-Attach-File: inline;{attachment}\r\nX-ExtEditorR-Attach-File: Attachment; {attachment}\r\nX-ExtEditorR-Attach-File: embedded; {attachment}\r\n\r\n"
+        );
+        let mut request = get_blank_compose();
+        let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+        fs::remove_file(&attachment).unwrap();
+        assert_eq!(
+            vec![
+                AttachFile {
+                    path: attachment.clone(),
+                    disposition: ContentDisposition::Inline,
+                },
+                AttachFile {
+                    path: attachment.clone(),
+                    disposition: ContentDisposition::Attachment,
+                },
+            ],
+            responses[0].attach_files
+        );
+        assert_eq!(1, responses[0].warnings.len());
+        assert_eq!(
+            "Invalid attachment disposition",
+            responses[0].warnings[0].title
+        );
+        assert_contains!(responses[0].warnings[0].message, "embedded;");
+
+        let json = serde_json::to_value(&responses[0]).unwrap();
+        assert_eq!("inline", json["attachFiles"][0]["disposition"]);
+    }
+
+    #[test]
+    fn attach_file_path_test() {
+        // colons are part of the path
+        let attachment = env::temp_dir().join("external_editor_revived:attach_file_path_test.txt");
+        fs::write(&attachment, "Hello, world!").unwrap();
+        let attachment = attachment.to_string_lossy().to_string();
+        let eml = format!(
+            "X-ExtEditorR-Attach-File: {attachment}\r\nX-ExtEditorR-Attach-File: inline; {attachment}\r\nX-ExtEditorR-Attach-File: relative.txt\r\nX-ExtEditorR-Attach-File: ~/external_editor_revived_missing.txt\r\n\r\n"
+        );
+        let mut request = get_blank_compose();
+        let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+        fs::remove_file(&attachment).unwrap();
+        assert_eq!(
+            vec![
+                AttachFile {
+                    path: attachment.clone(),
+                    disposition: ContentDisposition::Attachment,
+                },
+                AttachFile {
+                    path: attachment.clone(),
+                    disposition: ContentDisposition::Inline,
+                },
+            ],
+            responses[0].attach_files
+        );
+        assert_eq!(2, responses[0].warnings.len());
+        assert_eq!(
+            "Attachment path not absolute",
+            responses[0].warnings[0].title
+        );
+        assert_contains!(responses[0].warnings[0].message, "relative.txt");
+        // ~ is expanded like in the temporary directory
+        assert_eq!("Attachment not found", responses[0].warnings[1].title);
+        refute_contains!(responses[0].warnings[1].message, "~");
+        assert_contains!(
+            responses[0].warnings[1].message,
+            "external_editor_revived_missing.txt"
+        );
+    }
+
+    #[test]
+    fn invalid_from_test() {
+        let mut request = get_blank_compose();
+        request.compose_details.from = ComposeRecipient::Email("foo@example.com".to_owned());
+        for from in ["From:", "From: \t", "From: foo"] {
+            let eml = format!("{from}\r\nX-ExtEditorR-Send-On-Exit: true\r\n\r\n");
+            let responses = request
+                .clone()
+                .merge_from_eml(&mut eml.as_bytes(), 512)
+                .unwrap();
+            assert_eq!(1, responses.len());
+            assert_eq!(1, responses[0].warnings.len());
+            assert_eq!("Invalid sender", responses[0].warnings[0].title);
+            assert!(!responses[0].configuration.send_on_exit);
+        }
+
+        // removed From header leaves it unchanged
+        let eml = "X-ExtEditorR-Send-On-Exit: true\r\n\r\n";
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut eml.as_bytes(), 512)
+            .unwrap();
+        assert!(responses[0].warnings.is_empty());
+        assert!(responses[0].configuration.send_on_exit);
+
+        request.compose_details.from = ComposeRecipient::Node(ComposeRecipientNode {
+            id: "foo".to_owned(),
+            node_type: ComposeRecipientNodeType::Contact,
+        });
+        let eml = to_eml_and_assert(&request);
+        let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+        assert!(responses[0].warnings.is_empty());
+    }
+
+    #[test]
+    fn duplicate_from_test() {
+        let mut request = get_blank_compose();
+        request.configuration.send_on_exit = true;
+        let eml = "From: foo@example.com\r\nSubject: Hello\r\nfrom: bar@example.com\r\nX-ExtEditorR-Send-On-Exit: true\r\n\r\n";
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut eml.as_bytes(), 512)
+            .unwrap();
+        assert_eq!(1, responses[0].warnings.len());
+        assert_eq!("Duplicate headers", responses[0].warnings[0].title);
+        assert_contains!(responses[0].warnings[0].message, "2 From headers");
+        assert_eq!(
+            ComposeRecipient::Email("bar@example.com".to_owned()),
+            responses[0].compose_details.from
+        );
+        assert!(!responses[0].configuration.send_on_exit);
+
+        let eml = "Subject: Hello\r\nSubject: World\r\n\r\n";
+        let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+        assert_eq!(1, responses[0].warnings.len());
+        assert_contains!(responses[0].warnings[0].message, "2 Subject headers");
+        assert_eq!("World", responses[0].compose_details.subject);
+    }
+
+    #[test]
+    fn node_from_test() {
+        let mut request = get_blank_compose();
+        request.compose_details.from = ComposeRecipient::Email("foo@example.com".to_owned());
+        let eml = "From: {\"id\": \"bar\", \"type\": \"mailingList\"}\r\nTo: {\"id\": \"baz\", \"type\": \"contact\"}\r\nX-ExtEditorR-Send-On-Exit: true\r\n\r\n";
+        let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+        assert_eq!(1, responses[0].warnings.len());
+        assert_eq!("Invalid sender", responses[0].warnings[0].title);
+        assert_eq!(
+            ComposeRecipient::Email("foo@example.com".to_owned()),
+            responses[0].compose_details.from
+        );
+        assert!(matches!(
+            responses[0].compose_details.to.as_slice(),
+            [ComposeRecipient::Node(_)]
+        ));
+        assert!(!responses[0].configuration.send_on_exit);
+    }
+
+    #[test]
+    fn body_length_test() {
+        let body = "Hello, world! Hello, world! Hello!\r\n";
+        let eml = format!("From: foo@example.com\r\n\r\n{body}");
+        let responses = get_blank_compose()
+            .merge_from_eml_in_mode(&mut eml.as_bytes(), 13, true)
+            .unwrap();
+        assert_eq!(3, responses.len());
+        for response in responses {
+            assert_eq!(Some(body.len()), response.configuration.body_length);
+            assert_eq!(3, response.configuration.total);
+        }
+
+        let responses = get_blank_compose()
+            .merge_from_eml_in_mode(&mut eml.as_bytes(), 13, false)
+            .unwrap();
+        assert_eq!(None, responses[0].configuration.body_length);
+    }
+
+    #[test]
+    fn send_later_test() {
+        let mut request = get_blank_compose();
+        let output = to_eml_and_assert(&request);
+        refute_contains!(output, "X-ExtEditorR-Send-Later");
+
+        request.configuration.send_later =
+            Some(DateTime::parse_from_rfc3339("2024-02-29T08:00:00+08:00").unwrap());
+        for meta_headers in [false, true] {
+            request.configuration.meta_headers = meta_headers;
+            let output = to_eml_and_assert(&request);
+            assert_contains!(output, "2024-02-29T08:00:00+08:00");
+            let responses = request
+                .clone()
+                .merge_from_eml(&mut output.as_bytes(), 512)
+                .unwrap();
+            assert_eq!(1, responses.len());
+            assert!(responses[0].warnings.is_empty());
+            assert_eq!(
+                request.configuration.send_later,
+                responses[0].configuration.send_later
+            );
+        }
+        let json = serde_json::to_value(&request).unwrap();
+        assert_eq!(
+            "2024-02-29T08:00:00+08:00",
+            json["configuration"]["sendLater"]
+        );
+
+        let eml = "X-ExtEditorR-Send-Later: tomorrow\r\n\r\n";
+        let error = request
+            .merge_from_eml(&mut eml.as_bytes(), 512)
+            .unwrap_err();
+        assert_eq!(
+            "ExtEditorR failed to parse X-ExtEditorR-Send-Later value: tomorrow (line 1: `X-ExtEditorR-Send-Later: tomorrow`)",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn send_later_with_send_on_exit_test() {
+        let eml = "X-ExtEditorR-Send-Later: 2024-02-29T00:00:00Z\r\nX-ExtEditorR-Send-On-Exit: true\r\n\r\n";
+        let responses = get_blank_compose()
+            .merge_from_eml(&mut eml.as_bytes(), 512)
+            .unwrap();
+        assert_eq!(1, responses.len());
+        assert_eq!(1, responses[0].warnings.len());
+        assert_eq!("Conflicting send options", responses[0].warnings[0].title);
+        assert!(!responses[0].configuration.send_on_exit);
+        assert!(responses[0].configuration.send_later.is_some());
+    }
+
+    #[test]
+    fn custom_help_lines_test() {
+        let mut request = get_blank_compose();
+        request.configuration.help_lines = vec!["One To per recipient.".to_owned()];
+        let output = to_eml_and_assert(&request);
+        assert_contains!(output, "X-ExtEditorR-Help: One To per recipient.\r\n");
+        for line in HEADER_HELP_LINES {
+            refute_contains!(output, line);
+        }
+        let responses = request.merge_from_eml(&mut output.as_bytes(), 512).unwrap();
+        assert!(responses[0].warnings.is_empty());
+
+        let mut request = get_blank_compose();
+        request.configuration.help_lines = vec!["Hello,\r\nworld!".to_owned()];
+        assert!(request.to_eml(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn subject_prefix_test() {
+        let eml = "Subject: Hello\r\nX-ExtEditorR-Subject-Prefix: [EXT]\r\n\r\n";
+        let mut request = get_blank_compose();
+        let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+        assert_eq!(1, responses.len());
+        assert_eq!("[EXT] Hello", responses[0].compose_details.subject);
+        assert_eq!("[EXT]", responses[0].configuration.subject_prefix);
+
+        let output = to_eml_and_assert(&responses[0]);
+        assert_contains!(output, "X-ExtEditorR-Subject-Prefix: [EXT]\r\n");
+    }
+
+    #[test]
+    fn subject_prefix_idempotency_test() {
+        let mut request = get_blank_compose();
+        request.compose_details.subject = "[EXT] Hello".to_owned();
+        request.configuration.subject_prefix = "[EXT]".to_owned();
+        for _ in 0..2 {
+            let output = to_eml_and_assert(&request);
+            let responses = request
+                .clone()
+                .merge_from_eml(&mut output.as_bytes(), 512)
+                .unwrap();
+            assert_eq!(1, responses.len());
+            assert_eq!("[EXT] Hello", responses[0].compose_details.subject);
+            request = responses[0].clone();
+        }
+
+        // removed prefix header
+        let eml = "Subject: Hello\r\n\r\n";
+        let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+        assert_eq!("Hello", responses[0].compose_details.subject);
+        assert!(responses[0].configuration.subject_prefix.is_empty());
+    }
+
+    #[test]
+    fn fence_control_headers_test() {
+        let mut request = get_blank_compose();
+        request.configuration.fence_control_headers = true;
+        request.compose_details.subject = "Hello".to_owned();
+        request.compose_details.plain_text_body = "Hello, world!\r\n".to_owned();
+        let output = to_eml_and_assert(&request);
+        assert_contains!(
+            output,
+            "Subject: Hello\r\n# >>> ExtEditorR\r\nX-ExtEditorR-"
+        );
+        assert_eq!(2, output.matches("# >>> ExtEditorR\r\n").count());
+        assert_eq!(2, output.matches("# <<< ExtEditorR\r\n").count());
+        assert!(output.ends_with("# <<< ExtEditorR\r\n\r\nHello, world!\r\n"));
+
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut output.as_bytes(), 512)
+            .unwrap();
+        assert_eq!(1, responses.len());
+        assert!(responses[0].warnings.is_empty());
+        assert_eq!("Hello", responses[0].compose_details.subject);
+        assert_eq!(
+            "Hello, world!\r\n",
+            responses[0].compose_details.plain_text_body
+        );
+
+        // fences are stripped even if not enabled
+        request.configuration.fence_control_headers = false;
+        let responses = request.merge_from_eml(&mut output.as_bytes(), 512).unwrap();
+        assert!(responses[0].warnings.is_empty());
+        assert_eq!("Hello", responses[0].compose_details.subject);
+    }
+
+    #[test]
+    fn sort_recipients_test() {
+        let mut request = get_blank_compose();
+        request.configuration.sort_recipients = true;
+        request.compose_details.cc = ComposeRecipientList::Multiple(vec![
+            ComposeRecipient::Email("charlie@example.com".to_owned()),
+            ComposeRecipient::Email("Alice <alice@example.com>".to_owned()),
+            ComposeRecipient::Email("bob@example.com".to_owned()),
+        ]);
+        let output = to_eml_and_assert(&request);
+        assert_contains!(
+            output,
+            "Cc: Alice <alice@example.com>\r\nCc: bob@example.com\r\nCc: charlie@example.com\r\n"
+        );
+
+        // whatever order is in the file is used
+        let eml = "Cc: charlie@example.com\r\nCc: bob@example.com\r\n\r\n";
+        let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+        let cc: Vec<_> = responses[0]
+            .compose_details
+            .cc
+            .as_slice()
+            .iter()
+            .map(|recipient| recipient.to_header_value().unwrap())
+            .collect();
+        assert_eq!(vec!["charlie@example.com", "bob@example.com"], cc);
+    }
+
+    #[test]
+    fn collapse_recipients_test() {
+        let mut request = get_blank_compose();
+        request.configuration.collapse_recipients = true;
+        request.compose_details.to = ComposeRecipientList::Multiple(vec![
+            ComposeRecipient::Email("\"Doe, John\" <john@example.com>".to_owned()),
+            ComposeRecipient::Node(ComposeRecipientNode {
+                id: "mailing-list-id".to_owned(),
+                node_type: ComposeRecipientNodeType::MailingList,
+            }),
+            ComposeRecipient::Email("bar@example.com".to_owned()),
+        ]);
+        request.compose_details.cc =
+            ComposeRecipientList::Single(ComposeRecipient::Email("foo@example.com".to_owned()));
+        let output = to_eml_and_assert(&request);
+        assert_contains!(
+            output,
+            "To: \"Doe, John\" <john@example.com>, {  \"id\": \"mailing-list-id\",  \"type\": \"mailingList\"}, bar@example.com\r\n"
+        );
+        assert_contains!(output, "Cc: foo@example.com\r\n");
+        assert_contains!(output, "Bcc: \r\n");
+
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut output.as_bytes(), 512)
+            .unwrap();
+        assert!(responses[0].warnings.is_empty());
+        assert_eq!(request.compose_details.to, responses[0].compose_details.to);
+        assert_eq!(
+            ComposeRecipientList::Multiple(vec![ComposeRecipient::Email(
+                "foo@example.com".to_owned()
+            )]),
+            responses[0].compose_details.cc
+        );
+
+        // separate headers still work, and so does a trailing comma
+        let eml = "To: foo@example.com,\r\nTo: bar@example.com\r\n\r\n";
+        let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+        assert_eq!(2, responses[0].compose_details.to.as_slice().len());
+    }
+
+    #[test]
+    fn charset_test() {
+        let mut request = get_blank_compose();
+        let output = to_eml_and_assert(&request);
+        assert_contains!(output, "X-ExtEditorR-Charset: UTF-8\r\n");
+
+        let eml = "X-ExtEditorR-Charset: latin1\r\n\r\n";
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut eml.as_bytes(), 512)
+            .unwrap();
+        assert!(responses[0].warnings.is_empty());
+        assert_eq!("windows-1252", responses[0].configuration.charset);
+        let output = to_eml_and_assert(&responses[0]);
+        assert_contains!(output, "X-ExtEditorR-Charset: windows-1252\r\n");
+
+        request.configuration.send_on_exit = true;
+        let eml = "X-ExtEditorR-Charset: klingon\r\nX-ExtEditorR-Send-On-Exit: true\r\n\r\n";
+        let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+        assert_eq!(1, responses[0].warnings.len());
+        assert_eq!("Unknown charset", responses[0].warnings[0].title);
+        assert_contains!(responses[0].warnings[0].message, "klingon");
+        assert!(responses[0].configuration.charset.is_empty());
+        assert!(!responses[0].configuration.send_on_exit);
+    }
+
+    #[test]
+    fn hidden_headers_test() {
+        let mut request = get_blank_compose();
+        request.configuration.hidden_headers = vec!["bcc".to_owned(), "Charset".to_owned()];
+        request.compose_details.bcc =
+            ComposeRecipientList::Single(ComposeRecipient::Email("secret@example.com".to_owned()));
+        let output = to_eml_and_assert(&request);
+        refute_contains!(output, "Bcc:");
+        refute_contains!(output, "secret@example.com");
+        refute_contains!(output, "X-ExtEditorR-Charset:");
+        assert_contains!(output, "Cc: ");
+
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut output.as_bytes(), 512)
+            .unwrap();
+        assert!(responses[0].warnings.is_empty());
+        assert_eq!(
+            ComposeRecipientList::Single(ComposeRecipient::Email("secret@example.com".to_owned())),
+            responses[0].compose_details.bcc
+        );
+
+        // always shown headers take precedence
+        request.configuration.always_shown_headers =
+            vec!["Bcc".to_owned(), "X-ExtEditorR-Priority".to_owned()];
+        let output = to_eml_and_assert(&request);
+        assert_contains!(output, "Bcc: secret@example.com\r\n");
+        assert_contains!(output, "X-ExtEditorR-Priority: [normal]\r\n");
+        let responses = request.merge_from_eml(&mut output.as_bytes(), 512).unwrap();
+        assert!(responses[0].warnings.is_empty());
+        assert!(responses[0].compose_details.priority.is_none());
+    }
+
+    #[test]
+    fn alternative_body_test() {
+        let mut request = get_blank_compose();
+        request.compose_details.is_plain_text = true;
+        request.compose_details.body = "<p>Hello, world!</p>".to_owned();
+        request.compose_details.plain_text_body = "Hello, world!\r\n".to_owned();
+        let eml = "Subject: Hello\r\n\r\nHello, editor! This is a longer body.\r\n";
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut eml.as_bytes(), 16)
+            .unwrap();
+        // the alternative body counts towards the length of chunks as well
+        assert_eq!(4, responses.len());
+        for response in &responses {
+            let compose_details = &response.compose_details;
+            assert!(compose_details.body.len() + compose_details.plain_text_body.len() <= 17);
+        }
+        let body: String = responses
+            .iter()
+            .map(|response| response.compose_details.body.as_str())
+            .collect();
+        assert_eq!("<p>Hello, world!</p>", body);
+        let plain_text_body: String = responses
+            .iter()
+            .map(|response| response.compose_details.plain_text_body.as_str())
+            .collect();
+        assert_eq!("Hello, editor! This is a longer body.\r\n", plain_text_body);
+
+        request.configuration.max_chunks = 3;
+        assert!(request
+            .clone()
+            .merge_from_eml(&mut eml.as_bytes(), 16)
+            .is_err());
+        request.configuration.max_chunks = DEFAULT_MAX_CHUNKS;
+
+        request.compose_details.is_plain_text = false;
+        let eml = "Subject: Hello\r\n\r\n<p>Hello, editor!</p>\r\n";
+        let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+        assert_eq!(
+            "<p>Hello, editor!</p>\r\n",
+            responses[0].compose_details.body
+        );
+        assert_eq!(
+            "Hello, world!\r\n",
+            responses[0].compose_details.plain_text_body
+        );
+    }
+
+    #[test]
+    fn clear_priority_test() {
+        let mut request = get_blank_compose();
+        request.compose_details.priority = Some(Priority::High);
+        let output = to_eml_and_assert(&request);
+        assert_contains!(output, "X-ExtEditorR-Priority: high\r\n");
+
+        let eml = output.replace(
+            "X-ExtEditorR-Priority: high",
+            "X-ExtEditorR-Priority: [high]",
+        );
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut eml.as_bytes(), 512)
+            .unwrap();
+        assert!(responses[0].warnings.is_empty());
+        assert_eq!(None, responses[0].compose_details.priority);
+
+        let eml = "X-ExtEditorR: Priority: [normal]\r\n\r\n";
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut eml.as_bytes(), 512)
+            .unwrap();
+        assert_eq!(None, responses[0].compose_details.priority);
+
+        // a removed header still leaves it unchanged
+        let eml = "Subject: Hello\r\n\r\n";
+        let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+        assert_eq!(Some(Priority::High), responses[0].compose_details.priority);
+    }
+
+    #[test]
+    fn require_body_separator_test() {
+        let mut request = get_blank_compose();
+        request.compose_details.plain_text_body = "Hello, world!\r\n".to_owned();
+        let eml = "Subject: Hello\r\nTo: foo@example.com\r\n";
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut eml.as_bytes(), 512)
+            .unwrap();
+        assert_eq!("Hello", responses[0].compose_details.subject);
+        assert_eq!("", responses[0].compose_details.plain_text_body);
+
+        request.configuration.require_body_separator = true;
+        let e = request
+            .clone()
+            .merge_from_eml(&mut eml.as_bytes(), 512)
+            .unwrap_err();
+        assert!(e.is::<MissingSeparatorError>());
+        assert_contains!(e.to_string(), "end of file");
+
+        let eml = "Subject: Hello\r\n\r\n";
+        let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+        assert_eq!("", responses[0].compose_details.plain_text_body);
+    }
+
+    #[test]
+    fn body_separator_test() {
+        let mut request = get_blank_compose();
+        request.configuration.body_separator = "#BodyStartsAfterThisLine#".to_owned();
+        request.compose_details.plain_text_body = "Hello, world!\r\n".to_owned();
+        let output = to_eml_and_assert(&request);
+        assert!(output.ends_with("\r\n#BodyStartsAfterThisLine#\r\nHello, world!\r\n"));
+
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut output.as_bytes(), 512)
+            .unwrap();
+        assert_eq!(1, responses.len());
+        assert!(responses[0].warnings.is_empty());
+        assert_eq!(
+            "Hello, world!\r\n",
+            responses[0].compose_details.plain_text_body
+        );
+
+        // a blank line still works
+        let eml = "Subject: Hello\r\n\r\n#BodyStartsAfterThisLine#\r\n";
+        let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+        assert_eq!(
+            "#BodyStartsAfterThisLine#\r\n",
+            responses[0].compose_details.plain_text_body
+        );
+    }
+
+    #[test]
+    fn legacy_separator_test() {
+        let mut request = get_blank_compose();
+        request.compose_details.plain_text_body = "Hello, world!\r\n".to_owned();
+        for separator in [
+            "-=-=-=-=-=-=-=-=-=# Don't remove this line #=-=-=-=-=-=-=-=-=-",
+            "-=-=-=-=-=#DontRemoveThisLine#=-=-=-=-=-",
+        ] {
+            let eml = format!(
+                "Subject: Hello\r\nTo: foo@example.com\r\n{separator}\r\nHello, world!\r\n"
+            );
+            let responses = request
+                .clone()
+                .merge_from_eml(&mut eml.as_bytes(), 512)
+                .unwrap();
+            assert!(responses[0].warnings.is_empty());
+            assert_eq!("Hello", responses[0].compose_details.subject);
+            assert_eq!(
+                "Hello, world!\r\n",
+                responses[0].compose_details.plain_text_body
+            );
+        }
+
+        // only between headers and body
+        let eml =
+            "Subject: Hello\r\n\r\n#DontRemoveThisLine#\r\n-=# Don't remove this line #=-\r\n";
+        let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+        assert_eq!(
+            "#DontRemoveThisLine#\r\n-=# Don't remove this line #=-\r\n",
+            responses[0].compose_details.plain_text_body
+        );
+    }
+
+    #[test]
+    fn mixed_case_headers_test() {
+        for (priority, delivery_format, attach_vcard, dsn, return_receipt, send_on_exit, allow) in [
+            (
+                "x-exteditorr-priority",
+                "x-exteditorr-delivery-format",
+                "x-exteditorr-attach-vcard",
+                "x-exteditorr-dsn",
+                "x-exteditorr-return-receipt",
+                "x-exteditorr-send-on-exit",
+                "x-exteditorr-allow-x-headers",
+            ),
+            (
+                "X-EXTEDITORR-PRIORITY",
+                "X-EXTEDITORR-DELIVERY-FORMAT",
+                "X-EXTEDITORR-ATTACH-VCARD",
+                "X-EXTEDITORR-DELIVERY-STATUS-NOTIFICATION",
+                "X-EXTEDITORR-RETURN-RECEIPT",
+                "X-EXTEDITORR-SEND-ON-EXIT",
+                "X-EXTEDITORR-ALLOW-X-HEADERS",
+            ),
+            (
+                "X-Exteditorr-priority",
+                "x-ExtEditorR-Delivery-format",
+                "X-extEditorR-Attach-VCard",
+                "X-ExtEditorR-Dsn",
+                "x-Exteditorr-Return-receipt",
+                "X-exteditorr-Send-On-Exit",
+                "X-ExtEditorR-Allow-X-headers",
+            ),
+        ] {
+            let eml = [
+                format!("{priority}: high"),
+                format!("{delivery_format}: html"),
+                format!("{attach_vcard}: true"),
+                format!("{dsn}: true"),
+                format!("{return_receipt}: true"),
+                format!("{send_on_exit}: true"),
+                format!("{allow}: true"),
+                "X-Foo: bar".to_owned(),
+                "".to_owned(),
+                "".to_owned(),
+            ]
+            .join("\r\n");
+            let mut request = get_blank_compose();
+            // or there would be a delivery format notice
+            request.compose_details.is_plain_text = false;
+            let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+            assert_eq!(1, responses.len());
+            let response = &responses[0];
+            assert!(response.warnings.is_empty());
+            assert_eq!(Some(Priority::High), response.compose_details.priority);
+            assert_eq!(
+                Some(Some(DeliveryFormat::Html)),
+                response.compose_details.delivery_format
+            );
+            assert_eq!(Some(true), response.compose_details.attach_vcard.inner);
+            assert_eq!(
+                Some(true),
+                response.compose_details.delivery_status_notification
+            );
+            assert_eq!(Some(true), response.compose_details.return_receipt);
+            assert!(response.configuration.send_on_exit);
+            assert!(response.configuration.allow_custom_headers);
+            assert_eq!(
+                vec![CustomHeader::new("X-Foo", "bar")],
+                response.compose_details.custom_headers
+            );
+        }
+
+        // compact meta headers
+        let eml = "x-exteditorr: PRIORITY: low, send-on-exit: true\r\n\r\n";
+        let responses = get_blank_compose()
+            .merge_from_eml(&mut eml.as_bytes(), 512)
+            .unwrap();
+        assert!(responses[0].warnings.is_empty());
+        assert_eq!(Some(Priority::Low), responses[0].compose_details.priority);
+        assert!(responses[0].configuration.send_on_exit);
+    }
+
+    #[test]
+    fn merge_delivery_format_test() {
+        let mut request = get_blank_compose();
+        let output = to_eml_and_assert(&request);
+        refute_contains!(output, "X-ExtEditorR-Delivery-Format:");
+
+        request.compose_details.delivery_format = Some(None);
+        let output = to_eml_and_assert(&request);
+        assert_contains!(output, "X-ExtEditorR-Delivery-Format: [auto]");
+
+        request.compose_details.delivery_format = Some(Some(DeliveryFormat::Both));
+        let output = to_eml_and_assert(&request);
+        assert_contains!(output, "X-ExtEditorR-Delivery-Format: [both]");
+
+        let mut eml = "X-ExtEditorR-Delivery-Format: [hello]\r\n\r\nThis is a test.\r\n".as_bytes();
+        let responses = request.merge_from_eml(&mut eml, 512).unwrap();
+        assert_eq!(1, responses.len());
+        assert_eq!(
+            &DeliveryFormat::Both,
+            responses[0]
+                .compose_details
+                .delivery_format
+                .as_ref()
+                .unwrap()
+                .as_ref()
+                .unwrap()
+        );
+
+        request.compose_details.delivery_format = None;
+        let mut eml =
+            "X-ExtEditorR-Delivery-Format: plaintext\r\n\r\nThis is a test.\r\n".as_bytes();
+        let responses = request.merge_from_eml(&mut eml, 512).unwrap();
+        assert_eq!(1, responses.len());
+        assert_eq!(
+            &DeliveryFormat::PlainText,
+            responses[0]
+                .compose_details
                 .delivery_format
                 .as_ref()
                 .unwrap()
@@ -730,6 +3513,22 @@ pub mod tests {
         );
     }
 
+    #[test]
+    fn null_delivery_format_test() {
+        let mut json = serde_json::to_value(get_blank_compose()).unwrap();
+        json["composeDetails"]["deliveryFormat"] = serde_json::Value::Null;
+        let mut request: Compose = serde_json::from_value(json).unwrap();
+        assert_eq!(Some(None), request.compose_details.delivery_format);
+        let output = to_eml_and_assert(&request);
+        assert_contains!(output, "X-ExtEditorR-Delivery-Format: [auto]\r\n");
+
+        // still auto if left as it is, which is not sent back
+        let responses = request.merge_from_eml(&mut output.as_bytes(), 512).unwrap();
+        assert_eq!(Some(None), responses[0].compose_details.delivery_format);
+        let json = serde_json::to_value(&responses[0]).unwrap();
+        assert!(json["composeDetails"].get("deliveryFormat").is_none());
+    }
+
     #[test]
     fn merge_priority_test() {
         let mut request = get_blank_compose();
@@ -747,6 +3546,116 @@ pub mod tests {
         );
     }
 
+    #[test]
+    fn delivery_format_notice_test() {
+        let mut request = get_blank_compose();
+        request.compose_details.is_plain_text = true;
+        request.configuration.send_on_exit = true;
+        let eml = "X-ExtEditorR-Delivery-Format: plaintext\r\nX-ExtEditorR-Send-On-Exit: true\r\n\r\n<p>Hello, <b>world</b>!</p>\r\n";
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut eml.as_bytes(), 512)
+            .unwrap();
+        assert_eq!(1, responses[0].warnings.len());
+        assert_eq!("Delivery format mismatch", responses[0].warnings[0].title);
+        // advisory only
+        assert!(responses[0].configuration.send_on_exit);
+
+        let eml = eml.replace(
+            "<p>Hello, <b>world</b>!</p>",
+            "Foo <foo@example.com> wrote:",
+        );
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut eml.as_bytes(), 512)
+            .unwrap();
+        assert!(responses[0].warnings.is_empty());
+
+        let eml = eml.replace("Format: plaintext", "Format: html");
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut eml.as_bytes(), 512)
+            .unwrap();
+        assert_eq!(1, responses[0].warnings.len());
+
+        let eml = eml.replace("Format: html", "Format: [html]");
+        let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+        assert!(responses[0].warnings.is_empty());
+    }
+
+    #[test]
+    fn x_priority_test() {
+        let mut request = get_blank_compose();
+        request.configuration.emit_x_priority = true;
+        for (priority, x_priority) in [
+            (Priority::Highest, "1 (highest)"),
+            (Priority::High, "2 (high)"),
+            (Priority::Normal, "3 (normal)"),
+            (Priority::Low, "4 (low)"),
+            (Priority::Lowest, "5 (lowest)"),
+        ] {
+            request.compose_details.priority = Some(priority.clone());
+            let output = to_eml_and_assert(&request);
+            assert_contains!(
+                output,
+                &format!("Subject: \r\nX-Priority: {x_priority}\r\n")
+            );
+            refute_contains!(output, HEADER_PRIORITY);
+
+            let eml = format!("X-Priority: {}\r\n\r\n", &x_priority[..1]);
+            let responses = request
+                .clone()
+                .merge_from_eml(&mut eml.as_bytes(), 512)
+                .unwrap();
+            assert!(responses[0].compose_details.custom_headers.is_empty());
+            assert_eq!(Some(priority), responses[0].compose_details.priority);
+        }
+
+        // friendly header by default
+        request.configuration.emit_x_priority = false;
+        let output = to_eml_and_assert(&request);
+        assert_contains!(output, "X-ExtEditorR-Priority: lowest\r\n");
+        refute_contains!(output, "X-Priority");
+        let eml = "X-Priority: 2 (High)\r\n\r\n";
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut eml.as_bytes(), 512)
+            .unwrap();
+        assert_eq!(Some(Priority::High), responses[0].compose_details.priority);
+
+        // passed through if it's not a priority
+        request.configuration.emit_x_priority = true;
+        request.configuration.allow_custom_headers = true;
+        for x_priority in ["6", "urgent"] {
+            let eml = format!("X-Priority: {x_priority}\r\n\r\n");
+            let responses = request
+                .clone()
+                .merge_from_eml(&mut eml.as_bytes(), 512)
+                .unwrap();
+            assert!(responses[0].warnings.is_empty());
+            assert_eq!(
+                Some(Priority::Lowest),
+                responses[0].compose_details.priority
+            );
+            assert_eq!(
+                vec![CustomHeader::new("X-Priority", x_priority)],
+                responses[0].compose_details.custom_headers
+            );
+        }
+        // or if it's a custom header
+        request.configuration.emit_x_priority = false;
+        let eml = "X-Priority: 1\r\n\r\n";
+        let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+        assert_eq!(
+            Some(Priority::Lowest),
+            responses[0].compose_details.priority
+        );
+        assert_eq!(
+            vec![CustomHeader::new("X-Priority", "1")],
+            responses[0].compose_details.custom_headers
+        );
+    }
+
     #[test]
     fn merge_attach_vcard_test() {
         let mut request = get_blank_compose();
@@ -893,10 +3802,321 @@ pub mod tests {
         assert_eq!(1, responses[0].warnings.len());
         assert_eq!("Unknown header(s) found", responses[0].warnings[0].title);
         assert_eq!(
-            "ExtEditorR did not recognise the following headers:\n- Foo\n- X-Bar",
-            responses[0].warnings[0].message
+            "ExtEditorR did not recognise the following headers:\n- Foo\n- X-Bar",
+            responses[0].warnings[0].message
+        );
+        assert!(!responses[0].configuration.send_on_exit);
+    }
+
+    #[test]
+    fn attachment_size_notice_test() {
+        let mut request = get_blank_compose();
+        request.configuration.send_on_exit = true;
+        request.compose_details.attachments = vec![
+            ComposeAttachment {
+                id: 1,
+                name: "foo.pdf".to_owned(),
+                size: 15 * 1024 * 1024,
+            },
+            ComposeAttachment {
+                id: 2,
+                name: "bar.zip".to_owned(),
+                size: 10 * 1024 * 1024,
+            },
+        ];
+        let eml = "Subject: Hello\r\nX-ExtEditorR-Send-On-Exit: true\r\n\r\nThis is a test.\r\n";
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut eml.as_bytes(), 512)
+            .unwrap();
+        assert_eq!(1, responses[0].warnings.len());
+        assert_eq!("Large attachments", responses[0].warnings[0].title);
+        assert_eq!(
+            "ExtEditorR found 2 attachment(s) of 25.0 MiB in total",
+            responses[0].warnings[0].message
+        );
+        assert!(responses[0].configuration.send_on_exit);
+
+        request.compose_details.attachments.pop();
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut eml.as_bytes(), 512)
+            .unwrap();
+        assert!(responses[0].warnings.is_empty());
+
+        request.configuration.attachment_size_notice = 0;
+        request.compose_details.attachments[0].size = i32::MAX;
+        let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+        assert!(responses[0].warnings.is_empty());
+    }
+
+    #[test]
+    fn default_reply_to_from_test() {
+        let mut request = get_blank_compose();
+        let eml = "From: foo@example.com\r\nReply-To: \r\nSubject: Hello\r\n\r\n";
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut eml.as_bytes(), 512)
+            .unwrap();
+        assert!(responses[0].compose_details.reply_to.is_empty());
+
+        request.configuration.default_reply_to_from = true;
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut eml.as_bytes(), 512)
+            .unwrap();
+        assert_eq!(
+            vec![ComposeRecipient::Email("foo@example.com".to_owned())],
+            responses[0].compose_details.reply_to.as_slice()
+        );
+
+        // an explicit Reply-To is kept
+        let eml = "From: foo@example.com\r\nReply-To: bar@example.com\r\n\r\n";
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut eml.as_bytes(), 512)
+            .unwrap();
+        assert_eq!(
+            vec![ComposeRecipient::Email("bar@example.com".to_owned())],
+            responses[0].compose_details.reply_to.as_slice()
+        );
+
+        // node senders are skipped
+        let mut compose_details = request.compose_details.clone();
+        compose_details.from = ComposeRecipient::Node(ComposeRecipientNode {
+            id: "foo".to_owned(),
+            node_type: ComposeRecipientNodeType::Contact,
+        });
+        compose_details.default_reply_to_from();
+        assert!(compose_details.reply_to.is_empty());
+    }
+
+    #[test]
+    fn header_injection_test() {
+        let mut request = get_blank_compose();
+        request.compose_details.subject = "Hello\r\nBcc: victim@example.com\nX-Foo: bar".to_owned();
+        request.compose_details.to = ComposeRecipientList::Single(ComposeRecipient::Email(
+            "foo@example.com\nBcc: victim@example.com".to_owned(),
+        ));
+        let output = to_eml_and_assert(&request);
+        assert_contains!(
+            output,
+            "\r\nSubject: Hello Bcc: victim@example.com X-Foo: bar\r\n"
+        );
+        assert_contains!(
+            output,
+            "\r\nTo: foo@example.com Bcc: victim@example.com\r\n"
+        );
+        assert!(!output.contains("\nBcc: victim@example.com"));
+
+        let responses = request.merge_from_eml(&mut output.as_bytes(), 512).unwrap();
+        assert!(responses[0].compose_details.bcc.is_empty());
+        assert!(responses[0].compose_details.custom_headers.is_empty());
+        assert_eq!(
+            "Hello Bcc: victim@example.com X-Foo: bar",
+            responses[0].compose_details.subject
+        );
+    }
+
+    #[test]
+    fn send_despite_warnings_test() {
+        let mut eml =
+            "Foo: hello\r\nX-ExtEditorR-Send-On-Exit: true\r\n\r\nThis is a test.\r\n".as_bytes();
+        let mut request = get_blank_compose();
+        request.configuration.send_despite_warnings = true;
+        let responses = request.merge_from_eml(&mut eml, 512).unwrap();
+        assert_eq!(1, responses[0].warnings.len());
+        assert_eq!("Unknown header(s) found", responses[0].warnings[0].title);
+        assert!(responses[0].configuration.send_on_exit);
+
+        // but never for a message that can't be delivered
+        let mut eml =
+            "From: foo\r\nTo: bar@example.com\r\nX-ExtEditorR-Send-On-Exit: true\r\n\r\nThis is a test.\r\n".as_bytes();
+        let mut request = get_blank_compose();
+        request.configuration.send_despite_warnings = true;
+        let responses = request.merge_from_eml(&mut eml, 512).unwrap();
+        assert_eq!(1, responses[0].warnings.len());
+        assert_eq!("Invalid sender", responses[0].warnings[0].title);
+        assert!(!responses[0].configuration.send_on_exit);
+
+        let mut eml = "X-ExtEditorR-Send-On-Exit: true\r\n\r\nThis is a test.\r\n".as_bytes();
+        let mut request = get_blank_compose();
+        request.configuration.send_despite_warnings = true;
+        request.configuration.warn_no_recipients = true;
+        let responses = request.merge_from_eml(&mut eml, 512).unwrap();
+        assert_eq!("No recipients", responses[0].warnings[0].title);
+        assert!(!responses[0].configuration.send_on_exit);
+    }
+
+    #[test]
+    fn max_header_length_test() {
+        let mut request = get_blank_compose();
+        request.configuration.allow_custom_headers = true;
+        request.configuration.max_header_length = 32;
+        let eml = format!(
+            "Subject: Hello\r\nX-Foo: {}\r\nX-Bar: short\r\nX-ExtEditorR-Send-On-Exit: true\r\n\r\n",
+            "a".repeat(32)
+        );
+        let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+        assert_eq!(1, responses[0].warnings.len());
+        assert_eq!("Header too long", responses[0].warnings[0].title);
+        assert_contains!(responses[0].warnings[0].message, "X-Foo");
+        assert_eq!("Hello", responses[0].compose_details.subject);
+        assert_eq!(1, responses[0].compose_details.custom_headers.len());
+        assert_eq!("X-Bar", responses[0].compose_details.custom_headers[0].name);
+        assert!(!responses[0].configuration.send_on_exit);
+
+        let json = r#"{"version": "0.0.0.0", "shell": "sh", "template": "vim"}"#;
+        let configuration: Configuration = serde_json::from_str(json).unwrap();
+        assert_eq!(DEFAULT_MAX_HEADER_LENGTH, configuration.max_header_length);
+    }
+
+    #[test]
+    fn max_header_length_to_eml_test() {
+        let mut request = get_blank_compose();
+        // long enough for help headers
+        request.configuration.max_header_length = 128;
+        request.compose_details.subject = "s".repeat(128);
+        request.compose_details.custom_headers = vec![
+            CustomHeader {
+                name: "X-Foo".to_owned(),
+                value: "a".repeat(128),
+            },
+            CustomHeader {
+                name: "X-Bar".to_owned(),
+                value: "short".to_owned(),
+            },
+        ];
+        let output = to_eml_and_assert(&request);
+        refute_contains!(output, "Subject:");
+        refute_contains!(output, "X-Foo:");
+        assert_contains!(output, "X-Bar: short\r\n");
+
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut output.as_bytes(), 512)
+            .unwrap();
+        assert_eq!(1, responses[0].warnings.len());
+        assert_eq!("Header too long", responses[0].warnings[0].title);
+        assert_contains!(responses[0].warnings[0].message, "Subject, X-Foo");
+        assert_eq!(
+            request.compose_details.subject,
+            responses[0].compose_details.subject
+        );
+        let custom_headers = &responses[0].compose_details.custom_headers;
+        assert_eq!(2, custom_headers.len());
+        assert_eq!("X-Bar", custom_headers[0].name);
+        assert_eq!(request.compose_details.custom_headers[0], custom_headers[1]);
+    }
+
+    #[test]
+    fn warnings_in_eml_test() {
+        let mut request = get_blank_compose();
+        request.configuration.warnings_in_eml = true;
+        let eml = "Foo: bar\r\nSubject: Hello\r\n\r\nThis is a test.\r\n";
+        let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+        assert_eq!(1, responses[0].warnings.len());
+
+        let output = to_eml_and_assert(&responses[0]);
+        assert!(output.starts_with(
+            "X-ExtEditorR-Warning: Unknown header(s) found: ExtEditorR did not recognise the following headers:\r\n - Foo\r\nFrom: "
+        ));
+        let responses = {
+            let mut request = responses[0].clone();
+            request.merge_from_eml(&mut output.as_bytes(), 512).unwrap()
+        };
+        assert!(responses[0].warnings.is_empty());
+        assert!(responses[0].compose_details.custom_headers.is_empty());
+        assert_eq!("Hello", responses[0].compose_details.subject);
+
+        // as sent back by the extension
+        let mut json = serde_json::to_value(get_blank_compose()).unwrap();
+        json["configuration"]["warningsInEml"] = true.into();
+        json["warnings"] = serde_json::json!([{"title": "Foo", "message": "Bar"}]);
+        let request: Compose = serde_json::from_value(json).unwrap();
+        let output = to_eml_and_assert(&request);
+        assert!(output.starts_with("X-ExtEditorR-Warning: Foo: Bar\r\nFrom: "));
+
+        let mut request = responses[0].clone();
+        request.configuration.warnings_in_eml = false;
+        request.warnings.push(Warning {
+            title: "Foo".to_owned(),
+            message: "Bar".to_owned(),
+        });
+        let output = to_eml_and_assert(&request);
+        assert!(!output.contains(HEADER_WARNING));
+    }
+
+    #[test]
+    fn append_position_test() {
+        let mut request = get_blank_compose();
+        request.compose_details.plain_text_body = "Original body\r\n".to_owned();
+        request.configuration.append_position = AppendPosition::Prepend;
+        let output = to_eml_and_assert(&request);
+        assert!(output.ends_with("\r\n\r\n"));
+
+        let eml = output.clone() + "Edited text";
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut eml.as_bytes(), 512)
+            .unwrap();
+        assert_eq!(
+            "Edited text\r\nOriginal body\r\n",
+            responses[0].compose_details.plain_text_body
+        );
+
+        request.configuration.append_position = AppendPosition::Append;
+        let eml = output.clone() + "Edited text\r\n";
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut eml.as_bytes(), 512)
+            .unwrap();
+        assert_eq!(
+            "Original body\r\nEdited text\r\n",
+            responses[0].compose_details.plain_text_body
+        );
+
+        // the combined body is chunked
+        let responses = request.merge_from_eml(&mut eml.as_bytes(), 16).unwrap();
+        assert_eq!(2, responses.len());
+        assert_eq!(
+            "Original body\r\nEdited text\r\n",
+            responses[0].compose_details.plain_text_body.clone()
+                + &responses[1].compose_details.plain_text_body
+        );
+    }
+
+    #[test]
+    fn mozilla_headers_test() {
+        let mut request = get_blank_compose();
+        request.compose_details.custom_headers.push(CustomHeader {
+            name: "X-Mozilla-Status".to_owned(),
+            value: "0001".to_owned(),
+        });
+        let output = to_eml_and_assert(&request);
+        assert_contains!(output, "X-Mozilla-Status: 0001\r\n");
+
+        let eml = output.replace(
+            "X-ExtEditorR-Allow-X-Headers: true",
+            "X-ExtEditorR-Allow-X-Headers: false",
+        );
+        let eml = eml.replacen(
+            "X-Mozilla-Status: 0001\r\n",
+            "X-Mozilla-Status: 0001\r\nx-mozilla-keys: $label1\r\n",
+            1,
+        );
+        let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+        assert!(responses[0].warnings.is_empty());
+        let custom_headers: Vec<_> = responses[0]
+            .compose_details
+            .custom_headers
+            .iter()
+            .map(|custom_header| (custom_header.name.as_str(), custom_header.value.as_str()))
+            .collect();
+        assert_eq!(
+            vec![("X-Mozilla-Status", "0001"), ("X-mozilla-keys", "$label1")],
+            custom_headers
         );
-        assert!(!responses[0].configuration.send_on_exit);
     }
 
     #[test]
@@ -1168,6 +4388,412 @@ pub mod tests {
         );
     }
 
+    #[test]
+    fn custom_headers_order_test() {
+        let mut request = get_blank_compose();
+        request.compose_details.custom_headers = vec![
+            CustomHeader::new("X-Foo", "foo"),
+            CustomHeader::new("X-ExtEditorR-Bar", "bar"),
+            CustomHeader::new("X-Baz", "baz"),
+        ];
+
+        for meta_headers in [false, true] {
+            request.configuration.meta_headers = meta_headers;
+            let output = to_eml_and_assert(&request);
+            let responses = {
+                let mut request = request.clone();
+                let mut output = output.as_bytes();
+                request.merge_from_eml(&mut output, 512).unwrap()
+            };
+            assert_eq!(1, responses.len());
+            assert!(responses[0].warnings.is_empty());
+            assert_eq!(
+                request.compose_details.custom_headers,
+                responses[0].compose_details.custom_headers
+            );
+        }
+    }
+
+    #[test]
+    fn threading_headers_test() {
+        let mut request = get_blank_compose();
+        request.compose_details.custom_headers = vec![
+            CustomHeader::new("In-Reply-To", "<foo@example.com>"),
+            CustomHeader::new("References", "<bar@example.com> <foo@example.com>"),
+        ];
+
+        request.configuration.allow_threading_headers = true;
+        let output = to_eml_and_assert(&request);
+        assert!(output.contains("\r\nIn-Reply-To: <foo@example.com>\r\n"));
+        assert!(output.contains("\r\nReferences: <bar@example.com> <foo@example.com>\r\n"));
+        let responses = {
+            let mut request = request.clone();
+            let output = output.replace("In-Reply-To:", "in-reply-to:");
+            request.merge_from_eml(&mut output.as_bytes(), 512).unwrap()
+        };
+        assert_eq!(1, responses.len());
+        assert!(responses[0].warnings.is_empty());
+        assert_eq!(
+            request.compose_details.custom_headers,
+            responses[0].compose_details.custom_headers
+        );
+
+        request.configuration.allow_threading_headers = false;
+        let output = to_eml_and_assert(&request);
+        let responses = {
+            let mut request = request.clone();
+            request.merge_from_eml(&mut output.as_bytes(), 512).unwrap()
+        };
+        assert_eq!(1, responses.len());
+        assert_eq!(1, responses[0].warnings.len());
+        assert!(responses[0].warnings[0].message.contains("- In-Reply-To"));
+        assert!(responses[0].compose_details.custom_headers.is_empty());
+    }
+
+    #[test]
+    fn sender_header_test() {
+        let mut request = get_blank_compose();
+        request.compose_details.custom_headers = vec![CustomHeader::new(
+            "Sender",
+            "Assistant <assistant@example.com>",
+        )];
+
+        request.configuration.allow_sender_header = true;
+        let output = to_eml_and_assert(&request);
+        assert_contains!(output, "\r\nSender: Assistant <assistant@example.com>\r\n");
+        let responses = {
+            let mut request = request.clone();
+            let output = output.replace("Sender:", "SENDER:");
+            request.merge_from_eml(&mut output.as_bytes(), 512).unwrap()
+        };
+        assert_eq!(1, responses.len());
+        assert!(responses[0].warnings.is_empty());
+        assert_eq!(
+            request.compose_details.custom_headers,
+            responses[0].compose_details.custom_headers
+        );
+
+        request.configuration.allow_sender_header = false;
+        let output = to_eml_and_assert(&request);
+        let responses = {
+            let mut request = request.clone();
+            request.merge_from_eml(&mut output.as_bytes(), 512).unwrap()
+        };
+        assert_eq!(1, responses.len());
+        assert_eq!(1, responses[0].warnings.len());
+        assert!(responses[0].warnings[0].message.contains("- Sender"));
+        assert!(responses[0].compose_details.custom_headers.is_empty());
+    }
+
+    #[test]
+    fn permitted_headers_test() {
+        let eml = [
+            "From: foo@example.com",
+            "Organization: Example Ltd.",
+            "User-Agent: Foo/1.0",
+            "",
+            "This is a test.",
+            "",
+        ]
+        .join("\r\n");
+
+        let mut request = get_blank_compose();
+        request.configuration.permitted_headers = vec!["organization".to_owned()];
+        let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+        assert_eq!(1, responses.len());
+        assert_eq!(
+            vec![CustomHeader::new("Organization", "Example Ltd.")],
+            responses[0].compose_details.custom_headers
+        );
+        assert_eq!(1, responses[0].warnings.len());
+        assert!(responses[0].warnings[0].message.contains("- User-Agent"));
+        assert!(!responses[0].warnings[0].message.contains("Organization"));
+    }
+
+    #[test]
+    fn comments_header_test() {
+        let mut request = get_blank_compose();
+        request.configuration.permitted_headers = vec!["Comments".to_owned()];
+        request.compose_details.custom_headers =
+            vec![CustomHeader::new("Comments", "Follow up next week")];
+        let output = to_eml_and_assert(&request);
+        assert_contains!(output, "\r\nComments: Follow up next week\r\n");
+
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut output.as_bytes(), 512)
+            .unwrap();
+        assert!(responses[0].warnings.is_empty());
+        assert_eq!(
+            request.compose_details.custom_headers,
+            responses[0].compose_details.custom_headers
+        );
+
+        request.configuration.permitted_headers.clear();
+        let responses = request.merge_from_eml(&mut output.as_bytes(), 512).unwrap();
+        assert_eq!(1, responses[0].warnings.len());
+        assert!(responses[0].warnings[0].message.contains("- Comments"));
+        assert!(responses[0].compose_details.custom_headers.is_empty());
+    }
+
+    #[test]
+    fn custom_header_case_test() {
+        let mut request = get_blank_compose();
+        request.configuration.allow_custom_headers = true;
+        request.compose_details.custom_headers = vec![
+            CustomHeader::new("X-ExtEditorR-My-CASE", "meta"),
+            CustomHeader::new("X-Exteditorr-Normalised-case", "normalised"),
+            CustomHeader::new("X-My-Weird-CASE", "foo"),
+            CustomHeader::new("X-lower-case", "bar"),
+            CustomHeader::new("X-ExtEditorR-X-mIxEd", "escaped, folded\nvalue"),
+        ];
+
+        for meta_headers in [false, true] {
+            request.configuration.meta_headers = meta_headers;
+            let output = to_eml_and_assert(&request);
+            let responses = request
+                .clone()
+                .merge_from_eml(&mut output.as_bytes(), 512)
+                .unwrap();
+            assert!(responses[0].warnings.is_empty(), "{output}");
+            let names: Vec<_> = responses[0]
+                .compose_details
+                .custom_headers
+                .iter()
+                .map(|custom_header| custom_header.name.as_str())
+                .collect();
+            assert_eq!(
+                vec![
+                    "X-ExtEditorR-My-CASE",
+                    "X-ExtEditorR-Normalised-case",
+                    "X-My-Weird-CASE",
+                    "X-lower-case",
+                    "X-ExtEditorR-X-mIxEd",
+                ],
+                names,
+                "{output}"
+            );
+        }
+    }
+
+    #[test]
+    fn folded_custom_header_test() {
+        let mut request = get_blank_compose();
+        request.configuration.allow_custom_headers = true;
+        request.compose_details.custom_headers = vec![
+            CustomHeader::new("X-ExtEditorR-Foo", "foo\r\n  bar"),
+            CustomHeader::new("X-Label", "hello\nworld"),
+            CustomHeader::new("X-Other", "baz"),
+        ];
+
+        for meta_headers in [false, true] {
+            request.configuration.meta_headers = meta_headers;
+            let output = to_eml_and_assert(&request);
+            assert_contains!(output, "X-Label: hello\r\n world\r\n");
+            let responses = request
+                .clone()
+                .merge_from_eml(&mut output.as_bytes(), 512)
+                .unwrap();
+            assert_eq!(1, responses.len());
+            assert!(responses[0].warnings.is_empty());
+            assert_eq!(
+                vec![
+                    CustomHeader::new("X-ExtEditorR-Foo", "foo\n  bar"),
+                    CustomHeader::new("X-Label", "hello\nworld"),
+                    CustomHeader::new("X-Other", "baz"),
+                ],
+                responses[0].compose_details.custom_headers
+            );
+        }
+
+        // other headers are never folded, so an indented header is still a header of its own
+        let eml = "Subject: Hello\r\n  Cc: foo@example.com\r\n\tX-Foo: bar\r\n\r\n";
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut eml.as_bytes(), 512)
+            .unwrap();
+        assert_eq!("Hello", responses[0].compose_details.subject);
+        assert_eq!(
+            ComposeRecipientList::Multiple(vec![ComposeRecipient::Email(
+                "foo@example.com".to_owned()
+            )]),
+            responses[0].compose_details.cc
+        );
+        assert_eq!(
+            vec![CustomHeader::new("X-Foo", "bar")],
+            responses[0].compose_details.custom_headers
+        );
+        // and anything else is skipped
+        let eml = "Subject: Hello\r\n  world!\r\n\r\nThis is a test.\r\n";
+        let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+        assert_eq!("Hello", responses[0].compose_details.subject);
+        assert_eq!(
+            "This is a test.\r\n",
+            responses[0].compose_details.plain_text_body
+        );
+    }
+
+    #[test]
+    fn duplicate_custom_headers_test() {
+        let eml = [
+            "X-ExtEditorR-Allow-X-Headers: true",
+            "X-Label: foo",
+            "X-Other: hello",
+            "x-label: bar",
+            "X-LABEL: baz",
+            "",
+            "This is a test.",
+            "",
+        ]
+        .join("\r\n");
+
+        let mut request = get_blank_compose();
+        let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+        assert_eq!(1, responses.len());
+        assert_eq!(4, responses[0].compose_details.custom_headers.len());
+
+        let mut request = get_blank_compose();
+        request.configuration.merge_duplicate_custom_headers = CustomHeaderMergeStrategy::Join;
+        let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+        assert_eq!(1, responses.len());
+        assert_eq!(
+            vec![
+                CustomHeader::new("X-Label", "foo, bar, baz"),
+                CustomHeader::new("X-Other", "hello"),
+            ],
+            responses[0].compose_details.custom_headers
+        );
+
+        let mut request = get_blank_compose();
+        request.configuration.merge_duplicate_custom_headers = CustomHeaderMergeStrategy::KeepLast;
+        let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+        assert_eq!(1, responses.len());
+        assert_eq!(
+            vec![
+                CustomHeader::new("X-Label", "baz"),
+                CustomHeader::new("X-Other", "hello"),
+            ],
+            responses[0].compose_details.custom_headers
+        );
+    }
+
+    #[test]
+    fn merge_empty_body_test() {
+        let eml = "From: foo@example.com\r\n\r\n";
+        let mut request = get_blank_compose();
+        request.compose_details.is_plain_text = true;
+        request.compose_details.plain_text_body = "This is a test.\r\n".to_owned();
+        let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+        assert_eq!(1, responses.len());
+        assert_eq!("", responses[0].compose_details.plain_text_body);
+        let json = serde_json::to_value(&responses[0]).unwrap();
+        assert_eq!("", json["composeDetails"]["plainTextBody"]);
+    }
+
+    #[test]
+    fn merge_preview_test() {
+        let eml = [
+            "To: foo@example.com",
+            "Subject: Hello",
+            "X-ExtEditorR-Send-On-Exit: true",
+            "X-ExtEditorR-Preview: true",
+            "",
+            "This is a test.",
+            "",
+        ]
+        .join("\r\n");
+        let mut request = get_blank_compose();
+        request.configuration.send_despite_warnings = true;
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut eml.as_bytes(), 512)
+            .unwrap();
+        assert_eq!(1, responses.len());
+        assert!(responses[0].warnings.is_empty());
+        assert!(responses[0].configuration.preview);
+        assert!(!responses[0].configuration.send_on_exit);
+        // applied as usual otherwise
+        assert_eq!("Hello", responses[0].compose_details.subject);
+        assert_eq!(
+            "This is a test.\r\n",
+            responses[0].compose_details.plain_text_body
+        );
+
+        let eml = eml.replace("Preview: true", "Preview: false");
+        let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+        assert!(!responses[0].configuration.preview);
+        assert!(responses[0].configuration.send_on_exit);
+    }
+
+    #[test]
+    fn merge_discard_test() {
+        let eml = [
+            "To: foo@example.com",
+            "Subject: Hello",
+            "X-ExtEditorR-Send-On-Exit: true",
+            "X-ExtEditorR-Discard: true",
+            "",
+            "This is a test.",
+            "",
+        ]
+        .join("\r\n");
+        let mut request = get_blank_compose();
+        request.compose_details.subject = "Original".to_owned();
+        request.compose_details.plain_text_body = "Original body\r\n".to_owned();
+        let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+        assert_eq!(1, responses.len());
+        assert!(responses[0].configuration.discard);
+        assert!(!responses[0].configuration.send_on_exit);
+        assert_eq!("Original", responses[0].compose_details.subject);
+        assert_eq!(
+            ComposeRecipientList::Single(ComposeRecipient::Email("someone@example.com".to_owned())),
+            responses[0].compose_details.to
+        );
+        assert_eq!(
+            "Original body\r\n",
+            responses[0].compose_details.plain_text_body
+        );
+
+        let mut eml = "X-ExtEditorR: Discard: false\r\n\r\nThis is a test.\r\n".as_bytes();
+        let responses = request.merge_from_eml(&mut eml, 512).unwrap();
+        assert_eq!(1, responses.len());
+        assert!(!responses[0].configuration.discard);
+        assert_eq!(
+            "This is a test.\r\n",
+            responses[0].compose_details.plain_text_body
+        );
+    }
+
+    #[test]
+    fn merge_reset_test() {
+        let eml = [
+            "To: foo@example.com",
+            "Subject: Hello",
+            "X-ExtEditorR-Send-On-Exit: true",
+            "X-ExtEditorR-Reset: true",
+            "",
+            "This is a test.",
+            "",
+        ]
+        .join("\r\n");
+        let mut request = get_blank_compose();
+        request.compose_details.subject = "Original".to_owned();
+        request.compose_details.plain_text_body = "Original body\r\n".to_owned();
+        let responses = request.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+        assert_eq!(1, responses.len());
+        assert!(!responses[0].configuration.discard);
+        assert!(!responses[0].configuration.send_on_exit);
+        assert_eq!("Original", responses[0].compose_details.subject);
+        assert_eq!(
+            ComposeRecipientList::Single(ComposeRecipient::Email("someone@example.com".to_owned())),
+            responses[0].compose_details.to
+        );
+        assert_eq!(
+            "Original body\r\n",
+            responses[0].compose_details.plain_text_body
+        );
+    }
+
     #[test]
     fn delete_send_on_exit_header_test() {
         let mut eml = "Subject: Hello\r\n\r\nThis is a test.\r\n".as_bytes();
@@ -1178,6 +4804,78 @@ pub mod tests {
         assert!(!responses[0].configuration.send_on_exit);
     }
 
+    #[test]
+    fn quote_prefix_test() {
+        let mut request = get_blank_compose();
+        request.configuration.quote_prefix = "│ ".to_owned();
+        request.compose_details.plain_text_body =
+            "Hi,\r\n\r\nfoo wrote:\r\n> Hello\r\n>> World\r\n> > Again\r\n│ Bye\r\n".to_owned();
+        let output = to_eml_and_assert(&request);
+        assert!(output.ends_with(
+            "\r\n\r\nHi,\r\n\r\nfoo wrote:\r\n│ Hello\r\n││ World\r\n│ │ Again\r\n\u{200b}│ Bye\r\n"
+        ));
+
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut output.as_bytes(), 512)
+            .unwrap();
+        assert_eq!(
+            request.compose_details.plain_text_body,
+            responses[0].compose_details.plain_text_body
+        );
+
+        // edited quotes are restored as well
+        let eml = output.replace("│ Hello", "│ Hello there");
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut eml.as_bytes(), 512)
+            .unwrap();
+        assert_eq!(
+            "Hi,\r\n\r\nfoo wrote:\r\n> Hello there\r\n>> World\r\n> > Again\r\n│ Bye\r\n",
+            responses[0].compose_details.plain_text_body
+        );
+
+        // HTML bodies use blockquote
+        request.compose_details.is_plain_text = false;
+        request.compose_details.body = "> Hello".to_owned();
+        let output = to_eml_and_assert(&request);
+        assert!(output.ends_with("\r\n> Hello"));
+    }
+
+    #[test]
+    fn preserve_quote_prefixes_test() {
+        let body = "Hi,\r\n\r\n> Foo\r\n>> Bar  \r\n>>> \r\n> >Baz\r\n";
+        let mut request = get_blank_compose();
+        request.configuration.trim_trailing_newline = true;
+        request.configuration.preserve_quote_prefixes = true;
+        request.compose_details.plain_text_body = body.to_owned();
+        let output = to_eml_and_assert(&request);
+        assert!(output.ends_with(&format!("\r\n\r\n{body}")));
+
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut output.as_bytes(), 512)
+            .unwrap();
+        assert_eq!(body, responses[0].compose_details.plain_text_body);
+
+        // quote_prefix doesn't apply either
+        request.configuration.quote_prefix = "│ ".to_owned();
+        assert_eq!(output, to_eml_and_assert(&request));
+        let responses = request
+            .clone()
+            .merge_from_eml(&mut output.as_bytes(), 512)
+            .unwrap();
+        assert_eq!(body, responses[0].compose_details.plain_text_body);
+        request.configuration.quote_prefix.clear();
+
+        request.configuration.preserve_quote_prefixes = false;
+        let responses = request.merge_from_eml(&mut output.as_bytes(), 512).unwrap();
+        assert_eq!(
+            body.strip_suffix("\r\n").unwrap(),
+            responses[0].compose_details.plain_text_body
+        );
+    }
+
     #[test]
     fn invalid_utf8_test() {
         let eml = {
@@ -1220,6 +4918,15 @@ pub mod tests {
         refute_contains!(output, "X-ExtEditorR-Help");
     }
 
+    #[test]
+    fn configuration_defaults_test() {
+        let configuration: Configuration =
+            serde_json::from_str(r#"{"version": "1.0.0", "shell": "sh", "template": "vim"}"#)
+                .unwrap();
+        assert!(configuration.shell_args.is_empty());
+        assert!(configuration.macos_login_shell);
+    }
+
     fn to_eml_and_assert(compose: &Compose) -> String {
         let mut buf = Vec::new();
         let result = compose.to_eml(&mut buf);
@@ -1233,14 +4940,71 @@ pub mod tests {
                 version: "0.0.0".to_owned(),
                 sequence: 0,
                 total: 0,
+                request_id: "".to_owned(),
                 shell: "".to_owned(),
+                shell_args: Vec::new(),
+                macos_login_shell: true,
                 template: "".to_owned(),
+                command: Vec::new(),
+                persistent_editor_command: "".to_owned(),
+                persistent_editor_timeout: DEFAULT_PERSISTENT_EDITOR_TIMEOUT,
+                pre_command: "".to_owned(),
+                post_command: "".to_owned(),
                 temporary_directory: "".to_owned(),
+                temp_file_name_template: "".to_owned(),
                 send_on_exit: false,
+                send_later: None,
+                subject_prefix: "".to_owned(),
+                charset: "".to_owned(),
+                discard: false,
+                preview: false,
+                reset: false,
+                bcc_self: false,
+                default_reply_to_from: false,
+                emit_x_priority: false,
+                auto_cc: Vec::new(),
+                auto_bcc: Vec::new(),
                 suppress_help_headers: false,
+                warnings_in_eml: false,
                 meta_headers: false,
                 allow_custom_headers: false,
                 bypass_version_check: false,
+                merge_duplicate_custom_headers: CustomHeaderMergeStrategy::Off,
+                append_position: AppendPosition::Replace,
+                report_temp_file_path: false,
+                separate_output_file: false,
+                write_metadata_json: false,
+                convert_html_to_text: false,
+                preserve_quote_prefixes: false,
+                quote_prefix: "".to_owned(),
+                trim_trailing_newline: false,
+                preserve_signature: false,
+                strip_signature: false,
+                raw_body: false,
+                warn_no_recipients: false,
+                warn_empty_subject: false,
+                empty_subject_blocks_send: false,
+                compress_body: false,
+                help_lines: Vec::new(),
+                body_separator: "".to_owned(),
+                sort_recipients: false,
+                collapse_recipients: false,
+                send_despite_warnings: false,
+                read_retries: DEFAULT_READ_RETRIES,
+                attachment_size_notice: DEFAULT_ATTACHMENT_SIZE_NOTICE,
+                max_chunks: DEFAULT_MAX_CHUNKS,
+                ignore_exit_codes: Vec::new(),
+                require_body_separator: false,
+                blank_lines_in_headers: false,
+                fence_control_headers: false,
+                allow_threading_headers: false,
+                allow_sender_header: false,
+                permitted_headers: Vec::new(),
+                max_header_length: DEFAULT_MAX_HEADER_LENGTH,
+                hidden_headers: Vec::new(),
+                always_shown_headers: Vec::new(),
+                temp_file_path: "".to_owned(),
+                body_length: None,
             },
             warnings: Vec::new(),
             tab: Tab {
@@ -1256,6 +5020,7 @@ pub mod tests {
                 mail_tab: false,
             },
             compose_details: get_blank_compose_details(),
+            attach_files: Vec::new(),
         }
     }
 }
@@ -2,6 +2,11 @@ use anyhow::{anyhow, Result};
 use serde::{de::Visitor, Deserialize, Deserializer, Serialize};
 use strum::{Display, EnumString};
 
+pub const SIGNATURE_DELIMITER: &str = "-- ";
+
+/// Escapes a line that would otherwise be taken as quoted by [`restore_quote_markers`].
+const QUOTE_ESCAPE: char = '\u{200b}';
+
 pub trait EmailHeaderValue {
     fn to_header_value(&self) -> Result<String>;
     fn from_header_value(value: &str) -> Result<Self>
@@ -76,9 +81,10 @@ pub struct ComposeDetails {
     pub delivery_format: Option<Option<DeliveryFormat>>,
     #[serde(rename = "isPlainText")]
     pub is_plain_text: bool,
-    #[serde(skip_serializing_if = "String::is_empty")]
+    // always sent even if empty so that a cleared body reaches Thunderbird, the extension then
+    // removes the one that doesn't match isPlainText
     pub body: String,
-    #[serde(rename = "plainTextBody", skip_serializing_if = "String::is_empty")]
+    #[serde(rename = "plainTextBody")]
     pub plain_text_body: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub priority: Option<Priority>,
@@ -103,12 +109,16 @@ pub struct ComposeDetails {
 
 impl ComposeDetails {
     pub fn get_body(&self) -> String {
-        let body = if self.is_plain_text {
+        self.get_raw_body().replace('\r', "").replace('\n', "\r\n")
+    }
+
+    /// Unlike [`Self::get_body`], line breaks are left as they are
+    pub fn get_raw_body(&self) -> &str {
+        if self.is_plain_text {
             &self.plain_text_body
         } else {
             &self.body
-        };
-        body.replace('\r', "").replace('\n', "\r\n")
+        }
     }
 
     pub fn set_body(&mut self, body: String) {
@@ -119,6 +129,20 @@ impl ComposeDetails {
         }
     }
 
+    /// Set the body that doesn't match `is_plain_text`, i.e. the one that is not edited
+    pub fn set_alternative_body(&mut self, body: String) {
+        if self.is_plain_text {
+            self.body = body;
+        } else {
+            self.plain_text_body = body;
+        }
+    }
+
+    /// Whether the plain text body contains a `-- ` signature delimiter line
+    pub fn has_signature_delimiter(&self) -> bool {
+        self.is_plain_text && find_signature_delimiter(&self.plain_text_body).is_some()
+    }
+
     /// Reset all ComposeRecipientList fields to empty ComposeRecipientList::Multiple
     pub fn clear_recipients(&mut self) {
         self.to = ComposeRecipientList::Multiple(Vec::new());
@@ -154,6 +178,49 @@ impl ComposeDetails {
         }
     }
 
+    /// Whether the from address is in Bcc already
+    pub fn is_bcc_self(&self) -> bool {
+        self.bcc
+            .as_slice()
+            .iter()
+            .any(|recipient| recipient.is_same_as(&self.from))
+    }
+
+    /// Add the from address to Bcc unless it's there already
+    pub fn add_bcc_self(&mut self) {
+        if !self.from.is_empty() && !self.is_bcc_self() {
+            self.add_bcc(self.from.clone());
+        }
+    }
+
+    /// Add each address to Cc unless it's in Cc already
+    pub fn add_missing_cc(&mut self, emails: &[String]) {
+        for email in emails.iter().filter(|email| !email.trim().is_empty()) {
+            let recipient = ComposeRecipient::Email(email.trim().to_owned());
+            if !self.cc.contains(&recipient) {
+                self.add_cc(recipient);
+            }
+        }
+    }
+
+    /// Add each address to Bcc unless it's in Bcc already
+    pub fn add_missing_bcc(&mut self, emails: &[String]) {
+        for email in emails.iter().filter(|email| !email.trim().is_empty()) {
+            let recipient = ComposeRecipient::Email(email.trim().to_owned());
+            if !self.bcc.contains(&recipient) {
+                self.add_bcc(recipient);
+            }
+        }
+    }
+
+    /// Use the sender as Reply-To if there is none, unless the sender is a node
+    pub fn default_reply_to_from(&mut self) {
+        let is_email = matches!(self.from, ComposeRecipient::Email(_));
+        if is_email && !self.from.is_empty() && self.reply_to.is_empty() {
+            self.reply_to = ComposeRecipientList::Multiple(vec![self.from.clone()]);
+        }
+    }
+
     pub fn add_reply_to(&mut self, recipient: ComposeRecipient) {
         match &mut self.reply_to {
             ComposeRecipientList::Single(r) => {
@@ -248,6 +315,31 @@ pub enum Priority {
     Highest,
 }
 
+impl Priority {
+    /// The numeric `X-Priority` value, from 1 (highest) to 5 (lowest)
+    pub fn to_x_priority(&self) -> u8 {
+        match self {
+            Self::Highest => 1,
+            Self::High => 2,
+            Self::Normal => 3,
+            Self::Low => 4,
+            Self::Lowest => 5,
+        }
+    }
+
+    /// Parse an `X-Priority` value, e.g. `1` or `1 (Highest)`
+    pub fn from_x_priority(value: &str) -> Option<Self> {
+        match value.split_whitespace().next()?.parse::<u8>().ok()? {
+            1 => Some(Self::Highest),
+            2 => Some(Self::High),
+            3 => Some(Self::Normal),
+            4 => Some(Self::Low),
+            5 => Some(Self::Lowest),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub enum ComposeType {
@@ -301,6 +393,52 @@ pub enum ComposeRecipient {
     Node(ComposeRecipientNode),
 }
 
+impl ComposeRecipient {
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Self::Email(email) => email.trim().is_empty(),
+            Self::Node(_) => false,
+        }
+    }
+
+    /// Whether this looks like a usable sender, i.e. a node or an email address with `@`
+    pub fn is_valid_sender(&self) -> bool {
+        match self {
+            Self::Email(email) => get_address(email).contains('@'),
+            Self::Node(_) => true,
+        }
+    }
+
+    /// Compare email addresses, ignoring display names and case
+    pub fn is_same_as(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Email(email), Self::Email(other_email)) => {
+                get_address(email).eq_ignore_ascii_case(get_address(other_email))
+            }
+            _ => self == other,
+        }
+    }
+
+    /// Key for display ordering, i.e. the lowercased email or display string, or the node ID
+    pub fn sort_key(&self) -> String {
+        match self {
+            Self::Email(email) => email.trim().to_lowercase(),
+            Self::Node(node) => node.id.clone(),
+        }
+    }
+}
+
+/// Extract `foo@example.com` from `Foo <foo@example.com>`
+fn get_address(email: &str) -> &str {
+    match email.rsplit_once('<') {
+        Some((_, address)) => address
+            .split_once('>')
+            .map_or(address, |(address, _)| address),
+        None => email,
+    }
+    .trim()
+}
+
 impl EmailHeaderValue for ComposeRecipient {
     fn to_header_value(&self) -> Result<String> {
         match &self {
@@ -333,6 +471,26 @@ pub enum ComposeRecipientList {
     Multiple(Vec<ComposeRecipient>),
 }
 
+impl ComposeRecipientList {
+    /// Whether the recipient is in the list, ignoring display names and case
+    pub fn contains(&self, recipient: &ComposeRecipient) -> bool {
+        self.as_slice()
+            .iter()
+            .any(|existing| existing.is_same_as(recipient))
+    }
+
+    pub fn as_slice(&self) -> &[ComposeRecipient] {
+        match self {
+            Self::Single(recipient) => std::slice::from_ref(recipient),
+            Self::Multiple(recipients) => recipients.as_slice(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.as_slice().iter().all(ComposeRecipient::is_empty)
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum Newsgroups {
@@ -340,6 +498,15 @@ pub enum Newsgroups {
     Multiple(Vec<String>),
 }
 
+impl Newsgroups {
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Self::Single(newsgroup) => newsgroup.trim().is_empty(),
+            Self::Multiple(newsgroups) => newsgroups.iter().all(|n| n.trim().is_empty()),
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct CustomHeader {
     pub name: String,
@@ -347,6 +514,8 @@ pub struct CustomHeader {
 }
 
 impl CustomHeader {
+    /// Apart from a lowercase `x-` prefix, the name is kept as it is so that custom headers
+    /// round-trip byte-identically
     pub fn new(name: &str, value: &str) -> Self {
         let name = name.trim();
         Self {
@@ -361,6 +530,152 @@ impl CustomHeader {
     }
 }
 
+/// Byte offset of the last `-- ` signature delimiter line
+pub fn find_signature_delimiter(body: &str) -> Option<usize> {
+    lines_with_offsets(body)
+        .filter(|(_, line)| *line == SIGNATURE_DELIMITER)
+        .last()
+        .map(|(offset, _)| offset)
+}
+
+/// Put back the trailing space of the signature delimiter if it has been stripped, e.g. by
+/// editors that remove trailing whitespace. Only the last `--` line is considered.
+pub fn restore_signature_delimiter(body: &mut String) {
+    if find_signature_delimiter(body).is_some() {
+        return;
+    }
+    let stripped_delimiter = SIGNATURE_DELIMITER.trim_end();
+    if let Some((offset, _)) = lines_with_offsets(body)
+        .filter(|(_, line)| *line == stripped_delimiter)
+        .last()
+    {
+        body.insert(offset + stripped_delimiter.len(), ' ');
+    }
+}
+
+/// Remove the last `-- ` signature delimiter line and everything after it. A `--` line without
+/// the trailing space is not a delimiter and hence left alone.
+pub fn strip_signature(body: &mut String) {
+    if let Some(offset) = find_signature_delimiter(body) {
+        body.truncate(offset);
+    }
+}
+
+/// Replace `>` quote markers with `prefix` for display, e.g. `> foo` becomes `│ foo` with
+/// `prefix` `│ `. A marker that is not followed by a space only becomes the visible part of
+/// `prefix`, e.g. `>> foo` becomes `││ foo` while `> > foo` becomes `│ │ foo`, so that the markers
+/// can be restored exactly. Lines that already start with the visible part of `prefix` are
+/// escaped with a zero-width space, see [`restore_quote_markers`].
+pub fn replace_quote_markers(body: &str, prefix: &str) -> String {
+    let (marker, spacing) = split_quote_prefix(prefix);
+    if marker.is_empty() {
+        return body.to_owned();
+    }
+    map_lines(body, |line| {
+        if !line.starts_with('>') {
+            return if line.starts_with(marker) || line.starts_with(QUOTE_ESCAPE) {
+                format!("{QUOTE_ESCAPE}{line}")
+            } else {
+                line.to_owned()
+            };
+        }
+        let mut displayed = String::new();
+        let mut rest = line;
+        while let Some(after_marker) = rest.strip_prefix('>') {
+            displayed.push_str(marker);
+            rest = match after_marker.strip_prefix(' ') {
+                Some(after_space) => {
+                    displayed.push_str(if spacing.is_empty() { " " } else { spacing });
+                    after_space
+                }
+                None => after_marker,
+            };
+        }
+        displayed + rest
+    })
+}
+
+/// Reverse of [`replace_quote_markers`]. Lines starting with the visible part of `prefix` are
+/// taken as quoted, including new ones, unless escaped with a zero-width space, which is removed.
+pub fn restore_quote_markers(body: &str, prefix: &str) -> String {
+    let (marker, spacing) = split_quote_prefix(prefix);
+    if marker.is_empty() {
+        return body.to_owned();
+    }
+    map_lines(body, |line| {
+        if let Some(literal) = line.strip_prefix(QUOTE_ESCAPE) {
+            return literal.to_owned();
+        }
+        let mut restored = String::new();
+        let mut rest = line;
+        while let Some(after_marker) = rest.strip_prefix(marker) {
+            restored.push('>');
+            let after_spacing = if spacing.is_empty() {
+                // only a space between markers is taken as part of the quote prefix
+                after_marker
+                    .strip_prefix(' ')
+                    .filter(|after_space| after_space.starts_with(marker))
+            } else {
+                after_marker.strip_prefix(spacing)
+            };
+            rest = match after_spacing {
+                Some(after_spacing) => {
+                    restored.push(' ');
+                    after_spacing
+                }
+                None => after_marker,
+            };
+        }
+        restored + rest
+    })
+}
+
+/// Split a quote prefix into its visible part and the trailing whitespace, e.g. `│ ` into `│` and
+/// ` `
+fn split_quote_prefix(prefix: &str) -> (&str, &str) {
+    let marker = prefix.trim_end();
+    (marker, &prefix[marker.len()..])
+}
+
+/// Convert `\r\n`, lone `\r` and lone `\n` line breaks to `\r\n`
+pub fn normalize_line_endings(body: &str) -> String {
+    let mut normalized = String::with_capacity(body.len());
+    let mut chars = body.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\r' => {
+                chars.next_if_eq(&'\n');
+                normalized.push_str("\r\n");
+            }
+            '\n' => normalized.push_str("\r\n"),
+            c => normalized.push(c),
+        }
+    }
+    normalized
+}
+
+/// Apply `f` to each line without its line break
+fn map_lines<F>(body: &str, f: F) -> String
+where
+    F: Fn(&str) -> String,
+{
+    body.split_inclusive('\n')
+        .map(|line| {
+            let content = line.trim_end_matches(['\r', '\n']);
+            f(content) + &line[content.len()..]
+        })
+        .collect()
+}
+
+fn lines_with_offsets(body: &str) -> impl Iterator<Item = (usize, &str)> {
+    let mut offset = 0;
+    body.split_inclusive('\n').map(move |line| {
+        let start = offset;
+        offset += line.len();
+        (start, line.trim_end_matches(['\r', '\n']))
+    })
+}
+
 // https://github.com/serde-rs/serde/issues/984#issuecomment-314143738
 // Any value that is present is considered Some value, including null.
 fn deserialize_some<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
@@ -379,6 +694,44 @@ fn is_none_nested<T>(v: &Option<Option<T>>) -> bool {
 pub mod tests {
     use super::*;
 
+    #[test]
+    fn quote_markers_test() {
+        let body = "On Monday, foo wrote:\r\n> Hello\r\n>\r\n>> Earlier\r\n> > Spaced\r\nReply > quote\r\n│ Not quoted\r\n\u{200b}│ Escaped\r\n";
+        let displayed = replace_quote_markers(body, "│ ");
+        assert_eq!(
+            "On Monday, foo wrote:\r\n│ Hello\r\n│\r\n││ Earlier\r\n│ │ Spaced\r\nReply > quote\r\n\u{200b}│ Not quoted\r\n\u{200b}\u{200b}│ Escaped\r\n",
+            displayed
+        );
+        assert_eq!(body, restore_quote_markers(&displayed, "│ "));
+        // trailing whitespace of empty quoted lines may have been stripped by the editor
+        assert_eq!(">>\n> Hi\n", restore_quote_markers("││\n│ Hi\n", "│ "));
+
+        // without spacing
+        let displayed = replace_quote_markers(body, "|");
+        assert!(displayed.contains("\r\n|| Earlier\r\n| | Spaced\r\n"));
+        assert_eq!(body, restore_quote_markers(&displayed, "|"));
+    }
+
+    #[test]
+    fn duplicate_quote_lines_test() {
+        // the same text both quoted and not, in any order
+        let body = "│\n>\n│ a\n> a\n>\n│\n│ a\n> a\n";
+        let displayed = replace_quote_markers(body, "│ ");
+        assert_eq!(body, restore_quote_markers(&displayed, "│ "));
+
+        // each line keeps what it was even if others are removed or added
+        let edited = displayed
+            .lines()
+            .skip(2)
+            .chain(["│ new quote", "reply"])
+            .map(|line| format!("{line}\n"))
+            .collect::<String>();
+        assert_eq!(
+            "│ a\n> a\n>\n│\n│ a\n> a\n> new quote\nreply\n",
+            restore_quote_markers(&edited, "│ ")
+        );
+    }
+
     #[test]
     fn compose_recipient_list_single_email_serialisation_test() {
         let single_email = ComposeRecipientList::Single(ComposeRecipient::Email(
@@ -557,6 +910,19 @@ pub mod tests {
         assert!(json.contains(r#""deliveryFormat":"html""#));
     }
 
+    #[test]
+    fn compose_details_null_delivery_format_test() {
+        let compose_details = get_blank_compose_details();
+        let mut json = serde_json::to_value(&compose_details).unwrap();
+        json["deliveryFormat"] = serde_json::Value::Null;
+        let compose_details: ComposeDetails = serde_json::from_value(json).unwrap();
+        // i.e. auto, unlike a missing one
+        assert_eq!(Some(None), compose_details.delivery_format);
+
+        let json = serde_json::to_value(&compose_details).unwrap();
+        assert!(json.get("deliveryFormat").is_none());
+    }
+
     #[test]
     fn tracked_option_bool_test() {
         let mut tracked_option_bool = TrackedOptionBool::default();
@@ -1,7 +1,12 @@
+use anyhow::Result;
 #[cfg(test)]
 use mockall::automock;
+use std::io::{self, BufRead};
+use std::sync::{Mutex, PoisonError};
 use webextension_native_messaging::MessagingError;
 
+use crate::util::exchange_log::{self, Direction};
+
 #[cfg_attr(test, automock)]
 pub trait Transport {
     fn read_message<D>() -> Result<D, MessagingError>
@@ -12,8 +17,32 @@ pub trait Transport {
         S: 'static + serde::Serialize;
 }
 
+/// Serialises writes to stdout, as each compose request is handled in its own thread and frames
+/// must not interleave
+static WRITE_LOCK: Mutex<()> = Mutex::new(());
+
+fn with_write_lock<F, T>(f: F) -> T
+where
+    F: FnOnce() -> T,
+{
+    let _guard = WRITE_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    f()
+}
+
 pub struct ThunderbirdTransport {}
 
+impl ThunderbirdTransport {
+    /// Whether stdin reached EOF before the next message, i.e. Thunderbird exited. Peeking goes
+    /// through the buffer shared by all stdin handles, so nothing is lost for
+    /// [`Transport::read_message`].
+    pub fn is_input_closed() -> bool {
+        io::stdin()
+            .lock()
+            .fill_buf()
+            .is_ok_and(|buf| buf.is_empty())
+    }
+}
+
 impl Transport for ThunderbirdTransport {
     fn read_message<D>() -> Result<D, MessagingError>
     where
@@ -26,6 +55,108 @@ impl Transport for ThunderbirdTransport {
     where
         S: serde::Serialize,
     {
-        webextension_native_messaging::write_message(message)
+        with_write_lock(|| {
+            exchange_log::record(Direction::Sent, message);
+            webextension_native_messaging::write_message(message)
+        })
+    }
+}
+
+/// Write a message using the length-prefixed framing of native messaging, i.e. how Thunderbird
+/// sends requests to the host.
+pub fn write_framed<W, S>(w: &mut W, message: &S) -> Result<()>
+where
+    W: io::Write,
+    S: serde::Serialize,
+{
+    let buf = serde_json::to_vec(message)?;
+    w.write_all(&u32::try_from(buf.len())?.to_ne_bytes())?;
+    w.write_all(&buf)?;
+    w.flush()?;
+    Ok(())
+}
+
+/// Read a length-prefixed native messaging message, i.e. how Thunderbird receives responses from
+/// the host.
+pub fn read_framed<R, D>(r: &mut R) -> Result<D>
+where
+    R: io::Read,
+    D: for<'a> serde::Deserialize<'a>,
+{
+    let mut length = [0u8; 4];
+    r.read_exact(&mut length)?;
+    let mut buf = vec![0u8; u32::from_ne_bytes(length) as usize];
+    r.read_exact(&mut buf)?;
+    Ok(serde_json::from_slice(&buf)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::messaging::Ping;
+
+    #[test]
+    fn framing_test() {
+        let ping: Ping = serde_json::from_str(r#"{"ping": 123456}"#).unwrap();
+        let mut buf = Vec::new();
+        write_framed(&mut buf, &ping).unwrap();
+        assert_eq!(
+            buf.len() - 4,
+            u32::from_ne_bytes(buf[..4].try_into().unwrap()) as usize
+        );
+
+        let ping: Ping = read_framed(&mut buf.as_slice()).unwrap();
+        assert_eq!(123456, ping.ping);
+    }
+
+    /// Writes a single byte at a time so that unsynchronised frames would interleave
+    struct BytewiseWriter(std::sync::Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for BytewiseWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let Some(&byte) = buf.first() else {
+                return Ok(0);
+            };
+            self.0.lock().unwrap().push(byte);
+            std::thread::yield_now();
+            Ok(1)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn concurrent_write_test() {
+        let output = std::sync::Arc::new(Mutex::new(Vec::new()));
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                let mut writer = BytewiseWriter(output.clone());
+                std::thread::spawn(move || {
+                    for j in 0..16 {
+                        let ping: Ping =
+                            serde_json::from_str(&format!(r#"{{"ping": {}}}"#, i * 100 + j))
+                                .unwrap();
+                        with_write_lock(|| write_framed(&mut writer, &ping)).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let output = output.lock().unwrap();
+        let mut r = output.as_slice();
+        let mut pings: Vec<_> = (0..8 * 16)
+            .map(|_| read_framed::<_, Ping>(&mut r).unwrap().ping)
+            .collect();
+        assert!(r.is_empty());
+        pings.sort();
+        let expected: Vec<_> = (0..8)
+            .flat_map(|i| (0..16).map(move |j| i * 100 + j))
+            .collect();
+        assert_eq!(expected, pings);
     }
 }
@@ -1,10 +1,32 @@
+pub mod allowed_shells;
+pub mod cleanup;
+pub mod config_file;
+pub mod echo;
+pub mod exchange_log;
+pub mod html_text;
+pub mod logger;
 pub mod meta_header;
 
 use std::env;
 use std::fmt::Display;
 use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use crate::log_warn;
 use crate::model::messaging::Compose;
+use crate::model::thunderbird::Tab;
+
+pub const TEMPLATE_TEMP_FILE_NAME: &str = "/path/to/temp.eml";
+pub const TEMPLATE_OUTPUT_FILE_NAME: &str = "/path/to/output.eml";
+pub const TEMPLATE_LOCK_FILE_NAME: &str = "/path/to/temp.lock";
+const TEMPLATE_TAB_ID: &str = "{tab_id}";
+const TEMPLATE_WINDOW_ID: &str = "{window_id}";
+const TEMPLATE_SUBJECT: &str = "{subject}";
+const TEMPLATE_DATE: &str = "{date}";
+const MAX_SUBJECT_LENGTH: usize = 40;
+const MAX_FILE_NAME_LENGTH: usize = 255;
 
 #[macro_export]
 macro_rules! writeln_crlf {
@@ -19,14 +41,317 @@ macro_rules! writeln_crlf {
 pub fn get_temp_filename(request: &Compose) -> PathBuf {
     let custom_dir = request.configuration.temporary_directory.as_str();
     let mut temp_dir = if !custom_dir.is_empty() {
-        PathBuf::from(custom_dir)
+        let per_tab =
+            custom_dir.contains(TEMPLATE_TAB_ID) || custom_dir.contains(TEMPLATE_WINDOW_ID);
+        // a literal directory is used as is, so that creating the temporary file can tell the
+        // user it's missing
+        let expanded = per_tab || custom_dir.starts_with('~') || custom_dir.contains('$');
+        let custom_dir = substitute_tab_placeholders(custom_dir, &request.tab);
+        let custom_dir = expand_path(&custom_dir, |name| env::var(name).ok());
+        if per_tab && !custom_dir.is_dir() {
+            if let Err(e) = std::fs::create_dir_all(&custom_dir) {
+                log_warn!(
+                    "ExtEditorR failed to create temporary directory {}: {}",
+                    custom_dir.to_string_lossy(),
+                    e
+                );
+            }
+        }
+        if !expanded || custom_dir.is_dir() {
+            custom_dir
+        } else {
+            log_warn!(
+                "ExtEditorR temporary directory {} does not exist, falling back to system default",
+                custom_dir.to_string_lossy()
+            );
+            env::temp_dir()
+        }
     } else {
         env::temp_dir()
     };
-    temp_dir.push(format!("external_editor_revived_{}.eml", request.tab.id));
+    let template = request.configuration.temp_file_name_template.as_str();
+    let file_name =
+        expand_temp_file_name(template, request, SystemTime::now()).unwrap_or_else(|| {
+            if !template.is_empty() {
+                log_warn!("ExtEditorR ignored invalid temporary file name template {template}");
+            }
+            format!("external_editor_revived_{}.eml", request.tab.id)
+        });
+    temp_dir.push(file_name);
     temp_dir
 }
 
+/// Expand `{tab_id}`, `{window_id}`, `{subject}` and `{date}` in a file name template, or `None`
+/// if the result is not a usable file name. The tab ID is added before the extension if the
+/// template doesn't contain it, as otherwise compose windows would share the same file.
+fn expand_temp_file_name(template: &str, request: &Compose, now: SystemTime) -> Option<String> {
+    let template = template.trim();
+    if template.is_empty() {
+        return None;
+    }
+    let template = if template.contains(TEMPLATE_TAB_ID) {
+        template.to_owned()
+    } else {
+        match template.rfind('.').filter(|&dot| dot > 0) {
+            Some(dot) => format!("{}_{TEMPLATE_TAB_ID}{}", &template[..dot], &template[dot..]),
+            None => format!("{template}_{TEMPLATE_TAB_ID}"),
+        }
+    };
+    let subject = sanitize_subject(&request.compose_details.subject);
+    if subject.is_empty() && template.contains(TEMPLATE_SUBJECT) {
+        return None;
+    }
+    let date = now
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .and_then(|since_epoch| {
+            chrono::DateTime::from_timestamp(since_epoch.as_secs().try_into().ok()?, 0)
+        })?
+        .format("%Y-%m-%d")
+        .to_string();
+    let file_name = substitute_tab_placeholders(&template, &request.tab)
+        .replace(TEMPLATE_SUBJECT, &subject)
+        .replace(TEMPLATE_DATE, &date);
+    let file_name = file_name.trim();
+    let is_valid = !file_name.is_empty()
+        && file_name.len() <= MAX_FILE_NAME_LENGTH
+        && !file_name.ends_with('.')
+        && !file_name.contains(['/', '\\', ':', '*', '?', '"', '<', '>', '|'])
+        && !file_name.contains(char::is_control)
+        && !is_reserved_file_name(file_name);
+    is_valid.then(|| file_name.to_owned())
+}
+
+/// Device names that can't be used as file names on Windows, with or without an extension
+fn is_reserved_file_name(file_name: &str) -> bool {
+    let stem = file_name
+        .split('.')
+        .next()
+        .unwrap_or_default()
+        .trim_end()
+        .to_uppercase();
+    matches!(stem.as_str(), "CON" | "PRN" | "AUX" | "NUL")
+        || ["COM", "LPT"].iter().any(|prefix| {
+            stem.strip_prefix(prefix)
+                .is_some_and(|n| matches!(n, "1" | "2" | "3" | "4" | "5" | "6" | "7" | "8" | "9"))
+        })
+}
+
+/// Keep only characters that are safe in file names on all platforms, and cap the length
+fn sanitize_subject(subject: &str) -> String {
+    let mut sanitized = String::new();
+    for c in subject.chars() {
+        if c.is_alphanumeric() || matches!(c, '-' | '.') {
+            sanitized.push(c);
+        } else if !sanitized.is_empty() && !sanitized.ends_with('_') {
+            sanitized.push('_');
+        }
+    }
+    sanitized
+        .chars()
+        .take(MAX_SUBJECT_LENGTH)
+        .collect::<String>()
+        .trim_end_matches(['_', '.'])
+        .to_owned()
+}
+
+/// Path of the file that editors save to when they can't edit the temporary file in place
+pub fn get_output_filename(temp_filename: &Path) -> PathBuf {
+    temp_filename.with_extension("output.eml")
+}
+
+/// Path of the JSON sidecar file with compose details for editor scripts
+pub fn get_metadata_filename(temp_filename: &Path) -> PathBuf {
+    temp_filename.with_extension("json")
+}
+
+/// Path of the lock file that a persistent editor removes once done with the temporary file
+pub fn get_lock_filename(temp_filename: &Path) -> PathBuf {
+    temp_filename.with_extension("lock")
+}
+
+/// Replace `{tab_id}` and `{window_id}` so that temporary files can be grouped per tab or window
+fn substitute_tab_placeholders(path: &str, tab: &Tab) -> String {
+    path.replace(TEMPLATE_TAB_ID, &tab.id.to_string())
+        .replace(TEMPLATE_WINDOW_ID, &tab.window_id.to_string())
+}
+
+/// Expand a leading `~` to the home directory, and `$VAR`/`${VAR}` references using `lookup`.
+/// Undefined variables are left untouched.
+pub fn expand_path<F>(path: &str, lookup: F) -> PathBuf
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut expanded = String::with_capacity(path.len());
+    let mut rest = path;
+    if let Some(after_tilde) = path.strip_prefix('~') {
+        if after_tilde.is_empty() || after_tilde.starts_with(['/', '\\']) {
+            if let Some(home) = lookup("HOME").or_else(|| lookup("USERPROFILE")) {
+                expanded.push_str(&home);
+                rest = after_tilde;
+            }
+        }
+    }
+    while let Some(dollar) = rest.find('$') {
+        expanded.push_str(&rest[..dollar]);
+        let after_dollar = &rest[dollar + 1..];
+        let (name, reference_len) = if let Some(braced) = after_dollar.strip_prefix('{') {
+            match braced.find('}') {
+                Some(end) => (&braced[..end], end + 2),
+                None => ("", 0),
+            }
+        } else {
+            let end = after_dollar
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(after_dollar.len());
+            (&after_dollar[..end], end)
+        };
+        match lookup(name).filter(|_| !name.is_empty()) {
+            Some(value) => expanded.push_str(&value),
+            None => expanded.push_str(&rest[dollar..=dollar + reference_len]),
+        }
+        rest = &after_dollar[reference_len..];
+    }
+    expanded.push_str(rest);
+    PathBuf::from(expanded)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShellKind {
+    Cmd,
+    PowerShell,
+    Posix,
+}
+
+impl ShellKind {
+    pub fn from_shell(shell: &str) -> Self {
+        let name = shell
+            .trim()
+            .rsplit(['/', '\\'])
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        match name.strip_suffix(".exe").unwrap_or(&name) {
+            "cmd" => Self::Cmd,
+            "powershell" | "pwsh" => Self::PowerShell,
+            _ => Self::Posix,
+        }
+    }
+
+    /// Escape a string so that it can be placed between double quotes
+    pub fn escape_double_quoted(&self, s: &str) -> String {
+        let (escape_char, special_chars): (char, &[char]) = match self {
+            // cmd.exe has no escape character inside double quotes, and double quotes are not
+            // allowed in Windows paths anyway. % is escaped outside of the quotes instead so
+            // that it's not taken as a variable reference.
+            Self::Cmd => return s.replace('%', "\"^%\""),
+            Self::PowerShell => ('`', &['`', '$', '"']),
+            Self::Posix => ('\\', &['\\', '$', '`', '"']),
+        };
+        let mut escaped = String::with_capacity(s.len());
+        for c in s.chars() {
+            if special_chars.contains(&c) {
+                escaped.push(escape_char);
+            }
+            escaped.push(c);
+        }
+        escaped
+    }
+
+    /// Escape a string so that it can be placed between single quotes
+    pub fn escape_single_quoted(&self, s: &str) -> String {
+        match self {
+            // single quotes are not special to cmd.exe
+            Self::Cmd => s.to_owned(),
+            Self::PowerShell => s.replace('\'', "''"),
+            Self::Posix => s.replace('\'', r"'\''"),
+        }
+    }
+
+    /// Quote and escape a string so that it's passed as a single argument
+    pub fn quote(&self, s: &str) -> String {
+        match self {
+            Self::Cmd => format!("\"{}\"", self.escape_double_quoted(s)),
+            Self::PowerShell | Self::Posix => format!("'{}'", self.escape_single_quoted(s)),
+        }
+    }
+
+    /// The quotes that are still open at the end of `s`, if any
+    fn open_quote(&self, s: &str) -> Option<char> {
+        let escape_char = match self {
+            Self::Cmd => '^',
+            Self::PowerShell => '`',
+            Self::Posix => '\\',
+        };
+        let mut quote = None;
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            match (quote, c) {
+                (Some('\''), '\'') => quote = None,
+                (Some('\''), _) => {}
+                // cmd.exe doesn't have escapes inside double quotes
+                (Some('"'), '^') if *self == Self::Cmd => {}
+                (_, c) if c == escape_char => {
+                    chars.next();
+                }
+                (Some('"'), '"') => quote = None,
+                (None, '"') => quote = Some('"'),
+                (None, '\'') if *self != Self::Cmd => quote = Some('\''),
+                _ => {}
+            }
+        }
+        quote
+    }
+}
+
+/// Replace the temporary file placeholder in the command template. If the placeholder is quoted,
+/// e.g. `"/path/to/temp.eml"`, the path is escaped according to the shell and the quotes.
+/// Otherwise it's quoted as well, so that e.g. spaces in the path don't split it.
+pub fn substitute_temp_filename(template: &str, shell: &str, temp_filename: &Path) -> String {
+    substitute_path(template, TEMPLATE_TEMP_FILE_NAME, shell, temp_filename)
+}
+
+/// Same as [`substitute_temp_filename`] but for the output file placeholder, i.e.
+/// `"/path/to/output.eml"`.
+pub fn substitute_output_filename(template: &str, shell: &str, output_filename: &Path) -> String {
+    substitute_path(template, TEMPLATE_OUTPUT_FILE_NAME, shell, output_filename)
+}
+
+/// Same as [`substitute_temp_filename`] but for the lock file placeholder, i.e.
+/// `"/path/to/temp.lock"`.
+pub fn substitute_lock_filename(template: &str, shell: &str, lock_filename: &Path) -> String {
+    substitute_path(template, TEMPLATE_LOCK_FILE_NAME, shell, lock_filename)
+}
+
+fn substitute_path(template: &str, placeholder: &str, shell: &str, path: &Path) -> String {
+    let shell_kind = ShellKind::from_shell(shell);
+    let path = path.to_string_lossy();
+    let mut substituted = String::with_capacity(template.len());
+    let mut last_end = 0;
+    for (start, _) in template.match_indices(placeholder) {
+        substituted.push_str(&template[last_end..start]);
+        match shell_kind.open_quote(&template[..start]) {
+            Some('"') => substituted.push_str(&shell_kind.escape_double_quoted(&path)),
+            Some(_) => substituted.push_str(&shell_kind.escape_single_quoted(&path)),
+            None => substituted.push_str(&shell_kind.quote(&path)),
+        }
+        last_end = start + placeholder.len();
+    }
+    substituted.push_str(&template[last_end..]);
+    substituted
+}
+
+/// Whether a string consists of only whitespace and zero-width characters
+pub fn is_blank(s: &str) -> bool {
+    s.chars().all(|c| {
+        c.is_whitespace()
+            || matches!(
+                c,
+                '\u{200b}' | '\u{200c}' | '\u{200d}' | '\u{2060}' | '\u{feff}'
+            )
+    })
+}
+
 #[inline]
 pub fn error_message_with_path<T>(e: T, path: &Path) -> String
 where
@@ -39,6 +364,22 @@ where
     )
 }
 
+/// An ID for requests that don't come with one, unique within this host process and very
+/// likely across processes as well
+pub fn generate_request_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    format!(
+        "{:x}-{:x}-{:x}",
+        process::id(),
+        nanos,
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
 pub fn is_extension_compatible(host_version: &str, extension_version: &str) -> bool {
     let host_version: Vec<&str> = host_version.split('.').collect();
     let extension_version: Vec<&str> = extension_version.split('.').collect();
@@ -53,6 +394,337 @@ pub fn is_extension_compatible(host_version: &str, extension_version: &str) -> b
 mod tests {
     use super::*;
 
+    #[test]
+    fn shell_kind_test() {
+        assert_eq!(ShellKind::Cmd, ShellKind::from_shell("cmd"));
+        assert_eq!(
+            ShellKind::Cmd,
+            ShellKind::from_shell(r"C:\Windows\System32\CMD.EXE")
+        );
+        assert_eq!(
+            ShellKind::PowerShell,
+            ShellKind::from_shell("powershell.exe")
+        );
+        assert_eq!(
+            ShellKind::PowerShell,
+            ShellKind::from_shell("/usr/bin/pwsh")
+        );
+        assert_eq!(ShellKind::Posix, ShellKind::from_shell("sh"));
+        assert_eq!(
+            ShellKind::Posix,
+            ShellKind::from_shell(r"C:\msys64\usr\bin\bash.exe")
+        );
+    }
+
+    #[test]
+    fn substitute_temp_filename_cmd_test() {
+        let temp_filename =
+            Path::new(r"C:\Users\John Smith\AppData\Local\Temp\external_editor_revived_1.eml");
+        let command = substitute_temp_filename(
+            r#"notepad.exe "/path/to/temp.eml""#,
+            "cmd.exe",
+            temp_filename,
+        );
+        assert_eq!(
+            r#"notepad.exe "C:\Users\John Smith\AppData\Local\Temp\external_editor_revived_1.eml""#,
+            command
+        );
+    }
+
+    #[test]
+    fn substitute_temp_filename_cmd_percent_test() {
+        let temp_filename = Path::new(r"C:\Users\100%done\Temp\external_editor_revived_1.eml");
+        let command = substitute_temp_filename(
+            r#"notepad.exe "/path/to/temp.eml""#,
+            "cmd.exe",
+            temp_filename,
+        );
+        assert_eq!(
+            r#"notepad.exe "C:\Users\100"^%"done\Temp\external_editor_revived_1.eml""#,
+            command
+        );
+
+        // ^ is literal inside double quotes
+        let command = substitute_temp_filename(
+            r#"echo "^" && notepad.exe /path/to/temp.eml"#,
+            "cmd.exe",
+            temp_filename,
+        );
+        assert_eq!(
+            r#"echo "^" && notepad.exe "C:\Users\100"^%"done\Temp\external_editor_revived_1.eml""#,
+            command
+        );
+    }
+
+    #[test]
+    fn substitute_temp_filename_powershell_test() {
+        let temp_filename = Path::new(r"C:\Users\John $mith\Temp\external_editor_revived_1.eml");
+        let command =
+            substitute_temp_filename(r#"notepad.exe "/path/to/temp.eml""#, "pwsh", temp_filename);
+        assert_eq!(
+            r#"notepad.exe "C:\Users\John `$mith\Temp\external_editor_revived_1.eml""#,
+            command
+        );
+    }
+
+    #[test]
+    fn substitute_temp_filename_posix_test() {
+        let temp_filename = Path::new(r"C:\Users\John Smith\Temp\external_editor_revived_1.eml");
+        let command = substitute_temp_filename(r#"vim "/path/to/temp.eml""#, "sh", temp_filename);
+        assert_eq!(
+            r#"vim "C:\\Users\\John Smith\\Temp\\external_editor_revived_1.eml""#,
+            command
+        );
+
+        let mut temp_dir = env::temp_dir();
+        temp_dir.push("external editor revived $HOME `true`");
+        std::fs::create_dir_all(&temp_dir).unwrap();
+        let temp_filename = temp_dir.join("external_editor_revived_1.eml");
+        std::fs::write(&temp_filename, "Hello, world!").unwrap();
+        let command = substitute_temp_filename(r#"cat "/path/to/temp.eml""#, "sh", &temp_filename);
+        let output = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .output()
+            .unwrap();
+        std::fs::remove_dir_all(&temp_dir).unwrap();
+        assert!(output.status.success());
+        assert_eq!("Hello, world!", String::from_utf8_lossy(&output.stdout));
+    }
+
+    #[test]
+    fn substitute_temp_filename_quoting_context_test() {
+        let temp_filename = Path::new("/tmp/it's $HOME/external_editor_revived_1.eml");
+        assert_eq!(
+            r#"vim '/tmp/it'\''s $HOME/external_editor_revived_1.eml'"#,
+            substitute_temp_filename("vim /path/to/temp.eml", "sh", temp_filename)
+        );
+        assert_eq!(
+            r#"vim '/tmp/it'\''s $HOME/external_editor_revived_1.eml'"#,
+            substitute_temp_filename("vim '/path/to/temp.eml'", "sh", temp_filename)
+        );
+        assert_eq!(
+            r#"echo "'" && vim "/tmp/it's \$HOME/external_editor_revived_1.eml""#,
+            substitute_temp_filename(
+                r#"echo "'" && vim "/path/to/temp.eml""#,
+                "sh",
+                temp_filename
+            )
+        );
+        assert_eq!(
+            r#"echo \" && vim '/tmp/it'\''s $HOME/external_editor_revived_1.eml'"#,
+            substitute_temp_filename(r#"echo \" && vim /path/to/temp.eml"#, "sh", temp_filename)
+        );
+        assert_eq!(
+            "notepad.exe '/tmp/it''s $HOME/external_editor_revived_1.eml'",
+            substitute_temp_filename("notepad.exe '/path/to/temp.eml'", "pwsh", temp_filename)
+        );
+    }
+
+    #[test]
+    fn substitute_unquoted_temp_filename_test() {
+        let temp_filename =
+            Path::new(r"C:\Users\John Smith\AppData\Local\Temp\external_editor_revived_1.eml");
+        assert_eq!(
+            r#"notepad.exe "C:\Users\John Smith\AppData\Local\Temp\external_editor_revived_1.eml""#,
+            substitute_temp_filename("notepad.exe /path/to/temp.eml", "cmd.exe", temp_filename)
+        );
+        assert_eq!(
+            r"notepad.exe 'C:\Users\John Smith\AppData\Local\Temp\external_editor_revived_1.eml'",
+            substitute_temp_filename("notepad.exe /path/to/temp.eml", "pwsh", temp_filename)
+        );
+        assert_eq!(
+            r"vim 'C:\Users\John Smith\AppData\Local\Temp\external_editor_revived_1.eml'",
+            substitute_temp_filename("vim /path/to/temp.eml", "sh", temp_filename)
+        );
+
+        let mut temp_dir = env::temp_dir();
+        temp_dir.push("external editor revived unquoted");
+        std::fs::create_dir_all(&temp_dir).unwrap();
+        let temp_filename = temp_dir.join("external_editor_revived_1.eml");
+        std::fs::write(&temp_filename, "Hello, world!").unwrap();
+        let command = substitute_temp_filename("cat /path/to/temp.eml", "sh", &temp_filename);
+        let output = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .output()
+            .unwrap();
+        std::fs::remove_dir_all(&temp_dir).unwrap();
+        assert!(output.status.success());
+        assert_eq!("Hello, world!", String::from_utf8_lossy(&output.stdout));
+    }
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "HOME" => Some("/home/foo".to_owned()),
+            "TMP_NAME" => Some("mail-tmp".to_owned()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn expand_tilde_test() {
+        assert_eq!(PathBuf::from("/home/foo"), expand_path("~", lookup));
+        assert_eq!(
+            PathBuf::from("/home/foo/mail-tmp"),
+            expand_path("~/mail-tmp", lookup)
+        );
+        assert_eq!(PathBuf::from("~bar/tmp"), expand_path("~bar/tmp", lookup));
+        assert_eq!(PathBuf::from("/tmp/~"), expand_path("/tmp/~", lookup));
+    }
+
+    #[test]
+    fn expand_env_var_test() {
+        assert_eq!(
+            PathBuf::from("/home/foo/tmp"),
+            expand_path("$HOME/tmp", lookup)
+        );
+        assert_eq!(
+            PathBuf::from("/home/foo/mail-tmp"),
+            expand_path("${HOME}/${TMP_NAME}", lookup)
+        );
+        assert_eq!(
+            PathBuf::from("/tmp/mail-tmp.d"),
+            expand_path("/tmp/$TMP_NAME.d", lookup)
+        );
+        assert_eq!(
+            PathBuf::from("/tmp/$UNDEFINED/${UNDEFINED}/$/${"),
+            expand_path("/tmp/$UNDEFINED/${UNDEFINED}/$/${", lookup)
+        );
+    }
+
+    #[test]
+    fn substitute_tab_placeholders_test() {
+        let mut tab = crate::model::messaging::tests::get_blank_compose().tab;
+        tab.id = 3;
+        tab.window_id = 7;
+        assert_eq!(
+            "/tmp/eer/7/3",
+            substitute_tab_placeholders("/tmp/eer/{window_id}/{tab_id}", &tab)
+        );
+        assert_eq!("/tmp/eer", substitute_tab_placeholders("/tmp/eer", &tab));
+    }
+
+    #[test]
+    fn per_tab_temp_dir_test() {
+        let mut compose = crate::model::messaging::tests::get_blank_compose();
+        compose.tab.id = 8;
+        compose.tab.window_id = 9;
+        compose.configuration.temporary_directory =
+            "./external_editor_revived_window_{window_id}/tab_{tab_id}".to_owned();
+        let temp_filename = get_temp_filename(&compose);
+        let created = Path::new("./external_editor_revived_window_9/tab_8").is_dir();
+        std::fs::remove_dir_all("./external_editor_revived_window_9").unwrap();
+        assert!(created);
+        assert_eq!(
+            Path::new("./external_editor_revived_window_9/tab_8/external_editor_revived_8.eml"),
+            temp_filename
+        );
+
+        // literal directories are neither created nor replaced
+        compose.configuration.temporary_directory = "./external_editor_revived_literal".to_owned();
+        let temp_filename = get_temp_filename(&compose);
+        assert!(!Path::new("./external_editor_revived_literal").exists());
+        assert!(temp_filename.starts_with("./external_editor_revived_literal"));
+    }
+
+    #[test]
+    fn sanitize_subject_test() {
+        assert_eq!("Re_Hello_world", sanitize_subject("Re: Hello, world!"));
+        assert_eq!("a_b_c", sanitize_subject("  a/b\\c?  "));
+        assert_eq!("Grüße", sanitize_subject("Grüße"));
+        assert_eq!("", sanitize_subject("..."));
+        assert_eq!(MAX_SUBJECT_LENGTH, sanitize_subject(&"x".repeat(100)).len());
+    }
+
+    #[test]
+    fn expand_temp_file_name_test() {
+        let mut compose = crate::model::messaging::tests::get_blank_compose();
+        compose.tab.id = 5;
+        compose.compose_details.subject = "Re: Hello".to_owned();
+        let now = UNIX_EPOCH + std::time::Duration::from_secs(86400 * 365);
+        assert_eq!(
+            Some("1971-01-01_Re_Hello_5.eml".to_owned()),
+            expand_temp_file_name("{date}_{subject}_{tab_id}.eml", &compose, now)
+        );
+        assert_eq!(None, expand_temp_file_name("", &compose, now));
+        assert_eq!(
+            None,
+            expand_temp_file_name("../{tab_id}.eml", &compose, now)
+        );
+
+        // compose windows never share a file
+        assert_eq!(
+            Some("Re_Hello_5.eml".to_owned()),
+            expand_temp_file_name("{subject}.eml", &compose, now)
+        );
+        assert_eq!(
+            Some("1971-01-01_5".to_owned()),
+            expand_temp_file_name("{date}", &compose, now)
+        );
+
+        // reserved on Windows
+        for template in [
+            "{tab_id}:{subject}.eml",
+            "{tab_id}?.eml",
+            "<{tab_id}>.eml",
+            "{tab_id}|{date}.eml",
+            "{tab_id}*.eml",
+            "\"{tab_id}\".eml",
+            "{tab_id}.eml.",
+            "CON.{tab_id}.eml",
+            "nul.{tab_id}.eml",
+            "com1 .{date}_{tab_id}.eml",
+            "LPT9.{tab_id}",
+        ] {
+            assert_eq!(
+                None,
+                expand_temp_file_name(template, &compose, now),
+                "{template}"
+            );
+        }
+        assert_eq!(
+            Some("CON_5.eml".to_owned()),
+            expand_temp_file_name("CON.eml", &compose, now)
+        );
+        assert_eq!(
+            Some("CONTACT_5.eml".to_owned()),
+            expand_temp_file_name("CONTACT.eml", &compose, now)
+        );
+
+        compose.configuration.temporary_directory = ".".to_owned();
+        compose.configuration.temp_file_name_template = "{subject}/{tab_id}.eml".to_owned();
+        assert_eq!(
+            Path::new("./external_editor_revived_5.eml"),
+            get_temp_filename(&compose)
+        );
+
+        // nothing left of the subject
+        compose.compose_details.subject = "???".to_owned();
+        compose.configuration.temp_file_name_template = "{subject}.eml".to_owned();
+        assert_eq!(
+            Path::new("./external_editor_revived_5.eml"),
+            get_temp_filename(&compose)
+        );
+    }
+
+    #[test]
+    fn is_blank_test() {
+        assert!(is_blank(""));
+        assert!(is_blank(" \t\r\n"));
+        assert!(is_blank("\u{a0}\u{3000}"));
+        assert!(is_blank("\u{200b}\u{feff}"));
+        assert!(!is_blank(" a "));
+    }
+
+    #[test]
+    fn generate_request_id_test() {
+        let first = generate_request_id();
+        let second = generate_request_id();
+        assert!(!first.is_empty());
+        assert_ne!(first, second);
+    }
+
     #[test]
     fn extension_patch_version_diff_test() {
         let host_version = "1.0.0";
@@ -0,0 +1,50 @@
+use std::env;
+use std::sync::OnceLock;
+
+/// Paths separated like `PATH`, i.e. `:` on Unix and `;` on Windows
+pub const ALLOWED_SHELLS_ENV: &str = "EER_ALLOWED_SHELLS";
+
+static ALLOWED_SHELLS: OnceLock<Vec<String>> = OnceLock::new();
+
+/// Restrict the programs the host runs, which are the shell and the first argument of a direct
+/// editor command. Set by the host-side configuration only, never by requests.
+pub fn set(shells: Vec<String>) {
+    let _ = ALLOWED_SHELLS.set(shells);
+}
+
+pub fn get() -> Option<&'static [String]> {
+    ALLOWED_SHELLS.get().map(Vec::as_slice)
+}
+
+pub fn from_env() -> Option<Vec<String>> {
+    let value = env::var_os(ALLOWED_SHELLS_ENV)?;
+    Some(
+        env::split_paths(&value)
+            .map(|path| path.to_string_lossy().into_owned())
+            .filter(|path| !path.is_empty())
+            .collect(),
+    )
+}
+
+/// Any program is allowed without an allow-list. Otherwise it must be listed exactly as it is
+/// configured, e.g. `/bin/sh` does not allow `sh`.
+pub fn is_allowed(allowed_shells: Option<&[String]>, program: &str) -> bool {
+    allowed_shells.is_none_or(|allowed_shells| allowed_shells.iter().any(|shell| shell == program))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_allowed_test() {
+        assert!(is_allowed(None, "sh"));
+
+        let allowed_shells = vec!["/bin/sh".to_owned(), "bash".to_owned()];
+        assert!(is_allowed(Some(&allowed_shells), "/bin/sh"));
+        assert!(is_allowed(Some(&allowed_shells), "bash"));
+        assert!(!is_allowed(Some(&allowed_shells), "sh"));
+        assert!(!is_allowed(Some(&allowed_shells), "/tmp/bash"));
+        assert!(!is_allowed(Some(&[]), "/bin/sh"));
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::{log_error, log_warn};
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry::new());
+
+/// Temporary files and editor processes belonging to in-flight compose requests, so that they
+/// can be cleaned up if the host gets terminated.
+#[derive(Debug)]
+pub struct Registry {
+    temp_files: Option<HashSet<PathBuf>>,
+    editors: Option<HashMap<PathBuf, u32>>,
+}
+
+impl Registry {
+    const fn new() -> Self {
+        Self {
+            temp_files: None,
+            editors: None,
+        }
+    }
+
+    pub fn add_temp_file(&mut self, path: &Path) {
+        self.temp_files
+            .get_or_insert_with(HashSet::new)
+            .insert(path.to_path_buf());
+    }
+
+    pub fn add_editor(&mut self, path: &Path, pid: u32) {
+        self.editors
+            .get_or_insert_with(HashMap::new)
+            .insert(path.to_path_buf(), pid);
+    }
+
+    pub fn remove_editor(&mut self, path: &Path) {
+        if let Some(editors) = self.editors.as_mut() {
+            editors.remove(path);
+        }
+    }
+
+    /// Forget about a temporary file (and its editor) once its request has completed.
+    pub fn remove(&mut self, path: &Path) {
+        self.remove_editor(path);
+        if let Some(temp_files) = self.temp_files.as_mut() {
+            temp_files.remove(path);
+        }
+    }
+
+    /// Take all registered temporary files and editor process IDs, leaving the registry empty.
+    pub fn drain(&mut self) -> (Vec<PathBuf>, Vec<u32>) {
+        let temp_files = self
+            .temp_files
+            .take()
+            .map(|temp_files| temp_files.into_iter().collect())
+            .unwrap_or_default();
+        let editors = self
+            .editors
+            .take()
+            .map(|editors| editors.into_values().collect())
+            .unwrap_or_default();
+        (temp_files, editors)
+    }
+}
+
+pub fn registry() -> MutexGuard<'static, Registry> {
+    match REGISTRY.lock() {
+        Ok(registry) => registry,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+/// Install a SIGINT/SIGTERM/SIGHUP (Ctrl-C on Windows) handler that kills spawned editors and
+/// removes in-flight temporary files before exiting.
+pub fn install_signal_handler() {
+    let result = ctrlc::set_handler(|| {
+        clean_up();
+        process::exit(1);
+    });
+    if let Err(e) = result {
+        log_warn!("ExtEditorR failed to install signal handler: {e}");
+    }
+}
+
+fn clean_up() {
+    let (temp_files, editors) = registry().drain();
+    for pid in editors {
+        kill(pid);
+    }
+    for temp_file in temp_files {
+        if let Err(e) = fs::remove_file(&temp_file) {
+            log_error!(
+                "ExtEditorR failed to remove temporary file {}: {}",
+                temp_file.to_string_lossy(),
+                e
+            );
+        }
+    }
+}
+
+fn kill(pid: u32) {
+    let result = if cfg!(target_os = "windows") {
+        process::Command::new("taskkill")
+            .args(["/F", "/T", "/PID", &pid.to_string()])
+            .output()
+    } else {
+        process::Command::new("kill").arg(pid.to_string()).output()
+    };
+    if let Err(e) = result {
+        log_error!("ExtEditorR failed to kill editor process {pid}: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registry_add_remove_test() {
+        let mut registry = Registry::new();
+        let foo = PathBuf::from("foo.eml");
+        let bar = PathBuf::from("bar.eml");
+        registry.add_temp_file(&foo);
+        registry.add_temp_file(&bar);
+        registry.add_editor(&foo, 42);
+        registry.add_editor(&bar, 43);
+
+        registry.remove_editor(&bar);
+        registry.remove(&foo);
+        let (temp_files, editors) = registry.drain();
+        assert_eq!(vec![bar], temp_files);
+        assert!(editors.is_empty());
+    }
+
+    #[test]
+    fn registry_drain_test() {
+        let mut registry = Registry::new();
+        let foo = PathBuf::from("foo.eml");
+        registry.add_temp_file(&foo);
+        registry.add_editor(&foo, 42);
+
+        let (temp_files, editors) = registry.drain();
+        assert_eq!(vec![foo], temp_files);
+        assert_eq!(vec![42], editors);
+        let (temp_files, editors) = registry.drain();
+        assert!(temp_files.is_empty());
+        assert!(editors.is_empty());
+    }
+}
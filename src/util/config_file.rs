@@ -0,0 +1,109 @@
+use anyhow::{anyhow, Result};
+use serde_json::{Map, Value};
+use std::fs;
+use std::path::Path;
+
+pub const CONFIG_FILE_ENV: &str = "EER_CONFIG";
+/// Host-only key, which is not applied to requests
+const ALLOWED_SHELLS_KEY: &str = "allowedShells";
+
+/// Read host-side configuration defaults, i.e. a JSON object with the same (camelCase) keys as
+/// [`crate::model::messaging::Configuration`]
+pub fn load(path: &Path) -> Result<Map<String, Value>> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| anyhow!("Failed to read {}: {e}", path.to_string_lossy()))?;
+    match serde_json::from_str(&content)? {
+        Value::Object(defaults) => Ok(defaults),
+        _ => Err(anyhow!(
+            "{} does not contain a JSON object",
+            path.to_string_lossy()
+        )),
+    }
+}
+
+/// Remove the host-only allow-list of shells from the defaults, see
+/// [`crate::util::allowed_shells`]
+pub fn take_allowed_shells(defaults: &mut Map<String, Value>) -> Result<Option<Vec<String>>> {
+    match defaults.remove(ALLOWED_SHELLS_KEY) {
+        Some(value) => serde_json::from_value(value)
+            .map(Some)
+            .map_err(|e| anyhow!("Invalid {ALLOWED_SHELLS_KEY}: {e}")),
+        None => Ok(None),
+    }
+}
+
+/// Fill in configuration values missing from a compose request with the defaults. Values from
+/// the request always win, unless they are null. Other requests are left untouched.
+pub fn apply_defaults(defaults: &Map<String, Value>, request: &mut Value) {
+    let Some(Value::Object(configuration)) = request.get_mut("configuration") else {
+        return;
+    };
+    for (key, value) in defaults {
+        match configuration.get(key) {
+            Some(existing) if !existing.is_null() => {}
+            _ => {
+                configuration.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::messaging::tests::get_blank_compose;
+    use crate::model::messaging::{Compose, Exchange};
+    use serde_json::json;
+
+    #[test]
+    fn apply_defaults_test() {
+        let defaults = json!({
+            "shell": "zsh",
+            "template": "vim \"/path/to/temp.eml\"",
+            "temporaryDirectory": "/tmp/eer",
+            "sortRecipients": true,
+        });
+        let defaults = defaults.as_object().unwrap();
+
+        let mut request = serde_json::to_value(get_blank_compose()).unwrap();
+        // shell and template are not serialised
+        let configuration = request["configuration"].as_object_mut().unwrap();
+        configuration.insert("shell".to_owned(), json!("bash"));
+        configuration.insert("temporaryDirectory".to_owned(), Value::Null);
+        apply_defaults(defaults, &mut request);
+
+        let compose: Compose = serde_json::from_value(request).unwrap();
+        // from the request
+        assert_eq!("bash", compose.configuration.shell);
+        assert!(!compose.configuration.sort_recipients);
+        // from the defaults
+        assert_eq!("vim \"/path/to/temp.eml\"", compose.configuration.template);
+        assert_eq!("/tmp/eer", compose.configuration.temporary_directory);
+    }
+
+    #[test]
+    fn take_allowed_shells_test() {
+        let defaults = json!({"shell": "zsh", "allowedShells": ["/bin/zsh"]});
+        let mut defaults = defaults.as_object().unwrap().clone();
+        assert_eq!(
+            Some(vec!["/bin/zsh".to_owned()]),
+            take_allowed_shells(&mut defaults).unwrap()
+        );
+        assert!(!defaults.contains_key("allowedShells"));
+        assert_eq!(None, take_allowed_shells(&mut defaults).unwrap());
+
+        defaults.insert("allowedShells".to_owned(), json!("/bin/zsh"));
+        assert!(take_allowed_shells(&mut defaults).is_err());
+    }
+
+    #[test]
+    fn apply_defaults_to_ping_test() {
+        let defaults = json!({"shell": "zsh"});
+        let mut request = json!({"ping": 123456});
+        apply_defaults(defaults.as_object().unwrap(), &mut request);
+        assert!(matches!(
+            serde_json::from_value(request).unwrap(),
+            Exchange::Ping(_)
+        ));
+    }
+}
@@ -0,0 +1,55 @@
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ECHO: AtomicBool = AtomicBool::new(false);
+
+/// Print each generated temporary file to stderr, e.g. to paste it into a bug report
+pub fn enable() {
+    ECHO.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ECHO.load(Ordering::Relaxed)
+}
+
+/// Print the content of the temporary file if enabled. Never stdout, which is reserved for
+/// native messaging.
+pub fn print_eml(temp_filename: &Path, eml: &[u8]) {
+    if enabled() {
+        let _ = write_eml(&mut io::stderr().lock(), temp_filename, eml);
+    }
+}
+
+fn write_eml<W>(w: &mut W, temp_filename: &Path, eml: &[u8]) -> io::Result<()>
+where
+    W: Write,
+{
+    writeln!(w, "----- BEGIN {} -----", temp_filename.to_string_lossy())?;
+    w.write_all(eml)?;
+    if !eml.ends_with(b"\n") {
+        writeln!(w)?;
+    }
+    writeln!(w, "----- END {} -----", temp_filename.to_string_lossy())?;
+    w.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_eml_test() {
+        let mut buf = Vec::new();
+        write_eml(
+            &mut buf,
+            Path::new("/tmp/foo.eml"),
+            b"Subject: Hello\r\n\r\nHello, world!",
+        )
+        .unwrap();
+        assert_eq!(
+            "----- BEGIN /tmp/foo.eml -----\nSubject: Hello\r\n\r\nHello, world!\n----- END /tmp/foo.eml -----\n",
+            String::from_utf8(buf).unwrap()
+        );
+    }
+}
@@ -0,0 +1,98 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::log_error;
+
+pub const LOG_FILE_ENV: &str = "EER_LOG";
+
+static EXCHANGE_LOG: OnceLock<Mutex<fs::File>> = OnceLock::new();
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Received,
+    Sent,
+}
+
+#[derive(Serialize)]
+struct Record<'a, S> {
+    timestamp: u128,
+    direction: Direction,
+    message: &'a S,
+}
+
+/// Append all subsequent exchanges to the file at `path`. Must NOT be stdout, which is reserved
+/// for native messaging.
+pub fn open(path: &Path) -> io::Result<()> {
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    let _ = EXCHANGE_LOG.set(Mutex::new(file));
+    Ok(())
+}
+
+pub fn record<S>(direction: Direction, message: &S)
+where
+    S: Serialize,
+{
+    if let Some(log) = EXCHANGE_LOG.get() {
+        let mut file = match log.lock() {
+            Ok(file) => file,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if let Err(e) = write_record(&mut *file, direction, message) {
+            log_error!("ExtEditorR failed to write to log file: {e}");
+        }
+    }
+}
+
+fn write_record<W, S>(w: &mut W, direction: Direction, message: &S) -> Result<()>
+where
+    W: io::Write,
+    S: Serialize,
+{
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default();
+    let record = Record {
+        timestamp,
+        direction,
+        message,
+    };
+    serde_json::to_writer_pretty(&mut *w, &record)?;
+    writeln!(w)?;
+    w.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::messaging::Ping;
+
+    #[test]
+    fn ping_record_test() {
+        let ping: Ping = serde_json::from_str(r#"{"ping": 123456, "version": "1.0.0"}"#).unwrap();
+        let mut buf = Vec::new();
+        write_record(&mut buf, Direction::Received, &ping).unwrap();
+        write_record(&mut buf, Direction::Sent, &ping).unwrap();
+
+        let records: Vec<serde_json::Value> = serde_json::Deserializer::from_slice(&buf)
+            .into_iter()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(2, records.len());
+        assert_eq!("received", records[0]["direction"]);
+        assert_eq!("sent", records[1]["direction"]);
+        assert_eq!(123456, records[0]["message"]["ping"]);
+        assert_eq!("1.0.0", records[0]["message"]["version"]);
+        assert!(records[0]["timestamp"].as_u64().unwrap() > 0);
+    }
+}
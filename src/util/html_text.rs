@@ -0,0 +1,285 @@
+const BLOCK_TAGS: &[&str] = &[
+    "address",
+    "blockquote",
+    "div",
+    "dl",
+    "dt",
+    "dd",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "li",
+    "ol",
+    "p",
+    "pre",
+    "table",
+    "tr",
+    "ul",
+];
+const SKIPPED_TAGS: &[&str] = &["head", "script", "style", "title"];
+const INLINE_TAGS: &[&str] = &[
+    "a", "b", "body", "br", "code", "em", "html", "i", "img", "span", "strong", "u",
+];
+
+/// A rough HTML to plain text conversion, where links become `text <url>` and block elements
+/// become line breaks. Whitespace is collapsed except in `<pre>`.
+pub fn to_plain_text(html: &str) -> String {
+    let mut text = String::new();
+    let mut hrefs: Vec<Option<String>> = Vec::new();
+    let mut link_start = Vec::new();
+    let mut skipping: Option<String> = None;
+    let mut pre_depth = 0usize;
+    let mut rest = html;
+    while let Some(lt) = rest.find('<') {
+        if skipping.is_none() {
+            push_content(&mut text, &rest[..lt], pre_depth > 0);
+        }
+        let after_lt = &rest[lt + 1..];
+        let Some(gt) = after_lt.find('>') else {
+            rest = &rest[lt..];
+            break;
+        };
+        let tag = &after_lt[..gt];
+        rest = &after_lt[gt + 1..];
+
+        let closing = tag.starts_with('/');
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_ascii_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        if let Some(ref skipped) = skipping {
+            if closing && name == *skipped {
+                skipping = None;
+            }
+            continue;
+        }
+        match name.as_str() {
+            "br" => text.push('\n'),
+            "a" if !closing => {
+                hrefs.push(get_attribute(tag, "href"));
+                link_start.push(text.len());
+            }
+            "a" => {
+                let start = link_start.pop().unwrap_or(text.len());
+                if let Some(Some(href)) = hrefs.pop() {
+                    let link_text = text[start..].trim();
+                    let href_text = href.strip_prefix("mailto:").unwrap_or(&href);
+                    if link_text.is_empty() {
+                        text.push_str(href_text);
+                    } else if link_text != href_text {
+                        text.push_str(&format!(" <{href}>"));
+                    }
+                }
+            }
+            "pre" => {
+                pre_depth = if closing {
+                    pre_depth.saturating_sub(1)
+                } else {
+                    pre_depth + 1
+                };
+                if !text.is_empty() && !text.ends_with('\n') {
+                    text.push('\n');
+                }
+            }
+            name if !closing && SKIPPED_TAGS.contains(&name) => {
+                skipping = Some(name.to_owned());
+            }
+            name if BLOCK_TAGS.contains(&name) && !text.is_empty() && !text.ends_with('\n') => {
+                text.push('\n');
+            }
+            _ => {}
+        }
+    }
+    if skipping.is_none() {
+        push_content(&mut text, rest, pre_depth > 0);
+    }
+
+    let mut lines: Vec<_> = text.lines().map(str::trim_end).collect();
+    // at most one blank line in a row
+    lines.dedup_by(|line, previous| line.is_empty() && previous.is_empty());
+    lines.join("\n").trim_matches('\n').to_owned() + "\n"
+}
+
+/// Whether the text contains something like a known HTML tag, e.g. `<p>` or `</div>`, but not
+/// `<foo@example.com>`
+pub fn contains_markup(text: &str) -> bool {
+    text.match_indices('<').any(|(i, _)| {
+        let tag = text[i + 1..].trim_start_matches('/');
+        let name_len = tag
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(tag.len());
+        let name = tag[..name_len].to_ascii_lowercase();
+        let followed_by_end = tag[name_len..].starts_with(['>', '/', ' ', '\t', '\r', '\n']);
+        followed_by_end
+            && (BLOCK_TAGS.contains(&name.as_str())
+                || SKIPPED_TAGS.contains(&name.as_str())
+                || INLINE_TAGS.contains(&name.as_str()))
+    })
+}
+
+/// Append text content with entities decoded, as is if `preformatted`
+fn push_content(text: &mut String, content: &str, preformatted: bool) {
+    if !preformatted {
+        return push_text(text, content);
+    }
+    let content = content.replace("\r\n", "\n");
+    // a newline right after `<pre>` is not part of the content
+    let content = match content.strip_prefix('\n') {
+        Some(stripped) if text.ends_with('\n') => stripped,
+        _ => &content,
+    };
+    text.push_str(&decode_entities(content));
+}
+
+/// Append text content with HTML whitespace collapsing and entities decoded
+fn push_text(text: &mut String, content: &str) {
+    for (i, word) in content.split_ascii_whitespace().enumerate() {
+        let starts_with_space = i > 0 || content.starts_with(|c: char| c.is_ascii_whitespace());
+        if starts_with_space && !text.is_empty() && !text.ends_with([' ', '\n']) {
+            text.push(' ');
+        }
+        text.push_str(&decode_entities(word));
+    }
+    if content.ends_with(|c: char| c.is_ascii_whitespace())
+        && !content.trim().is_empty()
+        && !text.ends_with([' ', '\n'])
+    {
+        text.push(' ');
+    }
+}
+
+fn get_attribute(tag: &str, name: &str) -> Option<String> {
+    let lower = tag.to_ascii_lowercase();
+    let mut search_from = 0;
+    while let Some(found) = lower[search_from..].find(name) {
+        let start = search_from + found;
+        search_from = start + name.len();
+        let preceded_by_space = lower[..start].ends_with(|c: char| c.is_ascii_whitespace());
+        let value = tag[search_from..].trim_start();
+        if !preceded_by_space || !value.starts_with('=') {
+            continue;
+        }
+        let value = value[1..].trim_start();
+        let value = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => value[1..].split(quote).next().unwrap_or_default(),
+            _ => value
+                .split(|c: char| c.is_ascii_whitespace())
+                .next()
+                .unwrap_or_default(),
+        };
+        return Some(decode_entities(value));
+    }
+    None
+}
+
+fn decode_entities(s: &str) -> String {
+    let mut decoded = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(amp) = rest.find('&') {
+        decoded.push_str(&rest[..amp]);
+        let after_amp = &rest[amp + 1..];
+        let entity = after_amp
+            .find(';')
+            .map(|semicolon| &after_amp[..semicolon])
+            .and_then(|entity| Some((entity, decode_entity(entity)?)));
+        match entity {
+            Some((entity, c)) => {
+                decoded.push(c);
+                rest = &after_amp[entity.len() + 1..];
+            }
+            None => {
+                decoded.push('&');
+                rest = after_amp;
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+fn decode_entity(entity: &str) -> Option<char> {
+    match entity {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some(' '),
+        _ => {
+            let code = entity.strip_prefix('#')?;
+            let code = match code.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => code.parse().ok()?,
+            };
+            char::from_u32(code)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_plain_text_test() {
+        let html = r#"<html><head><style>p { color: red; }</style></head><body>
+<p>Hello,   <b>world</b>!</p>
+<div>See <a href="https://example.com/?a=1&amp;b=2">the docs</a> or
+<a href="mailto:foo@example.com">foo@example.com</a>.<br>Thanks &amp; regards</div>
+<ul><li>One</li><li>Two</li></ul>
+</body></html>"#;
+        assert_eq!(
+            "Hello, world!\nSee the docs <https://example.com/?a=1&b=2> or foo@example.com.\nThanks & regards\nOne\nTwo\n",
+            to_plain_text(html)
+        );
+    }
+
+    #[test]
+    fn pre_test() {
+        let html = "<p>Run:</p><pre>\nfn main() {\n    let  x = 1;\n\n    println!(\"{x}\");\n}\n</pre><p>Then   check</p>";
+        assert_eq!(
+            "Run:\nfn main() {\n    let  x = 1;\n\n    println!(\"{x}\");\n}\nThen check\n",
+            to_plain_text(html)
+        );
+        assert_eq!(
+            "  a\n  &lt;b&gt;\nc d\n",
+            to_plain_text("<pre>  a\n  <b>&amp;lt;b&amp;gt;</b></pre>c   d")
+        );
+    }
+
+    #[test]
+    fn empty_link_text_test() {
+        assert_eq!(
+            "Mail foo@example.com or https://example.com/\n",
+            to_plain_text(
+                "Mail <a href=\"mailto:foo@example.com\"></a> or <a href=\"https://example.com/\"> </a>"
+            )
+        );
+    }
+
+    #[test]
+    fn contains_markup_test() {
+        assert!(contains_markup("Hello,<br>world!"));
+        assert!(contains_markup("<P class=\"foo\">Hello</P>"));
+        assert!(contains_markup("Hello, <b>world</b>!"));
+        assert!(!contains_markup("Foo <foo@example.com> wrote:"));
+        assert!(!contains_markup("See <https://example.com/>"));
+        assert!(!contains_markup("1 < 2 and <3"));
+        assert!(!contains_markup("<blink>"));
+    }
+
+    #[test]
+    fn decode_entities_test() {
+        assert_eq!(
+            "<a> & \"b\" 'c'",
+            decode_entities("&lt;a&gt; &amp; &quot;b&quot; &#39;c&#x27;")
+        );
+        assert_eq!("AT&T &unknown;", decode_entities("AT&T &unknown;"));
+    }
+}
@@ -0,0 +1,95 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+use strum::{Display, EnumString};
+
+pub const LOG_LEVEL_ENV: &str = "EER_LOG_LEVEL";
+
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Warn as u8);
+
+#[derive(Clone, Copy, Display, Debug, PartialEq, Eq, PartialOrd, Ord, EnumString)]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+#[repr(u8)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+}
+
+pub fn set_max_level(level: Level) {
+    MAX_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    is_enabled(level, MAX_LEVEL.load(Ordering::Relaxed))
+}
+
+#[inline]
+fn is_enabled(level: Level, max_level: u8) -> bool {
+    level as u8 <= max_level
+}
+
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)+) => {
+        if $crate::util::logger::enabled($level) {
+            eprintln!($($arg)+);
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! log_error {
+    ($($arg:tt)+) => {
+        $crate::log!($crate::util::logger::Level::Error, $($arg)+)
+    };
+}
+
+#[macro_export]
+macro_rules! log_warn {
+    ($($arg:tt)+) => {
+        $crate::log!($crate::util::logger::Level::Warn, $($arg)+)
+    };
+}
+
+#[macro_export]
+macro_rules! log_info {
+    ($($arg:tt)+) => {
+        $crate::log!($crate::util::logger::Level::Info, $($arg)+)
+    };
+}
+
+#[macro_export]
+macro_rules! log_debug {
+    ($($arg:tt)+) => {
+        $crate::log!($crate::util::logger::Level::Debug, $($arg)+)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn level_filtering_test() {
+        let max_level = Level::Warn as u8;
+        assert!(is_enabled(Level::Error, max_level));
+        assert!(is_enabled(Level::Warn, max_level));
+        assert!(!is_enabled(Level::Info, max_level));
+        assert!(!is_enabled(Level::Debug, max_level));
+
+        let max_level = Level::Debug as u8;
+        assert!(is_enabled(Level::Info, max_level));
+        assert!(is_enabled(Level::Debug, max_level));
+
+        let max_level = Level::Error as u8;
+        assert!(!is_enabled(Level::Warn, max_level));
+    }
+
+    #[test]
+    fn level_parsing_test() {
+        assert_eq!(Level::Debug, Level::from_str("debug").unwrap());
+        assert_eq!(Level::Warn, Level::from_str("WARN").unwrap());
+        assert!(Level::from_str("verbose").is_err());
+    }
+}
@@ -0,0 +1,71 @@
+use std::fs;
+use std::process::{Command, Output};
+
+fn check(name: &str, eml: &str) -> Output {
+    check_with(name, eml, &[])
+}
+
+fn check_with(name: &str, eml: &str, extra_args: &[&str]) -> Output {
+    let eml_filename = std::env::temp_dir().join(name);
+    fs::write(&eml_filename, eml).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_external-editor-revived"))
+        .arg("--check")
+        .args(extra_args)
+        .arg(&eml_filename)
+        .output()
+        .unwrap();
+    fs::remove_file(eml_filename).unwrap();
+    output
+}
+
+#[test]
+fn check_clean_eml_test() {
+    let output = check(
+        "external_editor_revived_check_clean_test.eml",
+        "From: foo@example.com\r\nTo: bar@example.com\r\nSubject: Hello\r\n\r\nHello, world!\r\n",
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(stdout.contains("  To: bar@example.com\n"));
+    assert!(stdout.contains("  Subject: Hello\n"));
+    assert!(stdout.contains("Body: 15 byte(s) in 1 chunk(s)\n"));
+    assert!(stdout.contains("Warnings: none\n"));
+}
+
+#[test]
+fn check_eml_with_warnings_test() {
+    let output = check(
+        "external_editor_revived_check_warnings_test.eml",
+        "From: foo@example.com\r\nFoo: bar\r\nSubject: Hello\r\n\r\nHello, world!\r\n",
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(Some(1), output.status.code(), "{stdout}");
+    assert!(stdout.contains("  Unknown header(s) found: "));
+}
+
+#[test]
+fn check_eml_json_test() {
+    let output = check_with(
+        "external_editor_revived_check_json_test.eml",
+        "From: foo@example.com\r\nTo: bar@example.com\r\nTo: baz@example.com\r\nFoo: bar\r\nSubject: Hello\r\nX-ExtEditorR-Send-On-Exit: true\r\n\r\nHello, world!\r\n",
+        &["--json"],
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(Some(1), output.status.code(), "{stdout}");
+    let report: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!("foo@example.com", report["composeDetails"]["from"]);
+    assert_eq!(
+        serde_json::json!(["bar@example.com", "baz@example.com"]),
+        report["composeDetails"]["to"]
+    );
+    assert_eq!("Hello", report["composeDetails"]["subject"]);
+    // disabled by the warning
+    assert_eq!(false, report["configuration"]["sendOnExit"]);
+    assert_eq!(1, report["warnings"].as_array().unwrap().len());
+    assert!(report["warnings"][0]["message"]
+        .as_str()
+        .unwrap()
+        .contains("Foo"));
+    assert_eq!(15, report["bodyLength"]);
+    assert_eq!(1, report["chunks"]);
+}
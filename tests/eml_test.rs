@@ -0,0 +1,63 @@
+use external_editor_revived::model::messaging::Compose;
+use external_editor_revived::model::thunderbird::{ComposeRecipient, ComposeRecipientList};
+
+const COMPOSE_JSON: &str = r#"{
+    "configuration": {
+        "version": "0.0.0",
+        "shell": "sh",
+        "template": "vim \"/path/to/temp.eml\""
+    },
+    "tab": {
+        "id": 1,
+        "index": 0,
+        "windowId": 1,
+        "status": "complete",
+        "width": 800,
+        "height": 600,
+        "type": "messageCompose",
+        "mailTab": false
+    },
+    "composeDetails": {
+        "from": "foo@example.com",
+        "to": ["bar@example.com", "Baz <baz@example.com>"],
+        "cc": [],
+        "bcc": [],
+        "type": "new",
+        "replyTo": [],
+        "followupTo": [],
+        "newsgroups": [],
+        "subject": "Hello",
+        "isPlainText": true,
+        "body": "",
+        "plainTextBody": "Hello, world!\n",
+        "attachments": [],
+        "deliveryStatusNotification": null,
+        "returnReceipt": null
+    }
+}"#;
+
+#[test]
+fn eml_round_trip_test() {
+    let mut compose: Compose = serde_json::from_str(COMPOSE_JSON).unwrap();
+    let mut eml = Vec::new();
+    compose.to_eml(&mut eml).unwrap();
+    let eml = String::from_utf8(eml).unwrap();
+    assert!(eml.starts_with("From: foo@example.com\r\nTo: bar@example.com\r\n"));
+    assert!(eml.ends_with("\r\n\r\nHello, world!\r\n"));
+
+    let responses = compose.merge_from_eml(&mut eml.as_bytes(), 512).unwrap();
+    assert_eq!(1, responses.len());
+    assert!(responses[0].warnings.is_empty());
+    assert_eq!(
+        ComposeRecipientList::Multiple(vec![
+            ComposeRecipient::Email("bar@example.com".to_owned()),
+            ComposeRecipient::Email("Baz <baz@example.com>".to_owned()),
+        ]),
+        responses[0].compose_details.to
+    );
+    assert_eq!("Hello", responses[0].compose_details.subject);
+    assert_eq!(
+        "Hello, world!\r\n",
+        responses[0].compose_details.plain_text_body
+    );
+}
@@ -0,0 +1,176 @@
+use external_editor_revived::model::messaging::{Ping, Warning};
+use external_editor_revived::transport::{read_framed, write_framed};
+use std::io::Write;
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+fn spawn_host() -> Child {
+    spawn_host_with(&[], Stdio::null())
+}
+
+fn spawn_host_with(extra_args: &[&str], stderr: Stdio) -> Child {
+    // arguments passed by Thunderbird
+    Command::new(env!("CARGO_BIN_EXE_external-editor-revived"))
+        .args([
+            "/path/to/native-messaging-hosts/external_editor_revived.json",
+            "external-editor-revived@tsundere.moe",
+        ])
+        .args(extra_args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(stderr)
+        .spawn()
+        .unwrap()
+}
+
+fn finish(mut host: Child, mut stdout: ChildStdout) {
+    drop(host.stdin.take());
+    // nothing more to read once stdin is closed
+    let mut rest = Vec::new();
+    std::io::Read::read_to_end(&mut stdout, &mut rest).unwrap();
+    assert!(rest.is_empty());
+    host.wait().unwrap();
+}
+
+fn get_compose(tab_id: i32) -> serde_json::Value {
+    serde_json::json!({
+        "configuration": {
+            "version": env!("CARGO_PKG_VERSION"),
+            "shell": "sh",
+            "template": "cat \"/path/to/temp.eml\"",
+            "temporaryDirectory": env!("CARGO_TARGET_TMPDIR"),
+        },
+        "tab": {
+            "id": tab_id,
+            "index": 0,
+            "windowId": 1,
+            "status": "complete",
+            "width": 800,
+            "height": 600,
+            "type": "messageCompose",
+            "mailTab": false,
+        },
+        "composeDetails": {
+            "from": "foo@example.com",
+            "to": ["bar@example.com"],
+            "cc": [],
+            "bcc": [],
+            "type": "new",
+            "replyTo": [],
+            "followupTo": [],
+            "newsgroups": [],
+            "subject": "Hello",
+            "isPlainText": true,
+            "body": "",
+            "plainTextBody": "Hello, world!\r\n",
+            "attachments": [],
+            "deliveryStatusNotification": null,
+            "returnReceipt": null,
+        },
+    })
+}
+
+#[test]
+fn ping_test() {
+    let mut host = spawn_host();
+    let mut stdin = host.stdin.take().unwrap();
+    let mut stdout = host.stdout.take().unwrap();
+
+    let ping = serde_json::json!({
+        "ping": 123456,
+        "version": env!("CARGO_PKG_VERSION"),
+    });
+    write_framed(&mut stdin, &ping).unwrap();
+    let pong: Ping = read_framed(&mut stdout).unwrap();
+    assert_eq!(123456, pong.pong);
+    assert_eq!(env!("CARGO_PKG_VERSION"), pong.host_version);
+    assert!(pong.compatible);
+
+    host.stdin = Some(stdin);
+    finish(host, stdout);
+}
+
+#[test]
+fn malformed_json_test() {
+    let mut host = spawn_host();
+    let mut stdin = host.stdin.take().unwrap();
+    let mut stdout = host.stdout.take().unwrap();
+
+    let buf = b"{\"ping\": ";
+    stdin.write_all(&(buf.len() as u32).to_ne_bytes()).unwrap();
+    stdin.write_all(buf).unwrap();
+    stdin.flush().unwrap();
+    let warning: Warning = read_framed(&mut stdout).unwrap();
+    assert_eq!("ExtEditorR failed to read request", warning.title);
+
+    host.stdin = Some(stdin);
+    finish(host, stdout);
+}
+
+#[test]
+fn truncated_frame_test() {
+    let mut host = spawn_host();
+    let mut stdin = host.stdin.take().unwrap();
+    let mut stdout = host.stdout.take().unwrap();
+
+    stdin.write_all(&100u32.to_ne_bytes()).unwrap();
+    stdin.write_all(b"{}").unwrap();
+    drop(stdin);
+    let warning: Warning = read_framed(&mut stdout).unwrap();
+    assert_eq!("ExtEditorR failed to read request", warning.title);
+    assert!(!host.wait().unwrap().success());
+}
+
+#[test]
+fn clean_eof_test() {
+    let mut host = spawn_host();
+    drop(host.stdin.take());
+    let mut rest = Vec::new();
+    std::io::Read::read_to_end(&mut host.stdout.take().unwrap(), &mut rest).unwrap();
+    assert!(rest.is_empty());
+    assert!(host.wait().unwrap().success());
+}
+
+#[test]
+fn echo_compose_test() {
+    let mut host = spawn_host();
+    let mut stdin = host.stdin.take().unwrap();
+    let mut stdout = host.stdout.take().unwrap();
+
+    let compose = get_compose(1);
+    write_framed(&mut stdin, &compose).unwrap();
+    // host-only configuration e.g. shell is not sent back, so this can't be a Compose
+    let response: serde_json::Value = read_framed(&mut stdout).unwrap();
+    assert!(response["warnings"].as_array().unwrap().is_empty());
+    assert_eq!(0, response["configuration"]["sequence"]);
+    assert_eq!(1, response["configuration"]["total"]);
+    assert_eq!("Hello", response["composeDetails"]["subject"]);
+    assert_eq!(
+        "Hello, world!\r\n",
+        response["composeDetails"]["plainTextBody"]
+    );
+
+    host.stdin = Some(stdin);
+    finish(host, stdout);
+}
+
+#[test]
+fn echo_eml_test() {
+    let mut host = spawn_host_with(&["--echo"], Stdio::piped());
+    let mut stdin = host.stdin.take().unwrap();
+    let mut stdout = host.stdout.take().unwrap();
+    let mut stderr = host.stderr.take().unwrap();
+
+    write_framed(&mut stdin, &get_compose(2)).unwrap();
+    // stdout is still only native messaging
+    let response: serde_json::Value = read_framed(&mut stdout).unwrap();
+    assert_eq!("Hello", response["composeDetails"]["subject"]);
+
+    host.stdin = Some(stdin);
+    finish(host, stdout);
+    let mut echoed = String::new();
+    std::io::Read::read_to_string(&mut stderr, &mut echoed).unwrap();
+    assert!(echoed.contains("----- BEGIN "));
+    assert!(echoed.contains("To: bar@example.com\r\n"));
+    assert!(echoed.contains("Subject: Hello\r\n"));
+    assert!(echoed.contains("\r\n\r\nHello, world!\r\n----- END "));
+}
@@ -0,0 +1,63 @@
+use std::fs;
+use std::process::{Command, Output};
+
+fn self_test(name: &str, template: &str) -> Output {
+    self_test_with(name, serde_json::json!({ "template": template }))
+}
+
+fn self_test_with(name: &str, mut configuration: serde_json::Value) -> Output {
+    let temporary_directory = std::env::temp_dir().join(name);
+    fs::create_dir_all(&temporary_directory).unwrap();
+    let config_filename = temporary_directory.join("configuration.json");
+    configuration["shell"] = "sh".into();
+    configuration["temporaryDirectory"] = temporary_directory.to_string_lossy().into();
+    fs::write(&config_filename, configuration.to_string()).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_external-editor-revived"))
+        .arg("--selftest")
+        .arg(&config_filename)
+        .output()
+        .unwrap();
+    fs::remove_dir_all(temporary_directory).unwrap();
+    output
+}
+
+#[test]
+fn self_test_cat_test() {
+    let output = self_test(
+        "external_editor_revived_selftest_cat_test",
+        r#"cat "/path/to/temp.eml""#,
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(stdout.contains("Round trip: succeeded\n"));
+    assert!(stdout.contains("  From: ExtEditorR <self-test@example.com>\n"));
+    assert!(stdout.contains("  Subject: ExtEditorR self-test\n"));
+    assert!(stdout.contains("Warnings: none\n"));
+}
+
+#[test]
+fn self_test_failing_editor_test() {
+    let output = self_test(
+        "external_editor_revived_selftest_failing_test",
+        r#"echo "failed to open /path/to/temp.eml" >&2; exit 3"#,
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(Some(1), output.status.code(), "{stdout}");
+    assert!(stdout.contains("Round trip: failed\n"));
+    assert!(stdout.contains("  ExtEditorR encountered error from external editor: failed to open"));
+}
+
+#[test]
+fn self_test_allowed_shells_test() {
+    let output = self_test_with(
+        "external_editor_revived_selftest_allowed_shells_test",
+        serde_json::json!({
+            "template": r#"cat "/path/to/temp.eml""#,
+            "allowedShells": ["/bin/sh"],
+        }),
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(Some(1), output.status.code(), "{stdout}");
+    assert!(stdout.contains("Round trip: failed\n"));
+    assert!(stdout.contains("  ExtEditorR refused to run shell: sh is not allowed"));
+}